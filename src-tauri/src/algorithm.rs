@@ -59,7 +59,7 @@ pub mod lua_algorithm {
     }
 
     #[inline]
-    fn dxt5_decompress(compressed_data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    pub fn dxt5_decompress(compressed_data: &[u8], width: usize, height: usize) -> Vec<u8> {
         match bcndecode::decode(compressed_data, width, height,
             bcndecode::BcnEncoding::Bc3, // DXT5
             bcndecode::BcnDecoderFormat::RGBA) {
@@ -69,7 +69,7 @@ pub mod lua_algorithm {
     }
 
     #[inline]
-    fn dxt3_decompress(compressed_data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    pub fn dxt3_decompress(compressed_data: &[u8], width: usize, height: usize) -> Vec<u8> {
         match bcndecode::decode(compressed_data, width, height,
             bcndecode::BcnEncoding::Bc2, // DXT3
             bcndecode::BcnDecoderFormat::RGBA) {
@@ -79,7 +79,7 @@ pub mod lua_algorithm {
     }
 
     #[inline]
-    fn dxt1_decompress(compressed_data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    pub fn dxt1_decompress(compressed_data: &[u8], width: usize, height: usize) -> Vec<u8> {
         match bcndecode::decode(compressed_data, width, height,
             bcndecode::BcnEncoding::Bc1, // DXT1
             bcndecode::BcnDecoderFormat::RGBA) {
@@ -88,6 +88,160 @@ pub mod lua_algorithm {
         }
     }
 
+    /// fetch a 4x4 rgba block, pixels outside the image repeat the edge
+    #[inline]
+    fn fetch_block(bytes: &[u8], width: usize, height: usize, bx: usize, by: usize) -> [[u8; 4]; 16] {
+        let mut block = [[0; 4]; 16];
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let x = (bx + i % 4).min(width - 1);
+            let y = (by + i / 4).min(height - 1);
+            let start = (y* width + x)* 4;
+            pixel.copy_from_slice(&bytes[start..start+4]);
+        }
+        block
+    }
+
+    #[inline]
+    fn to_rgb565(c: [u8; 3]) -> u16 {
+        ((c[0] as u16 >> 3) << 11) | ((c[1] as u16 >> 2) << 5) | (c[2] as u16 >> 3)
+    }
+
+    #[inline]
+    fn from_rgb565(v: u16) -> [u8; 3] {
+        let (r, g, b) = ((v >> 11) as u8 & 31, (v >> 5) as u8 & 63, v as u8 & 31);
+        [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+    }
+
+    #[inline]
+    fn color_distance(c1: &[u8], c2: &[u8]) -> i32 {
+        let d = |i: usize| c1[i] as i32 - c2[i] as i32;
+        d(0)*d(0) + d(1)*d(1) + d(2)*d(2)
+    }
+
+    /// encode color endpoints and indices of a bc1 block (8 bytes)
+    /// if `punch_through` is set, pixels with alpha < 128 use the transparent index (3-color mode)
+    fn bc1_encode_color(block: &[[u8; 4]; 16], punch_through: bool) -> [u8; 8] {
+        let is_transparent = |p: &[u8; 4]| punch_through && p[3] < 128;
+        let mut min = [255_u8; 3];
+        let mut max = [0_u8; 3];
+        let mut has_transparent = false;
+        for p in block.iter() {
+            if is_transparent(p) {
+                has_transparent = true;
+                continue;
+            }
+            else if p[3] == 0 {
+                // color of invisible pixel is not important
+                continue;
+            }
+            min = [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])];
+            max = [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])];
+        }
+        if min[0] > max[0] {
+            // all pixels are transparent
+            return [0, 0, 0, 0, 255, 255, 255, 255];
+        }
+        // inset bounding box by 1/16 of its size to reduce rounding error
+        let inset = |i: usize| (max[i] - min[i]) >> 4;
+        let (mut c0, mut c1) = (
+            to_rgb565([max[0] - inset(0), max[1] - inset(1), max[2] - inset(2)]),
+            to_rgb565([min[0] + inset(0), min[1] + inset(1), min[2] + inset(2)]),
+        );
+        // c0 > c1 selects 4-color mode, c0 <= c1 selects 3-color + transparent mode
+        if (c0 < c1) != has_transparent {
+            std::mem::swap(&mut c0, &mut c1);
+        }
+        let (p0, p1) = (from_rgb565(c0), from_rgb565(c1));
+        let lerp = |w0: u16, w1: u16| {
+            let f = |i: usize| ((p0[i] as u16 * w0 + p1[i] as u16 * w1) / (w0 + w1)) as u8;
+            [f(0), f(1), f(2)]
+        };
+        let palette = if c0 > c1 {
+            vec![p0, p1, lerp(2, 1), lerp(1, 2)]
+        }
+        else {
+            vec![p0, p1, lerp(1, 1)]
+        };
+        let mut indices = 0_u32;
+        for (i, p) in block.iter().enumerate() {
+            let index = if is_transparent(p) {
+                3
+            }
+            else {
+                palette.iter()
+                    .enumerate()
+                    .min_by_key(|(_, c)|color_distance(&c[..], &p[..3]))
+                    .map(|(index, _)|index as u32)
+                    .unwrap_or(0)
+            };
+            indices |= index << (i* 2);
+        }
+        let mut result = [0; 8];
+        result[0..2].copy_from_slice(&c0.to_le_bytes());
+        result[2..4].copy_from_slice(&c1.to_le_bytes());
+        result[4..8].copy_from_slice(&indices.to_le_bytes());
+        result
+    }
+
+    /// encode alpha endpoints and indices of a bc3 block (8 bytes)
+    fn bc3_encode_alpha(block: &[[u8; 4]; 16]) -> [u8; 8] {
+        let a0 = block.iter().map(|p|p[3]).max().unwrap_or(255);
+        let a1 = block.iter().map(|p|p[3]).min().unwrap_or(255);
+        let mut result = [0; 8];
+        result[0] = a0;
+        result[1] = a1;
+        if a0 == a1 {
+            return result;
+        }
+        // a0 > a1 selects 8-alpha mode
+        let palette = (0..8_u32).map(|i| match i {
+            0 => a0,
+            1 => a1,
+            i => ((a0 as u32 * (8 - i) + a1 as u32 * (i - 1)) / 7) as u8,
+        }).collect::<Vec<u8>>();
+        let mut indices = 0_u64;
+        for (i, p) in block.iter().enumerate() {
+            let index = palette.iter()
+                .enumerate()
+                .min_by_key(|(_, a)|(**a as i32 - p[3] as i32).abs())
+                .map(|(index, _)|index as u64)
+                .unwrap_or(0);
+            indices |= index << (i* 3);
+        }
+        result[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+        result
+    }
+
+    #[inline]
+    fn bc_compress(bytes: &[u8], width: usize, height: usize, block_size: usize,
+        encode: impl Fn(&[[u8; 4]; 16], &mut Vec<u8>)) -> Vec<u8> {
+        let mut result = Vec::with_capacity(width.div_ceil(4)* height.div_ceil(4)* block_size);
+        if bytes.len() < width* height* 4 {
+            return result;
+        }
+        for by in (0..height).step_by(4) {
+            for bx in (0..width).step_by(4) {
+                encode(&fetch_block(bytes, width, height, bx, by), &mut result);
+            }
+        }
+        result
+    }
+
+    /// compress rgba bytes to DXT1, transparent pixels are kept as 1-bit alpha
+    pub fn dxt1_compress(bytes: &[u8], width: usize, height: usize) -> Vec<u8> {
+        bc_compress(bytes, width, height, 8, |block, result|{
+            result.extend_from_slice(&bc1_encode_color(block, true));
+        })
+    }
+
+    /// compress rgba bytes to DXT5
+    pub fn dxt5_compress(bytes: &[u8], width: usize, height: usize) -> Vec<u8> {
+        bc_compress(bytes, width, height, 16, |block, result|{
+            result.extend_from_slice(&bc3_encode_alpha(block));
+            result.extend_from_slice(&bc1_encode_color(block, false));
+        })
+    }
+
 
     #[inline]
     fn flip_bytes(bytes: &[u8], linewidth: usize) -> Vec<u8> {
@@ -97,7 +251,7 @@ pub mod lua_algorithm {
     }
 
     #[inline]
-    fn flip_bytes_mut(bytes: &mut [u8], linewidth: usize) {
+    pub fn flip_bytes_mut(bytes: &mut [u8], linewidth: usize) {
         let mut i = 0;
         let mut j = bytes.len() - linewidth;
         while i < j {
@@ -109,7 +263,7 @@ pub mod lua_algorithm {
     }
     
     #[inline]
-    fn kleihash(bytes: &[u8]) -> u32 {
+    pub fn kleihash(bytes: &[u8]) -> u32 {
         bytes.iter().fold::<u64, _>(0, |hash, x|{
             let x = match *x as u64 {
                 n @ 65..=90 => n + 32,
@@ -155,7 +309,7 @@ pub mod lua_algorithm {
     }
 
     #[inline]
-    fn mult_alpha(bytes: &[u8]) -> Vec<u8> {
+    pub fn mult_alpha(bytes: &[u8]) -> Vec<u8> {
         bytes.chunks_exact(4)
             .map(|color|[
                 mult_alpha_impl(color[0], color[3]),
@@ -190,7 +344,7 @@ pub mod lua_algorithm {
     }
 
     #[inline]
-    fn div_alpha_mut(bytes: &mut [u8]) {
+    pub fn div_alpha_mut(bytes: &mut [u8]) {
         bytes.chunks_exact_mut(4)
             .for_each(|color|{
                 color[0] = div_alpha_and_clamp(color[0], color[3]);
@@ -238,6 +392,22 @@ pub mod lua_algorithm {
             (compressed_data, width, height): (LuaString, usize, usize)|{
             lua_ctx.create_string(&dxt1_decompress(compressed_data.as_bytes(), width, height))
         })?)?;
+        table.set("DXT1_Compress", lua_ctx.create_function(|lua_ctx: Context,
+            (raw_data, width, height): (LuaString, usize, usize)|{
+            if raw_data.as_bytes().len() != width* height* 4 {
+                return Err(LuaError::RuntimeError(format!("Invalid rgba bytes length: {}, expected {}x{}x4", 
+                    raw_data.as_bytes().len(), width, height)));
+            }
+            lua_ctx.create_string(&dxt1_compress(raw_data.as_bytes(), width, height))
+        })?)?;
+        table.set("DXT5_Compress", lua_ctx.create_function(|lua_ctx: Context,
+            (raw_data, width, height): (LuaString, usize, usize)|{
+            if raw_data.as_bytes().len() != width* height* 4 {
+                return Err(LuaError::RuntimeError(format!("Invalid rgba bytes length: {}, expected {}x{}x4", 
+                    raw_data.as_bytes().len(), width, height)));
+            }
+            lua_ctx.create_string(&dxt5_compress(raw_data.as_bytes(), width, height))
+        })?)?;
         table.set("Bc_Decompress", lua_ctx.create_function(|lua,
             (data, options): (LuaString, Table)|{
             let data = data.as_bytes();
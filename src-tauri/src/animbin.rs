@@ -0,0 +1,387 @@
+// full parser for anim.bin and build.bin, see AnimLoader / BuildLoader in assetloader.lua
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::error::Error;
+
+use crate::fastindex::{parse_hash_table, HashTable};
+
+pub const ANIM_VERSION: u32 = 4;
+pub const BUILD_VERSION: u32 = 6;
+
+struct BinReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> BinReader<R> {
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        let mut buf = [0; 1];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let mut buf = [0; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        let mut buf = [0; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(f32::from_le_bytes(buf))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = vec![0; len];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn sig(&mut self, sig: &[u8; 4]) -> Result<(), Box<dyn Error>> {
+        if self.bytes(4)?.as_slice() != sig {
+            return Err(format!("Invalid file sig, expect {}", String::from_utf8_lossy(sig)).into());
+        }
+        Ok(())
+    }
+}
+
+impl BinReader<Cursor<&[u8]>> {
+    /// read count of items which take at least `item_size` bytes each,
+    /// counts from untrusted file are checked against remaining bytes before allocating
+    fn count(&mut self, item_size: usize) -> Result<usize, Box<dyn Error>> {
+        let count = self.u32()? as usize;
+        let remaining = self.inner.get_ref().len().saturating_sub(self.inner.position() as usize);
+        if count.saturating_mul(item_size) > remaining {
+            return Err(format!("Invalid count: {}, only {} bytes left", count, remaining).into());
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.count(1)?;
+        Ok(String::from_utf8_lossy(&self.bytes(len)?).to_string())
+    }
+}
+
+#[derive(Default)]
+struct BinWriter {
+    inner: Vec<u8>,
+}
+
+impl BinWriter {
+    fn u8(&mut self, v: u8) {
+        self.inner.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.inner.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.inner.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &[u8]) {
+        self.u32(s.len() as u32);
+        self.inner.extend_from_slice(s);
+    }
+
+    /// hash table at the tail of file, sorted by hash
+    fn hash_table(&mut self, hash_table: &HashTable) {
+        let mut list = hash_table.iter().collect::<Vec<_>>();
+        list.sort_by_key(|v| v.0);
+        self.u32(list.len() as u32);
+        for (hash, name) in list {
+            self.u32(*hash);
+            self.string(name);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Element {
+    pub imghash: u32,
+    pub imgindex: u32,
+    pub layerhash: u32,
+    /// a, b, c, d, tx, ty
+    pub matrix: [f32; 6],
+    /// raw z value, smaller is at front
+    pub z: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    /// x, y, w, h
+    pub rect: [f32; 4],
+    pub events: Vec<u32>,
+    pub elements: Vec<Element>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Anim {
+    pub name: String,
+    pub facing: u8,
+    pub bankhash: u32,
+    pub framerate: f32,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnimBin {
+    pub anims: Vec<Anim>,
+    pub hash_table: HashTable,
+}
+
+impl AnimBin {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut f = BinReader { inner: Cursor::new(bytes) };
+        f.sig(b"ANIM")?;
+        f.bytes(16)?; // version, num elements, num frames, num events
+        // name, facing, bankhash, framerate, num frames
+        let num_anims = f.count(17)?;
+        let mut anims = Vec::with_capacity(num_anims);
+        for _ in 0..num_anims {
+            let name = f.string()?;
+            let facing = f.u8()?;
+            let bankhash = f.u32()?;
+            let framerate = f.f32()?;
+            // rect, num events, num elements
+            let num_frames = f.count(24)?;
+            let mut frames = Vec::with_capacity(num_frames);
+            for _ in 0..num_frames {
+                let rect = [f.f32()?, f.f32()?, f.f32()?, f.f32()?];
+                let num_events = f.count(4)?;
+                let events = (0..num_events).map(|_| f.u32()).collect::<Result<Vec<_>, _>>()?;
+                let num_elements = f.count(40)?;
+                let mut elements = Vec::with_capacity(num_elements);
+                for _ in 0..num_elements {
+                    elements.push(Element {
+                        imghash: f.u32()?,
+                        imgindex: f.u32()?,
+                        layerhash: f.u32()?,
+                        matrix: [f.f32()?, f.f32()?, f.f32()?, f.f32()?, f.f32()?, f.f32()?],
+                        z: f.f32()?,
+                    });
+                }
+                frames.push(Frame { rect, events, elements });
+            }
+            anims.push(Anim { name, facing, bankhash, framerate, frames });
+        }
+        let hash_table = parse_hash_table(f.inner);
+        Ok(AnimBin { anims, hash_table })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let frames = self.anims.iter().flat_map(|a| a.frames.iter());
+        let num_elements = frames.clone().map(|f| f.elements.len() as u32).sum();
+        let num_frames = frames.clone().count() as u32;
+        let num_events = frames.map(|f| f.events.len() as u32).sum();
+        let mut f = BinWriter::default();
+        f.inner.extend_from_slice(b"ANIM");
+        f.u32(ANIM_VERSION);
+        f.u32(num_elements);
+        f.u32(num_frames);
+        f.u32(num_events);
+        f.u32(self.anims.len() as u32);
+        for anim in self.anims.iter() {
+            f.string(anim.name.as_bytes());
+            f.u8(anim.facing);
+            f.u32(anim.bankhash);
+            f.f32(anim.framerate);
+            f.u32(anim.frames.len() as u32);
+            for frame in anim.frames.iter() {
+                frame.rect.iter().for_each(|v| f.f32(*v));
+                f.u32(frame.events.len() as u32);
+                frame.events.iter().for_each(|v| f.u32(*v));
+                f.u32(frame.elements.len() as u32);
+                for e in frame.elements.iter() {
+                    f.u32(e.imghash);
+                    f.u32(e.imgindex);
+                    f.u32(e.layerhash);
+                    e.matrix.iter().for_each(|v| f.f32(*v));
+                    f.f32(e.z);
+                }
+            }
+        }
+        f.hash_table(&self.hash_table);
+        f.inner
+    }
+}
+
+/// x, y, z, u, v, w (w is atlas sampler index)
+pub type Vertex = [f32; 6];
+
+#[derive(Debug, Clone)]
+pub struct BuildImage {
+    pub index: u32,
+    pub duration: u32,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    /// triangles, 6 vertices per quad
+    pub vertices: Vec<Vertex>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub imghash: u32,
+    /// sorted by index
+    pub images: Vec<BuildImage>,
+}
+
+impl Symbol {
+    /// find the image which is displayed at frame index
+    pub fn find_image(&self, imgindex: u32) -> Option<&BuildImage> {
+        let i = self.images.partition_point(|img| img.index <= imgindex);
+        if i == 0 {
+            return None;
+        }
+        let img = &self.images[i - 1];
+        if img.index + img.duration > imgindex { Some(img) } else { None }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BuildBin {
+    pub name: String,
+    pub atlases: Vec<String>,
+    pub symbols: Vec<Symbol>,
+    pub hash_table: HashTable,
+}
+
+impl BuildBin {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut f = BinReader { inner: Cursor::new(bytes) };
+        f.sig(b"BILD")?;
+        f.u32()?; // version
+        // imghash, num images
+        let num_symbols = f.count(8)?;
+        f.u32()?; // num frames
+        let name = f.string()?;
+        let num_atlases = f.count(4)?;
+        let atlases = (0..num_atlases).map(|_| f.string()).collect::<Result<Vec<_>, _>>()?;
+        // (symbol, image, vertex index, num vertices)
+        let mut symbols = Vec::with_capacity(num_symbols);
+        let mut vertex_ranges = vec![];
+        for i in 0..num_symbols {
+            let imghash = f.u32()?;
+            // index, duration, x, y, w, h, vertex index, num vertices
+            let num_images = f.count(32)?;
+            let mut images = Vec::with_capacity(num_images);
+            for j in 0..num_images {
+                images.push(BuildImage {
+                    index: f.u32()?,
+                    duration: f.u32()?,
+                    x: f.f32()?,
+                    y: f.f32()?,
+                    w: f.f32()?,
+                    h: f.f32()?,
+                    vertices: vec![],
+                });
+                vertex_ranges.push((i, j, f.u32()? as usize, f.u32()? as usize));
+            }
+            symbols.push(Symbol { imghash, images });
+        }
+        let num_vertices = f.count(24)?;
+        let mut vertices = Vec::with_capacity(num_vertices);
+        for _ in 0..num_vertices {
+            vertices.push([f.f32()?, f.f32()?, f.f32()?, f.f32()?, f.f32()?, f.f32()?]);
+        }
+        for (i, j, start, len) in vertex_ranges {
+            let v = vertices.get(start..start + len)
+                .ok_or_else(|| format!("Vertex index out of range: {}+{}", start, len))?;
+            symbols[i].images[j].vertices = v.to_vec();
+        }
+        for s in symbols.iter_mut() {
+            s.images.sort_by_key(|img| img.index);
+        }
+        let hash_table = parse_hash_table(f.inner);
+        Ok(BuildBin { name, atlases, symbols, hash_table })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut f = BinWriter::default();
+        f.inner.extend_from_slice(b"BILD");
+        f.u32(BUILD_VERSION);
+        f.u32(self.symbols.len() as u32);
+        f.u32(self.symbols.iter().map(|s| s.images.len() as u32).sum());
+        f.string(self.name.as_bytes());
+        f.u32(self.atlases.len() as u32);
+        self.atlases.iter().for_each(|s| f.string(s.as_bytes()));
+        let mut vertex_index = 0;
+        for symbol in self.symbols.iter() {
+            f.u32(symbol.imghash);
+            f.u32(symbol.images.len() as u32);
+            for img in symbol.images.iter() {
+                f.u32(img.index);
+                f.u32(img.duration);
+                [img.x, img.y, img.w, img.h].iter().for_each(|v| f.f32(*v));
+                f.u32(vertex_index);
+                f.u32(img.vertices.len() as u32);
+                vertex_index += img.vertices.len() as u32;
+            }
+        }
+        f.u32(vertex_index);
+        for v in self.symbols.iter().flat_map(|s| s.images.iter()).flat_map(|img| img.vertices.iter()) {
+            v.iter().for_each(|v| f.f32(*v));
+        }
+        f.hash_table(&self.hash_table);
+        f.inner
+    }
+}
+
+#[test]
+fn check_anim_bin() {
+    let element = Element { imghash: 1, imgindex: 2, layerhash: 3, matrix: [1.0, 0.0, 0.0, 1.0, 5.0, -6.5], z: 0.25 };
+    let anim = Anim { name: "idle".into(), facing: 255, bankhash: 7, framerate: 30.0, frames: vec![
+        Frame { rect: [0.0, 1.0, 2.0, 3.0], events: vec![9], elements: vec![element, Element { z: 0.5, ..element }] },
+        Frame::default(),
+    ]};
+    let bin = AnimBin { anims: vec![anim], hash_table: HashMap::from([(1, b"body".to_vec()), (3, b"layer".to_vec())]) };
+    let bytes = bin.to_bytes();
+    let parsed = AnimBin::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed.to_bytes(), bytes);
+    let anim = &parsed.anims[0];
+    assert_eq!((anim.name.as_str(), anim.facing, anim.bankhash, anim.framerate), ("idle", 255, 7, 30.0));
+    assert_eq!(anim.frames.len(), 2);
+    assert_eq!(anim.frames[0].events, vec![9]);
+    assert_eq!(anim.frames[0].elements, vec![element, Element { z: 0.5, ..element }]);
+    assert_eq!(parsed.hash_table, bin.hash_table);
+    // huge count in a short file is rejected before allocating
+    let mut bytes = bytes[..24].to_vec();
+    bytes[20..24].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(AnimBin::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn check_build_bin() {
+    let image = |index, vertices| BuildImage { index, duration: 1, x: 1.0, y: -2.0, w: 4.0, h: 8.0, vertices };
+    let bin = BuildBin {
+        name: "wilson".into(),
+        atlases: vec!["atlas-0.tex".into()],
+        symbols: vec![Symbol { imghash: 5, images: vec![image(0, vec![[0.0, 1.0, 0.0, 0.5, 0.5, 0.0]; 6]), image(2, vec![])] }],
+        hash_table: HashMap::from([(5, b"arm".to_vec())]),
+    };
+    let bytes = bin.to_bytes();
+    let parsed = BuildBin::from_bytes(&bytes).unwrap();
+    assert_eq!(parsed.to_bytes(), bytes);
+    assert_eq!((parsed.name.as_str(), parsed.atlases.as_slice()), ("wilson", bin.atlases.as_slice()));
+    let symbol = &parsed.symbols[0];
+    assert_eq!(symbol.imghash, 5);
+    assert_eq!(symbol.images.iter().map(|img| img.index).collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(symbol.images[0].vertices, bin.symbols[0].images[0].vertices);
+    assert_eq!(symbol.find_image(1).map(|img| img.index), None);
+    assert_eq!(parsed.hash_table, bin.hash_table);
+    let mut bytes = bytes[..16].to_vec();
+    bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(BuildBin::from_bytes(&bytes).is_err());
+}
+
+/// resolve hash to string by hash table, use `hash-{}` if not found
+pub fn resolve_hash(hash_table: &HashMap<u32, Vec<u8>>, hash: u32) -> String {
+    match hash_table.get(&hash) {
+        Some(name) => String::from_utf8_lossy(name).to_string(),
+        None => format!("hash-{}", hash),
+    }
+}
@@ -0,0 +1,1016 @@
+// native renderer for anim.bin + build.bin, replaces the render loop in renderer.lua
+// source images are still loaded by lua (atlas + provider), everything else runs here
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError};
+use std::time::Duration;
+use std::sync::{Arc, Condvar, Mutex};
+use once_cell::sync::Lazy;
+
+use crate::image::{AffineTransform, AlphaMode, BlendMode, ColorSpace, Resampler};
+use crate::image::lua_image::{Image, Filter, MipChain};
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnimElement {
+    pub imghash: u32,
+    pub imgindex: u32,
+    pub layerhash: u32,
+    /// a, b, c, d, tx, ty
+    pub matrix: [f64; 6],
+    pub z_index: f64,
+}
+
+impl AnimElement {
+    /// parse raw element bytes of anim.bin frame (40 bytes each)
+    pub fn parse_raw(raw: &[u8]) -> Vec<Self> {
+        let num = raw.len() / 40;
+        let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i+4].try_into().unwrap());
+        let f32_at = |b: &[u8], i: usize| f32::from_le_bytes(b[i..i+4].try_into().unwrap()) as f64;
+        raw.chunks_exact(40).map(|b| {
+            let mut matrix = [0.0; 6];
+            for (i, v) in matrix.iter_mut().enumerate() {
+                *v = f32_at(b, 12 + i* 4);
+            }
+            AnimElement {
+                imghash: u32_at(b, 0),
+                imgindex: u32_at(b, 4),
+                layerhash: u32_at(b, 8),
+                matrix,
+                // same as AnimLoader:ParseFrames()
+                z_index: (f32_at(b, 36) + 5.0)* num as f64 / 10.0 + 0.5,
+            }
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BuildImage {
+    pub index: u32,
+    pub duration: u32,
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolSource {
+    pub buildname: String,
+    pub imghash: u32,
+    /// sorted by index
+    pub imglist: Vec<BuildImage>,
+}
+
+impl SymbolSource {
+    /// find the image which is displayed at frame index
+    pub fn find_image(&self, imgindex: u32) -> Option<&BuildImage> {
+        let i = self.imglist.partition_point(|img| img.index <= imgindex);
+        if i == 0 {
+            return None;
+        }
+        let img = &self.imglist[i - 1];
+        if img.index + img.duration > imgindex {
+            Some(img)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// SetMultColour / SetAddColour and symbol level overrides
+#[derive(Debug, Clone, Default)]
+pub struct ColorParams {
+    pub mult: Option<[f64; 4]>,
+    pub add: Option<[f64; 4]>,
+    pub symbol_mult: HashMap<u32, [f64; 4]>,
+    pub symbol_add: HashMap<u32, [f64; 4]>,
+    /// blend mode of symbol (imghash) and layer (layerhash), layer takes priority
+    pub symbol_blend: HashMap<u32, BlendMode>,
+    pub layer_blend: HashMap<u32, BlendMode>,
+}
+
+impl ColorParams {
+    /// return filter and its unique key, None if color is not changed
+    fn get_filter(&self, imghash: u32) -> Option<(Filter, Option<u32>)> {
+        let symbol_mult = self.symbol_mult.get(&imghash);
+        let symbol_add = self.symbol_add.get(&imghash);
+        if self.mult.is_none() && self.add.is_none() && symbol_mult.is_none() && symbol_add.is_none() {
+            return None;
+        }
+        let mut mult = [1.0; 4];
+        for v in [self.mult.as_ref(), symbol_mult].into_iter().flatten() {
+            mult.iter_mut().zip(v).for_each(|(a, b)| *a *= b);
+        }
+        let mut add = [0.0, 0.0, 0.0, 1.0];
+        for v in [self.add.as_ref(), symbol_add].into_iter().flatten() {
+            for k in 0..3 {
+                add[k] += v[k]* v[3];
+            }
+        }
+        let key = if symbol_mult.is_some() || symbol_add.is_some() { Some(imghash) } else { None };
+        Some((Filter::mult_add(mult, add), key))
+    }
+
+    fn get_blend(&self, element: &AnimElement) -> BlendMode {
+        self.layer_blend.get(&element.layerhash)
+            .or_else(|| self.symbol_blend.get(&element.imghash))
+            .copied()
+            .unwrap_or(BlendMode::Normal)
+    }
+}
+
+/// an element image to be transformed
+struct ElementTask {
+    source: usize,
+    width: u32,
+    height: u32,
+    matrix: AffineTransform,
+    filter: Option<Filter>,
+}
+
+/// a transformed element placed on canvas
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    task: usize,
+    x: i64,
+    y: i64,
+    blend: BlendMode,
+}
+
+pub struct AnimRenderer {
+    sources: Vec<Image>,
+    /// mip chain of each source built on demand
+    mips: Vec<MipChain>,
+    /// size of all stored mip levels, counted in memory budget
+    mip_bytes: AtomicUsize,
+    tasks: Vec<ElementTask>,
+    /// placements of each frame, sorted by z index (back to front)
+    frames: Vec<Vec<Placement>>,
+}
+
+/// task key: source, size, matrix, color
+type TaskKey = (usize, u32, u32, Vec<u8>, Option<Option<u32>>);
+
+impl AnimRenderer {
+    /// build element tasks from frames,
+    /// `resolve` returns symbol of imghash (None if hidden or missing),
+    /// `load` returns source image index of (symbol, image)
+    pub fn new<E>(frames: &[Vec<AnimElement>], colors: &ColorParams,
+        mut resolve: impl FnMut(&AnimElement)-> Result<Option<SymbolSource>, E>,
+        mut load: impl FnMut(&SymbolSource, &BuildImage)-> Result<Image, E>) -> Result<Self, E> {
+        let mut symbols = HashMap::<(u32, u32), Option<SymbolSource>>::new();
+        let mut source_index = HashMap::<(String, u32, u32), usize>::new();
+        let mut sources = vec![];
+        let mut task_index = HashMap::<TaskKey, usize>::new();
+        let mut tasks = vec![];
+        let mut placements = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let mut buffer = vec![];
+            for element in frame {
+                let key = (element.imghash, element.layerhash);
+                if let Entry::Vacant(e) = symbols.entry(key) {
+                    e.insert(resolve(element)?);
+                }
+                let symbol = match &symbols[&key] {
+                    Some(symbol) => symbol,
+                    None => continue,
+                };
+                let img = match symbol.find_image(element.imgindex) {
+                    Some(img) => *img,
+                    None => continue,
+                };
+                let source_key = (symbol.buildname.clone(), symbol.imghash, img.index);
+                let source = match source_index.get(&source_key) {
+                    Some(&i) => i,
+                    None => {
+                        sources.push(load(symbol, &img)?);
+                        source_index.insert(source_key, sources.len() - 1);
+                        sources.len() - 1
+                    }
+                };
+                // bbox of transformed image, same as renderer.lua
+                let [a, b, c, d, px, py] = element.matrix;
+                let linear = AffineTransform::from_vec(vec![a, b, c, d, 0.0, 0.0]);
+                let (x0, y0, x1, y1) = linear.onrect(
+                    img.x - img.w / 2.0, img.y - img.h / 2.0, img.x + img.w / 2.0, img.y + img.h / 2.0);
+                let rect = [x0 - 1.0, y0 - 1.0, x1 + 1.0, y1 + 1.0];
+                let width = (rect[2].ceil() - rect[0].floor()) as u32;
+                let height = (rect[3].ceil() - rect[1].floor()) as u32;
+                let (gx, gy) = (px + rect[0], py + rect[1]);
+                let (ox, oy) = (img.x - img.w / 2.0, img.y - img.h / 2.0);
+                let (tx, ty) = (-rect[0] + gx.fract_floor(), -rect[1] + gy.fract_floor());
+                let matrix = AffineTransform::from_vec(vec![
+                    a, b, c, d,
+                    a* ox + c* oy + tx,
+                    b* ox + d* oy + ty,
+                ]);
+                let filter = colors.get_filter(element.imghash);
+                let task_key = (source, width, height, matrix.to_bytes(), filter.as_ref().map(|f| f.1));
+                let task = match task_index.get(&task_key) {
+                    Some(&i) => i,
+                    None => {
+                        tasks.push(ElementTask {
+                            source, width, height, matrix, filter: filter.map(|f| f.0)
+                        });
+                        task_index.insert(task_key, tasks.len() - 1);
+                        tasks.len() - 1
+                    }
+                };
+                buffer.push((element.z_index, Placement {
+                    task, x: gx.floor() as i64, y: gy.floor() as i64, blend: colors.get_blend(element)
+                }));
+            }
+            // larger z index is at back
+            buffer.sort_by(|a, b| b.0.total_cmp(&a.0));
+            placements.push(buffer.into_iter().map(|v| v.1).collect());
+        }
+        let mips = sources.iter().map(|_| MipChain::default()).collect();
+        Ok(AnimRenderer { sources, mips, mip_bytes: AtomicUsize::new(0), tasks, frames: placements })
+    }
+
+    #[inline]
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// bounding box of all placed elements: left, top, right, bottom
+    pub fn region(&self) -> Option<(i64, i64, i64, i64)> {
+        let mut result: Option<(i64, i64, i64, i64)> = None;
+        for p in self.frames.iter().flatten() {
+            let task = &self.tasks[p.task];
+            let (l, t, r, b) = (p.x, p.y, p.x + task.width as i64, p.y + task.height as i64);
+            result = Some(match result {
+                Some((l0, t0, r0, b0)) => (l0.min(l), t0.min(t), r0.max(r), b0.max(b)),
+                None => (l, t, r, b),
+            });
+        }
+        result
+    }
+
+    /// transform element, result is shared with other renderers by `TRANSFORM_CACHE`
+    fn transform(&self, task: usize, options: &RenderOptions) -> Option<Image> {
+        let task = &self.tasks[task];
+        let source = &self.sources[task.source];
+        let level = if options.mipmap {
+            source.mip_level(&task.matrix, options.scale* options.supersample as f64)
+        }
+        else {
+            0
+        };
+        let key = TransformKey {
+            source: source.storage_id(),
+            width: task.width,
+            height: task.height,
+            matrix: task.matrix.to_bits(),
+            level,
+            resampler: options.resampler,
+            supersample: options.supersample,
+            alpha: options.alpha,
+            colorspace: options.colorspace,
+            filter: task.filter.as_ref().map(Filter::to_bits),
+        };
+        if let Some(img) = TRANSFORM_CACHE.lock().unwrap().get(&key) {
+            return Some(img);
+        }
+        // mip levels may take up to half of memory budget, the rest is for canvases and elements
+        let mip = self.mips[task.source].get(source, level, &self.mip_bytes, options.memory / 2);
+        let mut img = mip
+            .affine_transform(task.width, task.height, source.mip_matrix(&task.matrix, &mip),
+                options.resampler, options.supersample, options.alpha, options.colorspace)
+            .ok()?;
+        if let Some(filter) = task.filter.as_ref() {
+            img.apply_filter(filter, options.colorspace);
+        }
+        let mut cache = TRANSFORM_CACHE.lock().unwrap();
+        cache.set_budget(options.cache);
+        cache.insert(key, source, &img);
+        Some(img)
+    }
+
+    /// composite a single frame on a copy of canvas, (left, top) is the global coord of canvas origin
+    pub fn composite(&self, index: usize, canvas: &Image, left: i64, top: i64, options: &RenderOptions) -> Image {
+        let mut canvas = canvas.clone();
+        for p in self.frames[index].iter() {
+            if let Some(img) = self.transform(p.task, options) {
+                canvas.paste(&img, p.x - left, p.y - top, p.blend, options.colorspace);
+            }
+        }
+        canvas
+    }
+
+    /// streaming render: transform -> composite -> encode, connected by bounded queues.
+    /// Number of in-flight frames is limited by `options.memory`, and transformed elements are
+    /// released once no pending frame needs them (or cache is over budget).
+    /// `encoder` receives frames in order, and may return the canvas for reuse.
+    /// `poll` is called periodically on current thread, return error to cancel rendering.
+    /// Panicked workers are reported as error.
+    pub fn render<E: From<String>>(&self, canvas: &Image, left: i64, top: i64, options: &RenderOptions,
+        mut encoder: impl FnMut(Image, usize)-> Result<Option<Image>, E>,
+        mut poll: impl FnMut()-> Result<(), E>) -> Result<(), E> {
+        let total = self.frames.len();
+        if total == 0 {
+            return Ok(());
+        }
+        let num_threads = options.thread.max(1);
+        let canvas_bytes = (canvas.width as usize* canvas.height as usize* 4).max(1);
+        // half of budget for canvases, the rest for element cache and mip levels
+        let max_inflight = (options.memory / 2 / canvas_bytes).clamp(1, num_threads* 2);
+        let cache_budget = options.memory.saturating_sub(max_inflight* canvas_bytes);
+
+        // number of frames which still need the element
+        let mut uses = vec![0; self.tasks.len()];
+        for frame in self.frames.iter() {
+            let mut tasks = frame.iter().map(|p| p.task).collect::<Vec<_>>();
+            tasks.sort_unstable();
+            tasks.dedup();
+            tasks.into_iter().for_each(|t| uses[t] += 1);
+        }
+        let cache = ElementCache {
+            slots: (0..self.tasks.len()).map(|_| Mutex::new(None)).collect(),
+            uses: uses.into_iter().map(AtomicUsize::new).collect(),
+            bytes: AtomicUsize::new(0),
+            mip_bytes: &self.mip_bytes,
+            budget: cache_budget,
+        };
+        let next_frame = AtomicUsize::new(0);
+        let turn = Mutex::new(0);
+        let cond = Condvar::new();
+        let abort = AtomicBool::new(false);
+        let stop = || {
+            abort.store(true, Ordering::Relaxed);
+            cond.notify_all();
+        };
+
+        std::thread::scope(|s| {
+            let mut workers = Vec::with_capacity(num_threads* 2);
+            let (ready_tx, ready_rx) = sync_channel::<FrameJob>(max_inflight);
+            let (pool_tx, pool_rx) = sync_channel::<Image>(max_inflight);
+            let (done_tx, done_rx) = sync_channel::<(usize, Image)>(max_inflight);
+            for _ in 0..max_inflight {
+                pool_tx.send(canvas.clone()).unwrap();
+            }
+            // stage 1: transform elements of each frame, send frames in order
+            for _ in 0..num_threads {
+                let ready_tx = ready_tx.clone();
+                let (cache, next_frame, turn, cond, abort, stop) = (&cache, &next_frame, &turn, &cond, &abort, &stop);
+                workers.push(s.spawn(move || {
+                    let _guard = StopOnPanic(stop);
+                    loop {
+                        let index = next_frame.fetch_add(1, Ordering::Relaxed);
+                        if index >= total {
+                            break;
+                        }
+                        let elements = self.frames[index].iter()
+                            .take_while(|_| !abort.load(Ordering::Relaxed))
+                            .filter_map(|p| cache.get(p.task, || self.transform(p.task, options))
+                                .map(|img| (img, p.x - left, p.y - top, p.blend)))
+                            .collect();
+                        let mut current = turn.lock().unwrap();
+                        while *current != index && !abort.load(Ordering::Relaxed) {
+                            current = cond.wait(current).unwrap();
+                        }
+                        if abort.load(Ordering::Relaxed) || ready_tx.send(FrameJob { index, elements }).is_err() {
+                            stop();
+                            break;
+                        }
+                        *current += 1;
+                        cond.notify_all();
+                    }
+                }));
+            }
+            drop(ready_tx);
+            // stage 2: composite on pooled canvas, canvas is acquired in frame order
+            let inputs = Arc::new(Mutex::new((ready_rx, pool_rx)));
+            for _ in 0..num_threads {
+                let inputs = Arc::clone(&inputs);
+                let done_tx = done_tx.clone();
+                let (cache, stop) = (&cache, &stop);
+                workers.push(s.spawn(move || {
+                    let _guard = StopOnPanic(stop);
+                    loop {
+                        let (job, mut img) = {
+                            let inputs = inputs.lock().unwrap();
+                            let job = match inputs.0.recv() {
+                                Ok(job) => job,
+                                Err(_) => break,
+                            };
+                            match inputs.1.recv() {
+                                Ok(img) => (job, img),
+                                Err(_) => break,
+                            }
+                        };
+                        img.reset_from(canvas);
+                        for (ele, x, y, blend) in job.elements.iter() {
+                            img.paste(ele, *x, *y, *blend, options.colorspace);
+                        }
+                        drop(job.elements);
+                        cache.release(&self.frames[job.index]);
+                        if done_tx.send((job.index, img)).is_err() {
+                            stop();
+                            break;
+                        }
+                    }
+                }));
+            }
+            drop(inputs);
+            drop(done_tx);
+            // stage 3: encode in order on current thread
+            let mut pending = BTreeMap::new();
+            let mut current = 0;
+            let result = loop {
+                if current == total {
+                    break Ok(());
+                }
+                if let Err(e) = poll() {
+                    break Err(e);
+                }
+                // a worker panicked, remaining frames will never arrive
+                if abort.load(Ordering::Relaxed) {
+                    break Err(format!("Render worker failed before frame {}", current).into());
+                }
+                match pending.remove(&current) {
+                    Some(img) => {
+                        match encoder(img, current) {
+                            Ok(reuse) => {
+                                let img = reuse.unwrap_or_else(|| canvas.clone());
+                                // pool is closed when all frames are dispatched
+                                let _ = pool_tx.try_send(img);
+                                current += 1;
+                            },
+                            Err(e) => break Err(e),
+                        }
+                    },
+                    None => match done_rx.recv_timeout(Duration::from_millis(100)) {
+                        Ok((index, img)) => { pending.insert(index, img); },
+                        Err(RecvTimeoutError::Timeout) => (),
+                        // worker exited unexpectedly
+                        Err(RecvTimeoutError::Disconnected) => {
+                            break Err(format!("Render worker exited before frame {}", current).into());
+                        },
+                    }
+                }
+            };
+            stop();
+            // close queues so that blocked workers exit, then collect panics instead of unwinding here
+            drop((pool_tx, done_rx, pending));
+            let panicked = workers.into_iter().map(|w| w.join()).filter(Result::is_err).count();
+            match result {
+                Ok(()) if panicked > 0 => Err(format!("{} render worker(s) panicked", panicked).into()),
+                result => result,
+            }
+        })
+    }
+}
+
+pub struct RenderOptions {
+    pub thread: usize,
+    pub resampler: Resampler,
+    pub supersample: u32,
+    /// sample elements in premultiplied alpha to avoid dark fringes
+    pub alpha: AlphaMode,
+    /// transform, filter and composite in linear light, closer to in-game renderer
+    pub colorspace: ColorSpace,
+    /// sample elements from mip level picked by matrix, avoid aliasing on downscaled elements
+    pub mipmap: bool,
+    /// output scale applied after rendering (eg. export scale), only used for mip selection
+    pub scale: f64,
+    /// memory budget in bytes for canvases and transformed elements
+    pub memory: usize,
+    /// budget in bytes of transformed elements kept between renders, see `TRANSFORM_CACHE`
+    pub cache: usize,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            thread: num_cpus::get(),
+            resampler: Resampler::Bilinear,
+            supersample: 1,
+            alpha: AlphaMode::Premultiplied,
+            colorspace: ColorSpace::Srgb,
+            mipmap: true,
+            scale: 1.0,
+            memory: 1 << 30,
+            cache: 256 << 20,
+        }
+    }
+}
+
+/// everything that affects a transformed element, floats are compared by bits
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TransformKey {
+    /// address of source pixels, the source is kept alive by cache entry so the address is not reused
+    source: usize,
+    width: u32,
+    height: u32,
+    matrix: [u64; 6],
+    /// mip level of source
+    level: u32,
+    resampler: Resampler,
+    supersample: u32,
+    alpha: AlphaMode,
+    colorspace: ColorSpace,
+    filter: Option<Vec<u64>>,
+}
+
+/// bounded LRU cache of transformed elements, held poses and looping anims
+/// repeat the same element on many frames
+struct TransformCache {
+    /// key -> (source, result, last used)
+    entries: HashMap<TransformKey, (Image, Image, u64)>,
+    /// last used -> key
+    order: BTreeMap<u64, TransformKey>,
+    tick: u64,
+    bytes: usize,
+    budget: usize,
+}
+
+impl TransformCache {
+    fn new(budget: usize) -> Self {
+        TransformCache { entries: HashMap::new(), order: BTreeMap::new(), tick: 0, bytes: 0, budget }
+    }
+
+    fn size_of(source: &Image, result: &Image) -> usize {
+        source.as_bytes().len() + result.as_bytes().len()
+    }
+
+    fn get(&mut self, key: &TransformKey) -> Option<Image> {
+        let (_, result, last_used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let key = self.order.remove(last_used).unwrap();
+        *last_used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(result.clone())
+    }
+
+    fn insert(&mut self, key: TransformKey, source: &Image, result: &Image) {
+        let size = Self::size_of(source, result);
+        if size > self.budget {
+            return;
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        if let Some((source, result, last_used)) = self.entries.insert(key, (source.clone(), result.clone(), self.tick)) {
+            self.order.remove(&last_used);
+            self.bytes -= Self::size_of(&source, &result);
+        }
+        self.bytes += size;
+        self.set_budget(self.budget);
+    }
+
+    /// change budget in bytes, and evict least recently used elements
+    fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        while self.bytes > self.budget {
+            let Some((_, key)) = self.order.pop_first() else { break };
+            if let Some((source, result, _)) = self.entries.remove(&key) {
+                self.bytes -= Self::size_of(&source, &result);
+            }
+        }
+    }
+}
+
+/// shared by all renderers and kept between renders
+static TRANSFORM_CACHE: Lazy<Mutex<TransformCache>> = Lazy::new(||{
+    Mutex::new(TransformCache::new(RenderOptions::default().cache))
+});
+
+/// a frame with its transformed elements, ready to composite
+struct FrameJob {
+    index: usize,
+    elements: Vec<(Arc<Image>, i64, i64, BlendMode)>,
+}
+
+struct ElementCache<'a> {
+    slots: Vec<Mutex<Option<Arc<Image>>>>,
+    uses: Vec<AtomicUsize>,
+    bytes: AtomicUsize,
+    /// mip levels share the budget with elements
+    mip_bytes: &'a AtomicUsize,
+    budget: usize,
+}
+
+impl ElementCache<'_> {
+    /// get transformed element, slot is locked while transforming so that it runs only once
+    fn get(&self, task: usize, transform: impl FnOnce()-> Option<Image>) -> Option<Arc<Image>> {
+        let mut slot = self.slots[task].lock().unwrap();
+        if slot.is_none() {
+            let img = Arc::new(transform()?);
+            self.bytes.fetch_add(img.width as usize* img.height as usize* 4, Ordering::Relaxed);
+            *slot = Some(img);
+        }
+        slot.clone()
+    }
+
+    /// called after frame is composited
+    fn release(&self, frame: &[Placement]) {
+        // element may appear multiple times in a frame
+        let mut tasks = frame.iter().map(|p| p.task).collect::<Vec<_>>();
+        tasks.sort_unstable();
+        tasks.dedup();
+        for task in tasks {
+            let last = self.uses[task].fetch_sub(1, Ordering::Relaxed) == 1;
+            if last || self.bytes.load(Ordering::Relaxed) + self.mip_bytes.load(Ordering::Relaxed) > self.budget {
+                if let Some(img) = self.slots[task].lock().unwrap().take() {
+                    self.bytes.fetch_sub(img.width as usize* img.height as usize* 4, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// calls `stop` if a worker unwinds, so that workers waiting for its turn are released
+struct StopOnPanic<F: Fn()>(F);
+
+impl<F: Fn()> Drop for StopOnPanic<F> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            (self.0)();
+        }
+    }
+}
+
+trait FractFloor {
+    fn fract_floor(self) -> f64;
+}
+
+impl FractFloor for f64 {
+    /// x - floor(x), always positive
+    #[inline]
+    fn fract_floor(self) -> f64 {
+        self - self.floor()
+    }
+}
+
+#[test]
+fn check_transform_cache() {
+    let source = Image::from_rgba(vec![255; 2* 2* 4], 2, 2).unwrap();
+    let key = |tx: f64, level| TransformKey {
+        source: source.storage_id(), width: 2, height: 2,
+        matrix: AffineTransform::from_vec(vec![1.0, 0.0, 0.0, 1.0, tx, 0.0]).to_bits(), level,
+        resampler: Resampler::Bilinear, supersample: 1, alpha: AlphaMode::Premultiplied,
+        colorspace: ColorSpace::Srgb, filter: None };
+    // budget of two entries (source + result)
+    let mut cache = TransformCache::new(2* 2* 2* 4* 2);
+    cache.insert(key(0.0, 0), &source, &source);
+    cache.insert(key(1.0, 0), &source, &source);
+    assert!(cache.get(&key(0.0, 0)).is_some());
+    cache.insert(key(2.0, 0), &source, &source);
+    assert!(cache.get(&key(1.0, 0)).is_none());
+    assert!(cache.get(&key(0.0, 0)).is_some() && cache.get(&key(2.0, 0)).is_some());
+    // signed zero and mip level are different keys
+    assert!(cache.get(&key(-0.0, 0)).is_none());
+    assert!(cache.get(&key(0.0, 1)).is_none());
+    let linear = TransformKey { colorspace: ColorSpace::Linear, ..key(0.0, 0) };
+    assert!(cache.get(&linear).is_none());
+    let filtered = TransformKey { filter: Some(Filter::Hsv(0.0, 1.0, 1.0).to_bits()), ..key(0.0, 0) };
+    assert!(cache.get(&filtered).is_none());
+}
+
+#[test]
+fn check_render() {
+    let symbol = SymbolSource { buildname: "build".into(), imghash: 1, imglist: vec![
+        BuildImage { index: 0, duration: 2, x: 0.0, y: 0.0, w: 4.0, h: 4.0 },
+        BuildImage { index: 5, duration: 1, x: 0.0, y: 0.0, w: 4.0, h: 4.0 },
+    ]};
+    assert_eq!(symbol.find_image(1).map(|img| img.index), Some(0));
+    assert!(symbol.find_image(3).is_none());
+    let element = |imgindex, tx| AnimElement {
+        imghash: 1, imgindex, layerhash: 0, matrix: [1.0, 0.0, 0.0, 1.0, tx, 0.0], z_index: 0.0 };
+    let frames = vec![vec![element(0, 0.0)], vec![element(1, 10.0), element(3, 0.0)]];
+    let renderer = AnimRenderer::new::<()>(&frames, &ColorParams::default(),
+        |_| Ok(Some(symbol.clone())),
+        |_, _| Ok(Image::from_rgba(vec![255; 4* 4* 4], 4, 4).unwrap())).unwrap();
+    // same image and matrix in both frames
+    assert_eq!(renderer.tasks.len(), 1);
+    assert_eq!(renderer.region(), Some((-3, -3, 13, 3)));
+    let canvas = Image::from_rgba(vec![0; 16* 6* 4], 16, 6).unwrap();
+    // budget of a single canvas
+    let options = RenderOptions { thread: 2, resampler: Resampler::Nearest, supersample: 1,
+        alpha: AlphaMode::Premultiplied, colorspace: ColorSpace::Srgb, mipmap: true, scale: 1.0, memory: 16* 6* 4, cache: 0 };
+    let mut count = 0;
+    renderer.render::<String>(&canvas, -3, -3, &options, |img, index| {
+        assert_eq!(index, count);
+        assert_eq!((img.width, img.height), (16, 6));
+        count += 1;
+        Ok(Some(img))
+    }, || Ok(())).unwrap();
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn check_render_blend() {
+    let symbol = SymbolSource { buildname: "build".into(), imghash: 1, imglist: vec![
+        BuildImage { index: 0, duration: 1, x: 0.0, y: 0.0, w: 4.0, h: 4.0 },
+    ]};
+    let frames = vec![vec![AnimElement {
+        imghash: 1, imgindex: 0, layerhash: 2, matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0], z_index: 0.0 }]];
+    let colors = ColorParams { layer_blend: HashMap::from([(2, BlendMode::Additive)]), ..ColorParams::default() };
+    let renderer = AnimRenderer::new::<()>(&frames, &colors,
+        |_| Ok(Some(symbol.clone())),
+        |_, _| Ok(Image::from_rgba([50, 50, 50, 255].repeat(4* 4), 4, 4).unwrap())).unwrap();
+    let canvas = Image::from_rgba([100, 100, 100, 255].repeat(8* 8), 8, 8).unwrap();
+    let options = RenderOptions { thread: 2, resampler: Resampler::Nearest, ..RenderOptions::default() };
+    // additive element brightens canvas instead of covering it
+    let check = |img: &Image| assert_eq!(&img.as_bytes()[(4* 8 + 4)* 4..][..4], [150, 150, 150, 255]);
+    check(&renderer.composite(0, &canvas, -4, -4, &options));
+    renderer.render::<String>(&canvas, -4, -4, &options, |img, _| {
+        check(&img);
+        Ok(Some(img))
+    }, || Ok(())).unwrap();
+}
+
+#[test]
+fn check_render_canvas_reuse() {
+    let ids = Arc::new(Mutex::new(vec![]));
+    let lua = rlua::Lua::new();
+    lua.context(|lua_ctx| -> rlua::Result<()> {
+        crate::image::lua_image::init(lua_ctx)?;
+        let ids = Arc::clone(&ids);
+        lua_ctx.globals().set("record", lua_ctx.create_function(move |_, img: rlua::AnyUserData|{
+            ids.lock().unwrap().push(img.borrow::<Image>()?.storage_id());
+            Ok(())
+        })?)?;
+        lua_ctx.load(r#"
+            local frames = {}
+            for i = 1, 6 do
+                frames[i] = {{ imghash = 1, imgindex = 0, layerhash = 0, matrix = {1, 0, 0, 1, i, 0}, z_index = 0 }}
+            end
+            local r = Image.AnimRenderer{
+                frames = frames,
+                build = { buildname = "build", symbol_map = {
+                    [1] = { imghash = 1, imglist = {{ index = 0, duration = 1, x = 0, y = 0, w = 4, h = 4 }} },
+                }},
+                loader = function() return Image.From_RGBA(string.rep("\255", 4* 4* 4), 4, 4) end,
+            }
+            -- budget of a single canvas in flight
+            r:prepare{ thread = 1, memory = 1 }
+            r:render{
+                canvas = Image.From_RGBA(string.rep("\0", 512* 512* 4), 512, 512),
+                encoder = function(img) record(img) end,
+            }
+        "#).exec()
+    }).unwrap();
+    let ids = ids.lock().unwrap();
+    assert_eq!(ids.len(), 6);
+    // first frame copies the shared canvas, following frames are drawn on the same buffer
+    assert!(ids[1..].iter().all(|id| *id == ids[1]), "{:?}", ids);
+}
+
+pub mod lua_animrender {
+    use super::*;
+    use rlua::prelude::{LuaResult, LuaError};
+    use rlua::{Context, Function, Table, UserData, UserDataMethods, Value, Variadic};
+    use crate::image::lua_image::{is_interrupted, interrupted_error};
+
+    /// error of lua callbacks or failed render workers
+    struct RenderError(LuaError);
+
+    impl From<LuaError> for RenderError {
+        fn from(e: LuaError) -> Self {
+            RenderError(e)
+        }
+    }
+
+    impl From<String> for RenderError {
+        fn from(e: String) -> Self {
+            RenderError(LuaError::RuntimeError(e))
+        }
+    }
+
+    pub struct LuaAnimRenderer {
+        inner: AnimRenderer,
+        /// canvas origin
+        left: i64,
+        top: i64,
+        options: RenderOptions,
+    }
+
+    fn get_options(t: Table) -> LuaResult<RenderOptions> {
+        let default = RenderOptions::default();
+        Ok(RenderOptions {
+            thread: t.get::<_, Option<usize>>("thread")?.unwrap_or(default.thread).clamp(1, 64),
+            resampler: t.get::<_, Option<Resampler>>("resampler")?.unwrap_or(default.resampler),
+            supersample: t.get::<_, Option<u32>>("supersample")?.unwrap_or(default.supersample).clamp(1, 8),
+            alpha: t.get::<_, Option<AlphaMode>>("alpha")?.unwrap_or(default.alpha),
+            colorspace: t.get::<_, Option<ColorSpace>>("colorspace")?.unwrap_or(default.colorspace),
+            mipmap: t.get::<_, Option<bool>>("mipmap")?.unwrap_or(default.mipmap),
+            scale: t.get::<_, Option<f64>>("scale")?.unwrap_or(default.scale).clamp(0.01, 1.0),
+            memory: t.get::<_, Option<usize>>("memory")?.map(|mb| mb << 20).unwrap_or(default.memory),
+            cache: t.get::<_, Option<usize>>("cache")?.map(|mb| mb << 20).unwrap_or(default.cache),
+        })
+    }
+
+    fn get_color(t: &Table, key: &str) -> LuaResult<Option<[f64; 4]>> {
+        Ok(match t.get::<_, Option<Vec<f64>>>(key)? {
+            Some(v) => {
+                let mut c = [1.0; 4];
+                c.iter_mut().zip(v).for_each(|(a, b)| *a = b);
+                Some(c)
+            },
+            None => None,
+        })
+    }
+
+    fn get_color_map(t: &Table, key: &str) -> LuaResult<HashMap<u32, [f64; 4]>> {
+        let mut result = HashMap::new();
+        if let Some(map) = t.get::<_, Option<Table>>(key)? {
+            for pair in map.pairs::<u32, Vec<f64>>() {
+                let (k, v) = pair?;
+                let mut c = [1.0; 4];
+                c.iter_mut().zip(v).for_each(|(a, b)| *a = b);
+                result.insert(k, c);
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_blend_map(t: &Option<Table>, key: &str) -> LuaResult<HashMap<u32, BlendMode>> {
+        let mut result = HashMap::new();
+        if let Some(map) = t.as_ref().map(|t| t.get::<_, Option<Table>>(key)).transpose()?.flatten() {
+            for pair in map.pairs::<u32, BlendMode>() {
+                let (k, v) = pair?;
+                result.insert(k, v);
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_frames(frames: Table) -> LuaResult<Vec<Vec<AnimElement>>> {
+        let mut result = vec![];
+        for frame in frames.sequence_values::<Table>() {
+            let frame = frame?;
+            if let Some(raw) = frame.get::<_, Option<rlua::String>>("raw")? {
+                result.push(AnimElement::parse_raw(raw.as_bytes()));
+                continue;
+            }
+            let mut elements = vec![];
+            for e in frame.sequence_values::<Table>() {
+                let e = e?;
+                let m = e.get::<_, Vec<f64>>("matrix")?;
+                if m.len() < 6 {
+                    return Err(LuaError::RuntimeError("element matrix must contain 6 numbers".into()));
+                }
+                elements.push(AnimElement {
+                    imghash: e.get("imghash")?,
+                    imgindex: e.get("imgindex")?,
+                    layerhash: e.get("layerhash")?,
+                    matrix: [m[0], m[1], m[2], m[3], m[4], m[5]],
+                    z_index: e.get("z_index")?,
+                });
+            }
+            result.push(elements);
+        }
+        Ok(result)
+    }
+
+    fn get_symbol(buildname: String, symbol: Table) -> LuaResult<SymbolSource> {
+        let mut imglist = vec![];
+        for img in symbol.get::<_, Table>("imglist")?.sequence_values::<Table>() {
+            let img = img?;
+            imglist.push(BuildImage {
+                index: img.get("index")?,
+                duration: img.get("duration")?,
+                x: img.get("x")?,
+                y: img.get("y")?,
+                w: img.get("w")?,
+                h: img.get("h")?,
+            });
+        }
+        imglist.sort_by_key(|img| img.index);
+        Ok(SymbolSource { buildname, imghash: symbol.get("imghash")?, imglist })
+    }
+
+    // create a renderer and load all element images
+    //   frames     anim.frame list, each frame is element list or {raw = bytes}
+    //   build      main build loader (buildname, symbol_map)
+    //   overrides  {[hash] = {buildname, symboldata}}, see Render:BuildSymbolSource()
+    //   hide       {symbol = {[hash] = true}, layer = {[hash] = true}}
+    //   color      {mult, add, symbol_mult, symbol_add}
+    //   blend      {symbol = {[hash] = Image.BLEND_XXX}, layer = {[hash] = Image.BLEND_XXX}}
+    //   loader     function(buildname, imghash, index) -> Image
+    pub fn create_renderer(args: Table) -> LuaResult<LuaAnimRenderer> {
+        let frames = get_frames(args.get::<_, Table>("frames")?)?;
+        let build = args.get::<_, Option<Table>>("build")?;
+        let overrides = args.get::<_, Option<Table>>("overrides")?;
+        let loader = args.get::<_, Function>("loader")?;
+        let (hide_symbol, hide_layer) = match args.get::<_, Option<Table>>("hide")? {
+            Some(t) => (t.get::<_, Option<Table>>("symbol")?, t.get::<_, Option<Table>>("layer")?),
+            None => (None, None),
+        };
+        let is_hidden = |t: &Option<Table>, hash: u32| -> LuaResult<bool> {
+            Ok(match t {
+                Some(t) => !matches!(t.get::<_, Value>(hash)?, Value::Nil | Value::Boolean(false)),
+                None => false,
+            })
+        };
+        let blend = args.get::<_, Option<Table>>("blend")?;
+        let colors = match args.get::<_, Option<Table>>("color")? {
+            Some(t) => ColorParams {
+                mult: get_color(&t, "mult")?,
+                add: get_color(&t, "add")?,
+                symbol_mult: get_color_map(&t, "symbol_mult")?,
+                symbol_add: get_color_map(&t, "symbol_add")?,
+                ..ColorParams::default()
+            },
+            None => ColorParams::default(),
+        };
+        let colors = ColorParams {
+            symbol_blend: get_blend_map(&blend, "symbol")?,
+            layer_blend: get_blend_map(&blend, "layer")?,
+            ..colors
+        };
+        let resolve = |element: &AnimElement| -> LuaResult<Option<SymbolSource>> {
+            if is_hidden(&hide_symbol, element.imghash)? || is_hidden(&hide_layer, element.layerhash)? {
+                return Ok(None);
+            }
+            let hash = element.imghash;
+            if let Some(source) = overrides.as_ref().map(|t| t.get::<_, Option<Table>>(hash)).transpose()?.flatten() {
+                if let Some(symbol) = source.get::<_, Option<Table>>("symboldata")? {
+                    return get_symbol(source.get("buildname")?, symbol).map(Some);
+                }
+            }
+            if let Some(build) = build.as_ref() {
+                let symbol = build.get::<_, Table>("symbol_map")?.get::<_, Option<Table>>(hash)?;
+                if let Some(symbol) = symbol {
+                    return get_symbol(build.get("buildname")?, symbol).map(Some);
+                }
+            }
+            Ok(None)
+        };
+        let load = |symbol: &SymbolSource, img: &BuildImage| -> LuaResult<Image> {
+            let data = loader.call::<_, rlua::AnyUserData>(
+                (symbol.buildname.as_str(), symbol.imghash, img.index))?;
+            let img = data.borrow::<Image>()?;
+            Ok(img.clone())
+        };
+        let inner = AnimRenderer::new(&frames, &colors, resolve, load)?;
+        Ok(LuaAnimRenderer { inner, left: 0, top: 0, options: RenderOptions::default() })
+    }
+
+    impl UserData for LuaAnimRenderer {
+        fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {
+            _methods.add_method("numframe", |_, r: &Self, ()|{
+                Ok(r.inner.num_frames())
+            });
+            // set render options, return canvas region (left, top, width, height), nil if empty
+            //   thread, resampler, supersample, alpha, colorspace, memory: budget in MB (default 1024)
+            //   mipmap (default true), scale: export scale for mip selection (default 1.0)
+            //   cache: budget in MB of transformed elements kept between renders (default 256)
+            _methods.add_method_mut("prepare", |_, r: &mut Self, options: Option<Table>|{
+                if let Some(t) = options {
+                    r.options = get_options(t)?;
+                }
+                match r.inner.region() {
+                    Some((left, top, right, bottom)) => {
+                        r.left = left;
+                        r.top = top;
+                        Ok(Variadic::from_iter([left, top, right - left, bottom - top]))
+                    },
+                    None => Ok(Variadic::new()),
+                }
+            });
+            // render all frames on canvas, and send to encoder in order
+            //   canvas, encoder: function(img, index), progress: function(current, total, percent)
+            // pixels of the frame image are moved back to canvas pool after encoder returns,
+            // use img:clone() to keep the frame in lua
+            _methods.add_method("render", |lua, r: &Self, options: Table|{
+                let canvas = options.get::<_, rlua::AnyUserData>("canvas")?;
+                let canvas = canvas.borrow::<Image>()?;
+                let encoder = options.get::<_, Function>("encoder")?;
+                let progress = options.get::<_, Option<Function>>("progress")?;
+                let total = r.inner.num_frames();
+                r.inner.render::<RenderError>(&canvas, r.left, r.top, &r.options, |img, index|{
+                    // lua index starts at 1
+                    let data = lua.create_userdata(img)?;
+                    encoder.call::<_, ()>((data.clone(), index + 1))?;
+                    if let Some(progress) = &progress {
+                        progress.call::<_, ()>((index + 1, total, (index + 1) as f64 / total as f64))?;
+                    }
+                    // leave the only reference to pool, so that the canvas buffer is reused
+                    let img = std::mem::replace(&mut *data.borrow_mut::<Image>()?, Image::empty());
+                    Ok(Some(img))
+                }, ||{
+                    if is_interrupted(lua) { Err(interrupted_error().into()) } else { Ok(()) }
+                }).map_err(|e| e.0)
+            });
+            // get one composited frame (index starts at 1)
+            _methods.add_method("frame", |_, r: &Self, (index, canvas): (usize, rlua::AnyUserData)|{
+                if index == 0 || index > r.inner.num_frames() {
+                    return Err(LuaError::RuntimeError(format!("Frame index out of range: {}", index)));
+                }
+                let canvas = canvas.borrow::<Image>()?;
+                Ok(r.inner.composite(index - 1, &canvas, r.left, r.top, &r.options))
+            });
+        }
+    }
+
+    pub fn init<'lua>(lua_ctx: Context<'lua>, table: &Table<'lua>) -> LuaResult<()> {
+        table.set("AnimRenderer", lua_ctx.create_function(|_, args: Table|{
+            create_renderer(args)
+        })?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,606 @@
+// native animated image writer (gif / apng / webp), no FFmpeg required
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimFormat {
+    Gif,
+    Apng,
+    Webp,
+}
+
+impl AnimFormat {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "gif" => Some(AnimFormat::Gif),
+            "apng" => Some(AnimFormat::Apng),
+            "webp" => Some(AnimFormat::Webp),
+            _ => None,
+        }
+    }
+
+    /// time unit of frame delay, in 1/n second
+    fn time_base(&self) -> f64 {
+        match self {
+            AnimFormat::Gif => 100.0,
+            AnimFormat::Apng | AnimFormat::Webp => 1000.0,
+        }
+    }
+}
+
+/// native frame writer, receives rgba frames of canvas size in order,
+/// exposed to lua by `lua_animwriter::LuaFrameSink`
+pub trait FrameSink {
+    /// canvas size
+    fn size(&self) -> (u32, u32);
+    fn encode_frame(&mut self, rgba: &[u8]) -> Result<(), String>;
+    /// complete the output after all frames are sent
+    fn finish(&mut self) -> Result<(), String>;
+}
+
+/// sub-rect of canvas (x, y, width, height)
+pub(crate) type Rect = (u32, u32, u32, u32);
+
+/// find the bounding box of changed pixels, return None if two frames are identical
+fn diff_bbox(prev: &[u8], cur: &[u8], width: u32, height: u32) -> Option<Rect> {
+    let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+    for y in 0..height {
+        let row = (y* width* 4) as usize..((y + 1)* width* 4) as usize;
+        if prev[row.clone()] == cur[row.clone()] {
+            continue;
+        }
+        let (p, c) = (&prev[row.clone()], &cur[row]);
+        for x in 0..width {
+            let i = (x* 4) as usize;
+            if p[i..i+4] != c[i..i+4] {
+                x0 = x0.min(x);
+                x1 = x1.max(x);
+            }
+        }
+        y0 = y0.min(y);
+        y1 = y1.max(y);
+    }
+    if x0 > x1 {
+        None
+    }
+    else {
+        Some((x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+    }
+}
+
+pub(crate) fn crop_rgba(bytes: &[u8], width: u32, rect: Rect) -> Vec<u8> {
+    let (x, y, w, h) = rect;
+    let mut result = Vec::with_capacity((w* h* 4) as usize);
+    for row in y..y+h {
+        let start = ((row* width + x)* 4) as usize;
+        result.extend_from_slice(&bytes[start..start + (w* 4) as usize]);
+    }
+    result
+}
+
+/// gif only has 1-bit alpha
+fn binarize_alpha(bytes: &mut [u8]) {
+    for pix in bytes.chunks_exact_mut(4) {
+        if pix[3] >= 128 {
+            pix[3] = 255;
+        }
+        else {
+            pix.copy_from_slice(&[0, 0, 0, 0]);
+        }
+    }
+}
+
+/// build a gif frame with local palette, the last index is reserved for transparent pixels
+fn quantize_gif_frame(rgba: &[u8], rect: Rect) -> gif::Frame<'static> {
+    use std::collections::HashMap;
+    const TRANSPARENT: u8 = 255;
+    let opaque = rgba.chunks_exact(4)
+        .filter(|pix| pix[3] != 0)
+        .flat_map(|pix| pix.iter().copied())
+        .collect::<Vec<u8>>();
+    let mut colors = HashMap::<[u8; 3], u8>::new();
+    for pix in opaque.chunks_exact(4) {
+        let len = colors.len();
+        if len > 255 {
+            break;
+        }
+        colors.entry([pix[0], pix[1], pix[2]]).or_insert(len as u8);
+    }
+    let (palette, indices) = if colors.len() <= 255 {
+        // exact palette
+        let mut palette = vec![0; 256* 3];
+        for (c, &i) in colors.iter() {
+            palette[i as usize* 3..i as usize* 3 + 3].copy_from_slice(c);
+        }
+        let indices = rgba.chunks_exact(4)
+            .map(|pix| if pix[3] == 0 { TRANSPARENT } else { colors[&[pix[0], pix[1], pix[2]]] })
+            .collect::<Vec<u8>>();
+        (palette, indices)
+    }
+    else {
+        let nq = color_quant::NeuQuant::new(10, 255, &opaque);
+        let mut palette = nq.color_map_rgb();
+        palette.resize(256* 3, 0);
+        let indices = rgba.chunks_exact(4)
+            .map(|pix| if pix[3] == 0 { TRANSPARENT } else { nq.index_of(pix) as u8 })
+            .collect::<Vec<u8>>();
+        (palette, indices)
+    };
+    let mut frame = gif::Frame::from_palette_pixels(
+        rect.2 as u16, rect.3 as u16, indices, palette, Some(TRANSPARENT));
+    frame.left = rect.0 as u16;
+    frame.top = rect.1 as u16;
+    frame
+}
+
+/// crc32 of png chunks (name + data)
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn png_crc(parts: &[&[u8]]) -> u32 {
+    !parts.iter().flat_map(|p| p.iter()).fold(!0_u32, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8))
+}
+
+/// offset of acTL chunk data: png signature + IHDR chunk + acTL length and name
+const ACTL_OFFSET: u64 = 8 + 25 + 8;
+
+/// a frame waiting for its duration or disposal to be decided
+struct PendingFrame {
+    rect: Rect,
+    /// pixels of rect
+    data: Vec<u8>,
+    /// full canvas pixels of this frame
+    full: Vec<u8>,
+    duration: u32,
+    dispose_background: bool,
+}
+
+enum Inner {
+    Gif(gif::Encoder<BufWriter<File>>),
+    /// apng chunks are written by hand, so that frame count is known on finish
+    Apng { f: BufWriter<File>, sequence: u32 },
+    Webp(BufWriter<File>),
+    Closed,
+}
+
+pub struct AnimWriter {
+    pub format: AnimFormat,
+    pub width: u32,
+    pub height: u32,
+    rate: f64,
+    num_encoded: u32,
+    /// pixels on display after last frame
+    shown: Vec<u8>,
+    pending: Option<PendingFrame>,
+    inner: Inner,
+}
+
+impl AnimWriter {
+    /// create a writer, frames are encoded one by one and the file is completed by `finish()`
+    pub fn new(path: &str, format: AnimFormat, width: u32, height: u32, rate: f64) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("canvas size is 0x0".into());
+        }
+        if rate <= 0.0 || !rate.is_finite() {
+            return Err(format!("Invalid frame rate: {}", rate));
+        }
+        let f = File::create(path).map_err(|e| e.to_string())?;
+        let mut f = BufWriter::new(f);
+        let inner = match format {
+            AnimFormat::Gif => {
+                if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                    return Err(format!("Image size too large for gif: {}x{}", width, height));
+                }
+                let mut encoder = gif::Encoder::new(f, width as u16, height as u16, &[])
+                    .map_err(|e| e.to_string())?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+                Inner::Gif(encoder)
+            },
+            AnimFormat::Apng => {
+                f.write_all(b"\x89PNG\r\n\x1a\n").map_err(|e| e.to_string())?;
+                let mut ihdr = Vec::with_capacity(13);
+                ihdr.extend_from_slice(&width.to_be_bytes());
+                ihdr.extend_from_slice(&height.to_be_bytes());
+                ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8 bit rgba
+                Self::write_png_chunk(&mut f, b"IHDR", &[&ihdr])?;
+                // number of frames is filled on finish, loop forever
+                Self::write_png_chunk(&mut f, b"acTL", &[&[0; 8]])?;
+                Inner::Apng { f, sequence: 0 }
+            },
+            AnimFormat::Webp => {
+                if width > 16384 || height > 16384 {
+                    return Err(format!("Image size too large for webp: {}x{}", width, height));
+                }
+                // RIFF size is filled on finish
+                f.write_all(b"RIFF\0\0\0\0WEBP").map_err(|e| e.to_string())?;
+                let mut vp8x = [0; 10];
+                vp8x[0] = 0b0001_0010; // alpha, animation
+                vp8x[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+                vp8x[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+                Self::write_chunk(&mut f, b"VP8X", &vp8x)?;
+                // background color, loop count (infinite)
+                Self::write_chunk(&mut f, b"ANIM", &[0, 0, 0, 0, 0, 0])?;
+                Inner::Webp(f)
+            },
+        };
+        Ok(AnimWriter {
+            format,
+            width,
+            height,
+            rate,
+            num_encoded: 0,
+            shown: vec![0; (width* height* 4) as usize],
+            pending: None,
+            inner,
+        })
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        matches!(self.inner, Inner::Closed)
+    }
+
+    fn write_chunk(f: &mut impl Write, name: &[u8; 4], data: &[u8]) -> Result<(), String> {
+        f.write_all(name).map_err(|e| e.to_string())?;
+        f.write_all(&(data.len() as u32).to_le_bytes()).map_err(|e| e.to_string())?;
+        f.write_all(data).map_err(|e| e.to_string())?;
+        if data.len() % 2 == 1 {
+            f.write_all(&[0]).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn write_png_chunk(f: &mut impl Write, name: &[u8; 4], data: &[&[u8]]) -> Result<(), String> {
+        let len = data.iter().map(|d| d.len()).sum::<usize>() as u32;
+        f.write_all(&len.to_be_bytes()).map_err(|e| e.to_string())?;
+        f.write_all(name).map_err(|e| e.to_string())?;
+        data.iter().try_for_each(|d| f.write_all(d)).map_err(|e| e.to_string())?;
+        let crc = png_crc(&[name.as_slice()].into_iter().chain(data.iter().copied()).collect::<Vec<_>>());
+        f.write_all(&crc.to_be_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// duration of frame at index (starts at 0), in time base of format
+    fn frame_duration(&self, index: u32) -> u32 {
+        let base = self.format.time_base();
+        let t = |i: u32| (i as f64 * base / self.rate).round() as u32;
+        // some viewers treat 0 delay as default (~100ms)
+        (t(index + 1) - t(index)).max(1)
+    }
+
+    /// encode one rgba frame of canvas size
+    pub fn encode_frame(&mut self, rgba: &[u8]) -> Result<(), String> {
+        if self.is_closed() {
+            return Err("file is closed".into());
+        }
+        if rgba.len() != (self.width* self.height* 4) as usize {
+            return Err(format!("frame size not match: expect {}x{}", self.width, self.height));
+        }
+        let duration = self.frame_duration(self.num_encoded);
+        self.num_encoded += 1;
+        match self.format {
+            AnimFormat::Gif => self.encode_gif(rgba, duration),
+            AnimFormat::Apng => self.encode_apng(rgba, duration),
+            AnimFormat::Webp => self.encode_webp(rgba, duration),
+        }
+    }
+
+    fn encode_gif(&mut self, rgba: &[u8], duration: u32) -> Result<(), String> {
+        let mut cur = rgba.to_vec();
+        binarize_alpha(&mut cur);
+        if let Some(mut pending) = self.pending.take() {
+            // frames are drawn on previous one, a visible pixel can only be cleared by disposal
+            let need_clear = self.shown.chunks_exact(4).zip(cur.chunks_exact(4))
+                .any(|(p, c)| p[3] != 0 && c[3] == 0);
+            if need_clear {
+                pending.rect = (0, 0, self.width, self.height);
+                pending.data = pending.full.clone();
+                pending.dispose_background = true;
+                self.flush_pending(pending)?;
+                self.shown.fill(0);
+            }
+            else if self.shown == cur {
+                pending.duration += duration;
+                self.pending = Some(pending);
+                return Ok(());
+            }
+            else {
+                self.flush_pending(pending)?;
+            }
+        }
+        let rect = diff_bbox(&self.shown, &cur, self.width, self.height)
+            .unwrap_or((0, 0, 1, 1));
+        let mut data = crop_rgba(&cur, self.width, rect);
+        // unchanged pixels are transparent to keep previous frame
+        let shown = crop_rgba(&self.shown, self.width, rect);
+        for (pix, prev) in data.chunks_exact_mut(4).zip(shown.chunks_exact(4)) {
+            if pix == prev {
+                pix.copy_from_slice(&[0, 0, 0, 0]);
+            }
+        }
+        self.shown.copy_from_slice(&cur);
+        self.pending = Some(PendingFrame {
+            rect, data, full: cur, duration, dispose_background: false
+        });
+        Ok(())
+    }
+
+    fn encode_apng(&mut self, rgba: &[u8], duration: u32) -> Result<(), String> {
+        let rect = if self.num_encoded == 1 {
+            // default image must cover full canvas
+            (0, 0, self.width, self.height)
+        }
+        else {
+            diff_bbox(&self.shown, rgba, self.width, self.height).unwrap_or((0, 0, 1, 1))
+        };
+        let data = crop_rgba(rgba, self.width, rect);
+        self.shown.copy_from_slice(rgba);
+        // compress frame as a standalone png, and take its image data
+        let mut png_bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut png_bytes, rect.2, rect.3);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().and_then(|mut w| w.write_image_data(&data))
+            .map_err(|e| e.to_string())?;
+        let is_first = self.num_encoded == 1;
+        if let Inner::Apng { ref mut f, ref mut sequence } = self.inner {
+            let mut fctl = Vec::with_capacity(26);
+            fctl.extend_from_slice(&sequence.to_be_bytes());
+            [rect.2, rect.3, rect.0, rect.1].iter().for_each(|v| fctl.extend_from_slice(&v.to_be_bytes()));
+            fctl.extend_from_slice(&(duration.min(u16::MAX as u32) as u16).to_be_bytes());
+            fctl.extend_from_slice(&1000_u16.to_be_bytes());
+            fctl.extend_from_slice(&[0, 0]); // dispose none, blend source
+            Self::write_png_chunk(f, b"fcTL", &[&fctl])?;
+            *sequence += 1;
+            let mut i = 8;
+            while i + 8 <= png_bytes.len() {
+                let size = u32::from_be_bytes(png_bytes[i..i+4].try_into().unwrap()) as usize;
+                let end = (i + 12 + size).min(png_bytes.len());
+                if &png_bytes[i+4..i+8] == b"IDAT" {
+                    let idat = &png_bytes[i+8..(i + 8 + size).min(end)];
+                    if is_first {
+                        Self::write_png_chunk(f, b"IDAT", &[idat])?;
+                    }
+                    else {
+                        Self::write_png_chunk(f, b"fdAT", &[&sequence.to_be_bytes(), idat])?;
+                        *sequence += 1;
+                    }
+                }
+                i = end;
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_webp(&mut self, rgba: &[u8], duration: u32) -> Result<(), String> {
+        if let Some(mut pending) = self.pending.take() {
+            if self.shown == rgba {
+                pending.duration += duration;
+                self.pending = Some(pending);
+                return Ok(());
+            }
+            self.flush_pending(pending)?;
+        }
+        let rect = match diff_bbox(&self.shown, rgba, self.width, self.height) {
+            // frame offset must be even
+            Some((x, y, w, h)) if self.num_encoded > 1 => (x & !1, y & !1, w + (x & 1), h + (y & 1)),
+            _ => (0, 0, self.width, self.height),
+        };
+        let data = crop_rgba(rgba, self.width, rect);
+        self.shown.copy_from_slice(rgba);
+        self.pending = Some(PendingFrame {
+            rect, data, full: vec![], duration, dispose_background: false
+        });
+        Ok(())
+    }
+
+    fn flush_pending(&mut self, pending: PendingFrame) -> Result<(), String> {
+        let PendingFrame { rect, data, duration, dispose_background, .. } = pending;
+        match self.inner {
+            Inner::Gif(ref mut encoder) => {
+                let mut frame = quantize_gif_frame(&data, rect);
+                frame.delay = duration.min(u16::MAX as u32) as u16;
+                frame.dispose = if dispose_background {
+                    gif::DisposalMethod::Background
+                }
+                else {
+                    gif::DisposalMethod::Keep
+                };
+                encoder.write_frame(&frame).map_err(|e| e.to_string())
+            },
+            Inner::Webp(ref mut f) => {
+                let mut bytes = Vec::new();
+                image_webp::WebPEncoder::new(&mut bytes)
+                    .encode(&data, rect.2, rect.3, image_webp::ColorType::Rgba8)
+                    .map_err(|e| e.to_string())?;
+                let mut anmf = Vec::with_capacity(bytes.len() + 16);
+                anmf.extend_from_slice(&(rect.0 / 2).to_le_bytes()[..3]);
+                anmf.extend_from_slice(&(rect.1 / 2).to_le_bytes()[..3]);
+                anmf.extend_from_slice(&(rect.2 - 1).to_le_bytes()[..3]);
+                anmf.extend_from_slice(&(rect.3 - 1).to_le_bytes()[..3]);
+                anmf.extend_from_slice(&duration.min(0xFFFFFF).to_le_bytes()[..3]);
+                anmf.push(0b10); // do not blend, do not dispose
+                // skip RIFF header, keep image chunks
+                let mut i = 12;
+                while i + 8 <= bytes.len() {
+                    let size = u32::from_le_bytes(bytes[i+4..i+8].try_into().unwrap()) as usize;
+                    let end = (i + 8 + size + size % 2).min(bytes.len());
+                    if &bytes[i..i+4] != b"VP8X" {
+                        anmf.extend_from_slice(&bytes[i..end]);
+                    }
+                    i = end;
+                }
+                Self::write_chunk(f, b"ANMF", &anmf)
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// flush all frames and close the file
+    pub fn finish(&mut self) -> Result<(), String> {
+        if let Some(pending) = self.pending.take() {
+            self.flush_pending(pending)?;
+        }
+        match std::mem::replace(&mut self.inner, Inner::Closed) {
+            Inner::Gif(encoder) => {
+                encoder.into_inner().map_err(|e| e.to_string())?
+                    .flush().map_err(|e| e.to_string())
+            },
+            Inner::Apng { mut f, .. } => {
+                if self.num_encoded == 0 {
+                    return Err("apng requires at least one frame".into());
+                }
+                Self::write_png_chunk(&mut f, b"IEND", &[])?;
+                // write actual number of frames, stream may be cut short (eg. interrupted)
+                let mut actl = [0; 8];
+                actl[..4].copy_from_slice(&self.num_encoded.to_be_bytes());
+                f.seek(SeekFrom::Start(ACTL_OFFSET)).map_err(|e| e.to_string())?;
+                f.write_all(&actl).map_err(|e| e.to_string())?;
+                f.write_all(&png_crc(&[b"acTL", &actl]).to_be_bytes()).map_err(|e| e.to_string())?;
+                f.flush().map_err(|e| e.to_string())
+            },
+            Inner::Webp(mut f) => {
+                let len = f.stream_position().map_err(|e| e.to_string())?;
+                f.seek(SeekFrom::Start(4)).map_err(|e| e.to_string())?;
+                f.write_all(&(len as u32 - 8).to_le_bytes()).map_err(|e| e.to_string())?;
+                f.flush().map_err(|e| e.to_string())
+            },
+            Inner::Closed => Ok(()),
+        }
+    }
+}
+
+impl FrameSink for AnimWriter {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn encode_frame(&mut self, rgba: &[u8]) -> Result<(), String> {
+        AnimWriter::encode_frame(self, rgba)
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        AnimWriter::finish(self)
+    }
+}
+
+#[test]
+fn check_diff_bbox() {
+    let (w, h) = (7, 5);
+    let prev = vec![0; (w* h* 4) as usize];
+    let mut cur = prev.clone();
+    assert_eq!(diff_bbox(&prev, &cur, w, h), None);
+    cur[((w + 2)* 4) as usize] = 1;
+    cur[((3* w + 5)* 4 + 3) as usize] = 1;
+    assert_eq!(diff_bbox(&prev, &cur, w, h), Some((2, 1, 4, 3)));
+    assert_eq!(crop_rgba(&cur, w, (2, 1, 4, 3)).len(), 4* 3* 4);
+}
+
+#[test]
+fn check_apng_frame_count() {
+    let path = std::env::temp_dir().join(format!("apng-frames-{}.png", std::process::id()));
+    let mut writer = AnimWriter::new(path.to_str().unwrap(), AnimFormat::Apng, 4, 3, 30.0).unwrap();
+    let mut frame = vec![255; 4* 3* 4];
+    writer.encode_frame(&frame).unwrap();
+    frame[0] = 0;
+    writer.encode_frame(&frame).unwrap();
+    writer.finish().unwrap();
+    let decoder = png::Decoder::new(File::open(&path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    assert_eq!(reader.info().animation_control.map(|c| c.num_frames), Some(2));
+    let mut buf = vec![0; reader.output_buffer_size()];
+    for _ in 0..2 {
+        reader.next_frame(&mut buf).unwrap();
+    }
+    assert_eq!(buf[..4], [0, 255, 255, 255]);
+    std::fs::remove_file(path).unwrap();
+}
+
+pub mod lua_animwriter {
+    use super::*;
+    use rlua::prelude::{LuaResult, LuaError};
+    use rlua::{AnyUserData, Table, UserData, UserDataMethods};
+    use crate::image::lua_image::Image;
+    use crate::image::{Resampler, FitMode};
+
+    /// has same interface as FFcore.Encoder
+    pub struct LuaFrameSink<W: FrameSink> {
+        inner: W,
+    }
+
+    impl<W: FrameSink> LuaFrameSink<W> {
+        pub fn new(inner: W) -> Self {
+            LuaFrameSink { inner }
+        }
+    }
+
+    impl<W: FrameSink + Send + 'static> UserData for LuaFrameSink<W> {
+        fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {
+            // encode one canvas, frames must be sent in order
+            _methods.add_method_mut("encode_frame", |_, writer: &mut Self, (img, _index): (AnyUserData, Option<usize>)|{
+                let img = img.borrow::<Image>()?;
+                let (w, h) = writer.inner.size();
+                let bytes = if img.width != w || img.height != h {
+                    img.resize(w, h, Resampler::Lanczos3, FitMode::Exact).to_rgba8()
+                }
+                else {
+                    img.to_rgba8()
+                };
+                writer.inner.encode_frame(&bytes)
+                    .map_err(LuaError::RuntimeError)
+            });
+            // encode one rgba bytes frame
+            _methods.add_method_mut("encode_bytes", |_, writer: &mut Self, bytes: rlua::String|{
+                writer.inner.encode_frame(bytes.as_bytes())
+                    .map_err(LuaError::RuntimeError)
+            });
+            // finish and close file
+            _methods.add_method_mut("wait", |_, writer: &mut Self, ()|{
+                writer.inner.finish()
+                    .map_err(LuaError::RuntimeError)
+            });
+        }
+    }
+
+    /// common writer args: path, and canvas width, height multiplied by scale
+    pub fn get_path_and_size(args: &Table) -> LuaResult<(String, u32, u32)> {
+        let path = args.get::<_, String>("path")
+            .map_err(|_| LuaError::RuntimeError("field `path` must be string".into()))?;
+        let scale = args.get::<_, f64>("scale").unwrap_or(1.0);
+        let width = args.get::<_, u32>("width")?;
+        let height = args.get::<_, u32>("height")?;
+        let width = ((width as f64* scale).round() as u32).max(1);
+        let height = ((height as f64* scale).round() as u32).max(1);
+        Ok((path, width, height))
+    }
+
+    // create a new writer
+    //   path      xxxx/xxxx.gif
+    //   format    gif|apng|webp
+    //   width     canvas width
+    //   height    canvas height
+    //   scale     0.5|1
+    //   rate      30
+    pub fn create_writer(args: Table) -> LuaResult<LuaFrameSink<AnimWriter>> {
+        let (path, width, height) = get_path_and_size(&args)?;
+        let format = args.get::<_, String>("format")?;
+        let format = AnimFormat::from_name(format.as_str())
+            .ok_or_else(|| LuaError::RuntimeError(format!("Unsupported format: {}", format)))?;
+        let rate = args.get::<_, f64>("rate").unwrap_or(30.0);
+        let inner = AnimWriter::new(&path, format, width, height, rate)
+            .map_err(LuaError::RuntimeError)?;
+        Ok(LuaFrameSink::new(inner))
+    }
+}
@@ -139,6 +139,10 @@ pub mod lua_args {
                             .long("fps")
                             .short('r')
                             .help("动图/视频的每秒帧数"),
+                        Arg::new("linear_light")
+                            .long("linear-light")
+                            .action(ArgAction::SetTrue)
+                            .help("在线性色彩空间中混合颜色, 半透明叠加和染色效果更接近游戏内渲染"),
                         Arg::new("frame")
                             .long("frame")
                             .value_name("FRAME")
@@ -151,9 +155,31 @@ pub mod lua_args {
                         Arg::new("format")
                             .long("format")
                             .value_name("FORMAT")
-                            .value_parser(["gif", "mp4", "mov", "png"])
+                            .value_parser(["gif", "mp4", "mov", "apng", "webp", "png", "sheet"])
                             .ignore_case(true)
                             .help("导出格式"),
+                        Arg::new("sheet_format")
+                            .long("sheet-format")
+                            .value_name("FORMAT")
+                            .value_parser(["hash", "array"])
+                            .ignore_case(true)
+                            .help("精灵表json格式, 默认为hash (仅sheet格式)"),
+                        Arg::new("no_trim")
+                            .long("no-trim")
+                            .action(ArgAction::SetTrue)
+                            .help("不裁剪帧的透明边缘 (仅sheet格式)"),
+                        Arg::new("padding")
+                            .long("padding")
+                            .value_name("PIXELS")
+                            .help("帧之间的间距, 默认为1 (仅sheet格式)"),
+                        Arg::new("max_size")
+                            .long("max-size")
+                            .value_name("PIXELS")
+                            .help("精灵表的最大宽高, 默认为8192 (仅sheet格式)"),
+                        Arg::new("pot")
+                            .long("pot")
+                            .action(ArgAction::SetTrue)
+                            .help("精灵表宽高取2的幂 (仅sheet格式)"),
                         Arg::new("output")
                             .value_name("PATH")
                             .long("output")
@@ -168,6 +194,49 @@ pub mod lua_args {
                     
                     .after_help("颜色参数:\n  css格式的颜色值, 例如: red, #f00, rgb(255,255,0), rgba(255,255,0,100), transparent")
                 )
+                .subcommand(clap::Command::new("decompile")
+                    .about("反编译动画压缩包, 生成Spriter工程 (.scml)")
+                    .visible_aliases(["scml"])
+                    .args([
+                        generic_args[0].clone(),
+                        Arg::new("input")
+                            .value_name("ZIP")
+                            .required(true)
+                            .num_args(1..)
+                            .action(ArgAction::Append)
+                            .help("动画压缩包路径, 如果材质在另一个压缩包中, 可以同时指定, 例如: anim.zip build.zip"),
+                        Arg::new("output")
+                            .value_name("PATH")
+                            .long("output")
+                            .short('o')
+                            .help("输出目录, 默认为压缩包所在目录下的同名文件夹"),
+                    ])
+                )
+                .subcommand(clap::Command::new("compile-scml")
+                    .about("编译Spriter工程 (.scml), 生成动画压缩包")
+                    .visible_aliases(["autocompile"])
+                    .args([
+                        generic_args[0].clone(),
+                        Arg::new("input")
+                            .value_name("SCML")
+                            .required(true)
+                            .help("Spriter工程文件路径"),
+                        Arg::new("output")
+                            .value_name("PATH")
+                            .long("output")
+                            .short('o')
+                            .help("输出路径, 默认为工程所在目录下的同名压缩包"),
+                        Arg::new("build")
+                            .value_name("BUILD")
+                            .long("build")
+                            .short('b')
+                            .help("材质名, 默认为工程文件名"),
+                        Arg::new("fps")
+                            .value_name("FPS")
+                            .long("fps")
+                            .help("动画帧率, 默认使用工程中的帧间隔"),
+                    ])
+                )
                 .subcommand(clap::Command::new("install-ffmpeg")
                     .about("安装FFmpeg")
                     .visible_aliases(["ffmpeg"])
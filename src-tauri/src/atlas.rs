@@ -0,0 +1,371 @@
+// texture atlas packer, output is compatible with klei atlas xml
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.w
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.h
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y &&
+        other.right() <= self.right() && other.bottom() <= self.bottom()
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        other.x < self.right() && other.right() > self.x &&
+        other.y < self.bottom() && other.bottom() > self.y
+    }
+}
+
+pub struct PackOptions {
+    /// transparent pixels around each element
+    pub padding: u32,
+    /// force atlas width and height to power of two
+    pub pot: bool,
+    /// max width and height of one atlas
+    pub max_size: u32,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        PackOptions {
+            padding: 1,
+            pot: true,
+            max_size: 2048,
+        }
+    }
+}
+
+/// MaxRects bin, use best short side fit
+struct MaxRects {
+    free: Vec<Rect>,
+}
+
+impl MaxRects {
+    fn new(width: u32, height: u32) -> Self {
+        MaxRects {
+            free: vec![Rect { x: 0, y: 0, w: width, h: height }],
+        }
+    }
+
+    fn insert(&mut self, w: u32, h: u32) -> Option<Rect> {
+        let rect = self.free.iter()
+            .filter(|f| f.w >= w && f.h >= h)
+            .min_by_key(|f| ((f.w - w).min(f.h - h), (f.w - w).max(f.h - h)))
+            .map(|f| Rect { x: f.x, y: f.y, w, h })?;
+        self.place(&rect);
+        Some(rect)
+    }
+
+    fn place(&mut self, used: &Rect) {
+        let mut result = Vec::with_capacity(self.free.len() + 4);
+        for f in self.free.iter() {
+            if !f.intersects(used) {
+                result.push(*f);
+                continue;
+            }
+            if used.x > f.x {
+                result.push(Rect { w: used.x - f.x, ..*f });
+            }
+            if used.right() < f.right() {
+                result.push(Rect { x: used.right(), w: f.right() - used.right(), ..*f });
+            }
+            if used.y > f.y {
+                result.push(Rect { h: used.y - f.y, ..*f });
+            }
+            if used.bottom() < f.bottom() {
+                result.push(Rect { y: used.bottom(), h: f.bottom() - used.bottom(), ..*f });
+            }
+        }
+        // remove free rects contained by others
+        let mut pruned: Vec<Rect> = Vec::with_capacity(result.len());
+        for (i, r) in result.iter().enumerate() {
+            let contained = result.iter().enumerate().any(|(j, other)|
+                i != j && other.contains(r) && (other != r || j < i));
+            if !contained {
+                pruned.push(*r);
+            }
+        }
+        self.free = pruned;
+    }
+}
+
+pub struct PackedAtlas {
+    pub width: u32,
+    pub height: u32,
+    /// input index, element rect (padding excluded)
+    pub elements: Vec<(usize, Rect)>,
+}
+
+/// try to pack items into a bin, return placed items and rest items
+fn pack_bin(sizes: &[(u32, u32)], items: &[usize], width: u32, height: u32, padding: u32)
+    -> (Vec<(usize, Rect)>, Vec<usize>) {
+    let mut bin = MaxRects::new(width, height);
+    let mut placed = vec![];
+    let mut rest = vec![];
+    for &i in items {
+        let (w, h) = sizes[i];
+        match bin.insert(w + padding* 2, h + padding* 2) {
+            Some(r)=> placed.push((i, Rect { x: r.x + padding, y: r.y + padding, w, h })),
+            None=> rest.push(i),
+        }
+    }
+    (placed, rest)
+}
+
+/// pack image sizes into one or more atlases
+pub fn pack(sizes: &[(u32, u32)], options: &PackOptions) -> Result<Vec<PackedAtlas>, String> {
+    let padding = options.padding;
+    let max_size = options.max_size.max(1);
+    for (w, h) in sizes.iter() {
+        if w + padding* 2 > max_size || h + padding* 2 > max_size {
+            return Err(format!("Image size {}x{} exceeds max atlas size {}", w, h, max_size));
+        }
+    }
+    let mut items = (0..sizes.len()).collect::<Vec<_>>();
+    items.sort_by_key(|&i| {
+        let (w, h) = sizes[i];
+        std::cmp::Reverse((w.max(h), w* h))
+    });
+
+    let mut result = vec![];
+    while !items.is_empty() {
+        let area = items.iter()
+            .map(|&i| (sizes[i].0 + padding* 2) as u64 * (sizes[i].1 + padding* 2) as u64)
+            .sum::<u64>();
+        let max_w = items.iter().map(|&i| sizes[i].0 + padding* 2).max().unwrap_or(1);
+        let max_h = items.iter().map(|&i| sizes[i].1 + padding* 2).max().unwrap_or(1);
+        // start from the smallest power of two bin, and grow until all items are packed
+        let side = ((area as f64).sqrt().ceil() as u32).next_power_of_two().min(max_size);
+        let mut width = side.max(max_w.next_power_of_two()).min(max_size);
+        let mut height = (side / 2).max(max_h.next_power_of_two()).max(1).min(max_size);
+        let (placed, rest) = loop {
+            let (placed, rest) = pack_bin(sizes, &items, width, height, padding);
+            if rest.is_empty() || (width >= max_size && height >= max_size) {
+                break (placed, rest);
+            }
+            if height < width && height < max_size {
+                height = (height* 2).min(max_size);
+            }
+            else if width < max_size {
+                width = (width* 2).min(max_size);
+            }
+            else {
+                height = (height* 2).min(max_size);
+            }
+        };
+        if placed.is_empty() {
+            return Err("Failed to pack images".into());
+        }
+        if !options.pot {
+            // shrink to used region, aligned to 4 for block compression (unless exceeds max size)
+            let used_w = placed.iter().map(|(_, r)| r.right() + padding).max().unwrap_or(1);
+            let used_h = placed.iter().map(|(_, r)| r.bottom() + padding).max().unwrap_or(1);
+            width = (used_w.div_ceil(4)* 4).min(max_size);
+            height = (used_h.div_ceil(4)* 4).min(max_size);
+        }
+        result.push(PackedAtlas { width, height, elements: placed });
+        items = rest;
+    }
+    Ok(result)
+}
+
+/// uv of element in atlas, texel center is used so that the rect can be restored by floor()
+pub fn get_uv(rect: &Rect, width: u32, height: u32) -> (f64, f64, f64, f64) {
+    let (width, height) = (width as f64, height as f64);
+    let u1 = (rect.x as f64 + 0.5) / width;
+    let u2 = (rect.right() as f64 - 0.5) / width;
+    let v1 = 1.0 - (rect.bottom() as f64 - 0.5) / height;
+    let v2 = 1.0 - (rect.y as f64 + 0.5) / height;
+    (u1, u2, v1, v2)
+}
+
+/// copy edge pixels of `rect` outwards by `size` pixels, so that texture filtering and
+/// lower mip levels sample the element itself instead of its neighbours
+pub fn extrude(rgba: &mut [u8], width: u32, height: u32, rect: &Rect, size: u32) {
+    if rect.w == 0 || rect.h == 0 {
+        return;
+    }
+    let (width, size) = (width as usize, size as usize);
+    let index = |x: usize, y: usize| (y* width + x)* 4;
+    let (x0, x1) = (rect.x as usize, rect.right() as usize - 1);
+    let (y0, y1) = (rect.y as usize, rect.bottom() as usize - 1);
+    let (left, right) = (x0.saturating_sub(size), (x1 + size).min(width - 1));
+    let (top, bottom) = (y0.saturating_sub(size), (y1 + size).min(height as usize - 1));
+    for y in y0..=y1 {
+        for x in (left..x0).chain(x1 + 1..=right) {
+            let src = index(x.clamp(x0, x1), y);
+            rgba.copy_within(src..src + 4, index(x, y));
+        }
+    }
+    for x in left..=right {
+        for y in (top..y0).chain(y1 + 1..=bottom) {
+            let src = index(x, y.clamp(y0, y1));
+            rgba.copy_within(src..src + 4, index(x, y));
+        }
+    }
+}
+
+pub(crate) fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// generate atlas xml
+pub fn atlas_xml(texname: &str, width: u32, height: u32, elements: &[(&str, Rect)]) -> String {
+    let mut xml = String::with_capacity(128 + elements.len()* 100);
+    xml.push_str("<Atlas>\n");
+    writeln!(xml, "\t<Texture filename=\"{}\" />", escape_xml(texname)).unwrap();
+    xml.push_str("\t<Elements>\n");
+    for (name, rect) in elements {
+        let (u1, u2, v1, v2) = get_uv(rect, width, height);
+        writeln!(xml, "\t\t<Element name=\"{}\" u1=\"{:.8}\" u2=\"{:.8}\" v1=\"{:.8}\" v2=\"{:.8}\" />",
+            escape_xml(name), u1, u2, v1, v2).unwrap();
+    }
+    xml.push_str("\t</Elements>\n");
+    xml.push_str("</Atlas>\n");
+    xml
+}
+
+#[test]
+fn check_pack() {
+    let sizes = (0..60).map(|i| (10 + i* 7 % 50, 5 + i* 13 % 40)).collect::<Vec<_>>();
+    let options = PackOptions { padding: 2, pot: true, max_size: 128 };
+    let atlases = pack(&sizes, &options).unwrap();
+    assert!(atlases.len() > 1);
+    assert_eq!(atlases.iter().map(|a| a.elements.len()).sum::<usize>(), sizes.len());
+    for atlas in atlases.iter() {
+        assert!(atlas.width.is_power_of_two() && atlas.height.is_power_of_two());
+        for (n, (i, r)) in atlas.elements.iter().enumerate() {
+            assert_eq!((r.w, r.h), sizes[*i]);
+            assert!(r.x >= 2 && r.y >= 2 && r.right() + 2 <= atlas.width && r.bottom() + 2 <= atlas.height);
+            for (_, other) in atlas.elements[n+1..].iter() {
+                assert!(!r.intersects(other));
+            }
+            // same rule as Provider:GetImage()
+            let (u1, u2, v1, v2) = get_uv(r, atlas.width, atlas.height);
+            let (w, h) = (atlas.width as f64, atlas.height as f64);
+            assert_eq!((w* u1).floor() as u32, r.x);
+            assert_eq!((h* (1.0 - v2)).floor() as u32, r.y);
+            assert_eq!((w* u2).floor() as u32 + 1, r.right());
+            assert_eq!((h* (1.0 - v1)).floor() as u32 + 1, r.bottom());
+        }
+    }
+    // alignment never exceeds max size
+    let atlases = pack(&[(28, 9)], &PackOptions { padding: 1, pot: false, max_size: 30 }).unwrap();
+    assert_eq!((atlases[0].width, atlases[0].height), (30, 12));
+}
+
+pub mod lua_atlas {
+    use super::*;
+    use rlua::prelude::{LuaContext, LuaError, LuaResult};
+    use rlua::{AnyUserData, Table, Value};
+    use crate::filesystem::lua_filesystem::ConvertArgToString;
+    use crate::image::lua_image::Image;
+
+    /// pack images into atlases
+    ///   images:  { [name]: Image }
+    ///   options:
+    ///     padding    default 1
+    ///     pot        default true
+    ///     max_size   default 2048
+    ///     texname    default "atlas", used as `<texname>.tex` or `<texname>-<i>.tex`
+    ///     output     (optional) directory to write xml and tex files
+    ///     tex        (optional) encode options of tex writer, see `Image:save_tex()`
+    /// return { { texname, xmlname, xml, img, elements = { [name]: {x, y, w, h} } } }
+    pub fn pack_atlas<'lua>(lua: LuaContext<'lua>, images: Table<'lua>, options: Option<Table<'lua>>) -> LuaResult<Table<'lua>> {
+        let mut pack_options = PackOptions::default();
+        let mut texname = "atlas".to_string();
+        let mut output = None;
+        let mut tex_options = None;
+        if let Some(options) = options {
+            pack_options.padding = options.get::<_, Option<u32>>("padding")?.unwrap_or(pack_options.padding);
+            pack_options.pot = options.get::<_, Option<bool>>("pot")?.unwrap_or(pack_options.pot);
+            pack_options.max_size = options.get::<_, Option<u32>>("max_size")?.unwrap_or(pack_options.max_size);
+            texname = options.get::<_, Option<String>>("texname")?.unwrap_or(texname);
+            output = match options.get::<_, Value>("output")? {
+                Value::Nil => None,
+                v => Some(std::path::PathBuf::from(v.to_string()?)),
+            };
+            tex_options = options.get::<_, Option<Table>>("tex")?;
+        }
+        let tex_options = crate::ktex::lua_ktex::get_encode_options(tex_options)?;
+        let texname = texname.trim_end_matches(".tex").to_string();
+
+        let mut names = vec![];
+        let mut imgs = vec![];
+        for pair in images.pairs::<String, AnyUserData>() {
+            let (name, img) = pair?;
+            names.push(name);
+            imgs.push(img);
+        }
+        // make output stable
+        let mut order = (0..names.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| names[*a].cmp(&names[*b]));
+        let names = order.iter().map(|&i| names[i].clone()).collect::<Vec<_>>();
+        let imgs = order.iter().map(|&i| imgs[i].clone()).collect::<Vec<_>>();
+        let mut sizes = Vec::with_capacity(imgs.len());
+        for img in imgs.iter() {
+            let img = img.borrow::<Image>()?;
+            sizes.push((img.width, img.height));
+        }
+
+        let atlases = pack(&sizes, &pack_options).map_err(LuaError::RuntimeError)?;
+        let result = lua.create_table()?;
+        for (index, atlas) in atlases.iter().enumerate() {
+            let name = if atlases.len() == 1 { texname.clone() } else { format!("{}-{}", texname, index) };
+            let mut canvas = Image::from_rgba(vec![0; (atlas.width* atlas.height* 4) as usize], atlas.width, atlas.height)
+                .ok_or(LuaError::RuntimeError("Failed to create atlas canvas".into()))?;
+            let elements = lua.create_table()?;
+            let mut xml_elements = Vec::with_capacity(atlas.elements.len());
+            for (i, rect) in atlas.elements.iter() {
+                canvas.copy_from(&*imgs[*i].borrow::<Image>()?, rect.x, rect.y);
+                let e = lua.create_table()?;
+                e.set("x", rect.x)?;
+                e.set("y", rect.y)?;
+                e.set("w", rect.w)?;
+                e.set("h", rect.h)?;
+                elements.set(names[*i].as_str(), e)?;
+                xml_elements.push((names[*i].as_str(), *rect));
+            }
+            // tex has mip levels, fill padding with extruded edges instead of transparent black
+            let mut bytes = canvas.to_rgba8();
+            for (_, rect) in atlas.elements.iter() {
+                extrude(&mut bytes, atlas.width, atlas.height, rect, pack_options.padding);
+            }
+            let canvas = Image::from_rgba(bytes, atlas.width, atlas.height)
+                .ok_or(LuaError::RuntimeError("Failed to create atlas canvas".into()))?;
+            let texfile = format!("{}.tex", name);
+            let xmlfile = format!("{}.xml", name);
+            let xml = atlas_xml(&texfile, atlas.width, atlas.height, &xml_elements);
+            if let Some(dir) = output.as_ref() {
+                let tex = canvas.save_tex_bytes(&tex_options).map_err(LuaError::RuntimeError)?;
+                std::fs::write(dir.join(&texfile), tex)
+                    .and_then(|_| std::fs::write(dir.join(&xmlfile), xml.as_bytes()))
+                    .map_err(|e| LuaError::RuntimeError(format!("Failed to write atlas: {}", e)))?;
+            }
+            let t = lua.create_table()?;
+            t.set("texname", texfile)?;
+            t.set("xmlname", xmlfile)?;
+            t.set("xml", xml)?;
+            t.set("img", canvas)?;
+            t.set("elements", elements)?;
+            result.set(index + 1, t)?;
+        }
+        Ok(result)
+    }
+}
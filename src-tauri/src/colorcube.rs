@@ -0,0 +1,260 @@
+// 3D color lookup table, used by game colour cube (images/colour_cubes/*.tex) and .cube file
+use std::fmt::Write;
+
+use crate::ktex::KTex;
+use crate::image::{ColorSpace, srgb_to_linear, linear_to_srgb};
+
+#[derive(Debug, Clone)]
+pub struct ColorCube {
+    pub size: usize,
+    /// rgb in 0-1, red changes fastest, then green, then blue (same as .cube)
+    data: Vec<[f64; 3]>,
+}
+
+impl ColorCube {
+    /// build from game colour cube pixels, layout is (size*size) x size,
+    /// x = b * size + r, y = g
+    pub fn from_cc_bytes(bytes: &[u8], channels: usize, size: usize) -> Result<Self, String> {
+        if size < 2 {
+            return Err(format!("Invalid cube size: {}", size));
+        }
+        if bytes.len() < size* size* size* channels {
+            return Err(format!("cc must contain {} pixels", size* size* size));
+        }
+        let mut data = vec![[0.0; 3]; size* size* size];
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let i = (g* size* size + b* size + r)* channels;
+                    data[r + g* size + b* size* size] = [
+                        bytes[i] as f64 / 255.0,
+                        bytes[i + 1] as f64 / 255.0,
+                        bytes[i + 2] as f64 / 255.0,
+                    ];
+                }
+            }
+        }
+        Ok(ColorCube { size, data })
+    }
+
+    /// convert to game colour cube rgb pixels, (size*size) x size
+    pub fn to_cc_bytes(&self) -> Vec<u8> {
+        let size = self.size;
+        let mut bytes = vec![0; size* size* size* 3];
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let i = (g* size* size + b* size + r)* 3;
+                    let c = self.data[r + g* size + b* size* size];
+                    for k in 0..3 {
+                        bytes[i + k] = f64::clamp((c[k]* 255.0).round(), 0.0, 255.0) as u8;
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    /// load from colour cube texture (32x32x32 for game)
+    pub fn from_ktex(tex: &KTex) -> Result<Self, String> {
+        let mip = tex.mipmaps.first().ok_or("Texture has no mipmap")?;
+        let (width, height) = (mip.width as usize, mip.height as usize);
+        if width != height* height {
+            return Err(format!("Invalid colour cube size: {}x{}", width, height));
+        }
+        let bytes = tex.decode(0, true, false)?;
+        let channels = bytes.len() / (width* height);
+        Self::from_cc_bytes(&bytes, channels, height)
+    }
+
+    /// parse .cube (Adobe/Resolve) 3D LUT
+    pub fn from_cube_str(s: &str) -> Result<Self, String> {
+        let mut size = 0;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut data = vec![];
+        let parse_vec3 = |words: &[&str], line: usize| -> Result<[f64; 3], String> {
+            if words.len() != 3 {
+                return Err(format!("Invalid line {}: expect 3 values", line));
+            }
+            let mut v = [0.0; 3];
+            for (i, w) in words.iter().enumerate() {
+                v[i] = w.parse::<f64>().map_err(|_| format!("Invalid number at line {}: {}", line, w))?;
+            }
+            Ok(v)
+        };
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words[0] {
+                "TITLE" => (),
+                "LUT_1D_SIZE" => return Err("1D LUT is not supported".into()),
+                "LUT_3D_SIZE" => {
+                    size = words.get(1).and_then(|w| w.parse::<usize>().ok())
+                        .filter(|&n| (2..=256).contains(&n))
+                        .ok_or(format!("Invalid LUT_3D_SIZE at line {}", i + 1))?;
+                },
+                "DOMAIN_MIN" => domain_min = parse_vec3(&words[1..], i + 1)?,
+                "DOMAIN_MAX" => domain_max = parse_vec3(&words[1..], i + 1)?,
+                _ => data.push(parse_vec3(&words, i + 1)?),
+            }
+        }
+        if size == 0 {
+            return Err("LUT_3D_SIZE not found".into());
+        }
+        if data.len() != size* size* size {
+            return Err(format!("Expect {} entries, got {}", size* size* size, data.len()));
+        }
+        // normalize to 0-1
+        for c in data.iter_mut() {
+            for k in 0..3 {
+                let range = domain_max[k] - domain_min[k];
+                if range > 0.0 {
+                    c[k] = (c[k] - domain_min[k]) / range;
+                }
+            }
+        }
+        Ok(ColorCube { size, data })
+    }
+
+    pub fn to_cube_string(&self, title: &str) -> String {
+        let mut s = String::new();
+        if !title.is_empty() {
+            writeln!(s, "TITLE \"{}\"", title.replace('"', "")).unwrap();
+        }
+        writeln!(s, "LUT_3D_SIZE {}", self.size).unwrap();
+        for c in self.data.iter() {
+            writeln!(s, "{:.6} {:.6} {:.6}", c[0], c[1], c[2]).unwrap();
+        }
+        s
+    }
+
+    /// trilinear sample, rgb in 0-1
+    pub fn sample(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let n = self.size;
+        let max = (n - 1) as f64;
+        let mut lo = [0; 3];
+        let mut hi = [0; 3];
+        let mut t = [0.0; 3];
+        for k in 0..3 {
+            let f = f64::clamp(rgb[k], 0.0, 1.0)* max;
+            lo[k] = f64::floor(f) as usize;
+            hi[k] = (lo[k] + 1).min(n - 1);
+            t[k] = f - lo[k] as f64;
+        }
+        let get = |r: usize, g: usize, b: usize| self.data[r + g* n + b* n* n];
+        let lerp = |a: [f64; 3], b: [f64; 3], t: f64| [
+            a[0] + (b[0] - a[0])* t,
+            a[1] + (b[1] - a[1])* t,
+            a[2] + (b[2] - a[2])* t,
+        ];
+        let c00 = lerp(get(lo[0], lo[1], lo[2]), get(hi[0], lo[1], lo[2]), t[0]);
+        let c10 = lerp(get(lo[0], hi[1], lo[2]), get(hi[0], hi[1], lo[2]), t[0]);
+        let c01 = lerp(get(lo[0], lo[1], hi[2]), get(hi[0], lo[1], hi[2]), t[0]);
+        let c11 = lerp(get(lo[0], hi[1], hi[2]), get(hi[0], hi[1], hi[2]), t[0]);
+        lerp(lerp(c00, c10, t[1]), lerp(c01, c11, t[1]), t[2])
+    }
+
+    /// apply on rgb/rgba pixels, `percent` blends between source and graded color,
+    /// the cube is always indexed by sRGB color, but blending can be done in linear light
+    pub fn apply(&self, bytes: &mut [u8], channels: usize, percent: f64, colorspace: ColorSpace) {
+        for pixel in bytes.chunks_exact_mut(channels) {
+            let c = self.sample([
+                pixel[0] as f64 / 255.0,
+                pixel[1] as f64 / 255.0,
+                pixel[2] as f64 / 255.0,
+            ]);
+            for k in 0..3 {
+                pixel[k] = match colorspace {
+                    ColorSpace::Srgb => f64::clamp(
+                        percent* c[k]* 255.0 + (1.0 - percent)* pixel[k] as f64,
+                        0.0, 255.0).round() as u8,
+                    ColorSpace::Linear => {
+                        let graded = srgb_to_linear(f64::clamp(c[k]* 255.0, 0.0, 255.0).round() as u8);
+                        linear_to_srgb((percent* graded as f64 + (1.0 - percent)* srgb_to_linear(pixel[k]) as f64) as f32)
+                    },
+                };
+            }
+        }
+    }
+}
+
+#[test]
+fn check_cube() {
+    // identity cube
+    let size = 4;
+    let mut bytes = vec![];
+    for g in 0..size {
+        for b in 0..size {
+            for r in 0..size {
+                bytes.extend([r* 85, g* 85, b* 85].map(|v| v as u8));
+            }
+        }
+    }
+    let cube = ColorCube::from_cc_bytes(&bytes, 3, size).unwrap();
+    assert_eq!(cube.to_cc_bytes(), bytes);
+    let mut pixels = vec![10, 100, 200, 255, 30, 0, 77, 0];
+    let source = pixels.clone();
+    cube.apply(&mut pixels, 4, 1.0, ColorSpace::Srgb);
+    assert_eq!(pixels, source);
+    let cube2 = ColorCube::from_cube_str(&cube.to_cube_string("test")).unwrap();
+    assert_eq!(cube2.to_cc_bytes(), bytes);
+}
+
+pub mod lua_colorcube {
+    use super::*;
+    use rlua::prelude::{LuaResult, LuaError};
+    use rlua::{UserData, UserDataMethods, Value};
+    use crate::image::lua_image::Image;
+
+    impl UserData for ColorCube {
+        fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {
+            _methods.add_method("size", |_, cube: &Self, ()|{
+                Ok(cube.size)
+            });
+            // export to game colour cube layout
+            _methods.add_method("to_bytes", |lua, cube: &Self, ()|{
+                lua.create_string(&cube.to_cc_bytes())
+            });
+            _methods.add_method("image", |_, cube: &Self, ()|{
+                Ok(Image::from_rgb(cube.to_cc_bytes(), (cube.size* cube.size) as u32, cube.size as u32))
+            });
+            _methods.add_method("to_cube", |_, cube: &Self, title: Option<String>|{
+                Ok(cube.to_cube_string(title.as_deref().unwrap_or("")))
+            });
+            _methods.add_method("save_cube", |_, cube: &Self, (path, title): (Value, Option<String>)|{
+                use crate::filesystem::lua_filesystem::ConvertArgToString;
+                std::fs::write(path.to_string()?, cube.to_cube_string(title.as_deref().unwrap_or("")))
+                    .map_err(|e| LuaError::RuntimeError(e.to_string()))
+            });
+        }
+    }
+
+    /// load colour cube from texture path or KTex userdata
+    pub fn from_tex(tex: Value) -> LuaResult<ColorCube> {
+        use crate::filesystem::lua_filesystem::ConvertArgToString;
+        let cube = match tex {
+            Value::UserData(ref data) if data.is::<KTex>() => {
+                ColorCube::from_ktex(&*data.borrow::<KTex>()?)
+            },
+            _ => KTex::open(tex.to_string()?.as_str())
+                .map_err(|e| e.to_string())
+                .and_then(|tex| ColorCube::from_ktex(&tex)),
+        };
+        cube.map_err(LuaError::RuntimeError)
+    }
+
+    /// parse .cube file content
+    pub fn from_cube(s: &str) -> LuaResult<ColorCube> {
+        ColorCube::from_cube_str(s).map_err(LuaError::RuntimeError)
+    }
+
+    /// load from game colour cube rgb bytes, (size*size) x size
+    pub fn from_bytes(bytes: &[u8], size: Option<usize>) -> LuaResult<ColorCube> {
+        ColorCube::from_cc_bytes(bytes, 3, size.unwrap_or(32))
+            .map_err(LuaError::RuntimeError)
+    }
+}
@@ -0,0 +1,226 @@
+// reader and writer for DirectDraw Surface (*.dds), BC1-BC3 and uncompressed rgb(a)
+use crate::algorithm::lua_algorithm::{dxt1_decompress, dxt3_decompress, dxt5_decompress, div_alpha_mut};
+use crate::algorithm::lua_algorithm::{dxt1_compress, dxt5_compress};
+use crate::ktex::{half_size, PixelFormat};
+
+const HEADER_SIZE: usize = 128;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+pub fn is_dds(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"DDS ")
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// expand masked channel to 8 bits, use `default` if mask is empty
+fn unpack_channel(v: u32, mask: u32, default: u8) -> u8 {
+    if mask == 0 {
+        return default;
+    }
+    let max = mask >> mask.trailing_zeros();
+    (((v & mask) >> mask.trailing_zeros()) as u64* 255 / max as u64) as u8
+}
+
+/// decode top mipmap of dds file, return (rgba, width, height)
+pub fn decode(bytes: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    if !is_dds(bytes) || bytes.len() < HEADER_SIZE {
+        return Err("Invalid dds file".into());
+    }
+    let height = read_u32(bytes, 12) as usize;
+    let width = read_u32(bytes, 16) as usize;
+    let pf_flags = read_u32(bytes, 80);
+    let fourcc = &bytes[84..88];
+    let bit_count = read_u32(bytes, 88) as usize;
+    let masks = [read_u32(bytes, 92), read_u32(bytes, 96), read_u32(bytes, 100), read_u32(bytes, 104)];
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid dds size: {}x{}", width, height));
+    }
+
+    // (block encoding, premultiplied) or uncompressed masks
+    let (bc, premultiplied, masks, bit_count, offset) = if pf_flags & DDPF_FOURCC != 0 {
+        match fourcc {
+            b"DXT1" => (Some(PixelFormat::Dxt1), false, masks, bit_count, HEADER_SIZE),
+            b"DXT2" => (Some(PixelFormat::Dxt3), true, masks, bit_count, HEADER_SIZE),
+            b"DXT3" => (Some(PixelFormat::Dxt3), false, masks, bit_count, HEADER_SIZE),
+            b"DXT4" => (Some(PixelFormat::Dxt5), true, masks, bit_count, HEADER_SIZE),
+            b"DXT5" => (Some(PixelFormat::Dxt5), false, masks, bit_count, HEADER_SIZE),
+            b"DX10" => {
+                if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+                    return Err("Invalid dds file".into());
+                }
+                let offset = HEADER_SIZE + DX10_HEADER_SIZE;
+                match read_u32(bytes, HEADER_SIZE) {
+                    71 | 72 => (Some(PixelFormat::Dxt1), false, masks, 0, offset),
+                    74 | 75 => (Some(PixelFormat::Dxt3), false, masks, 0, offset),
+                    77 | 78 => (Some(PixelFormat::Dxt5), false, masks, 0, offset),
+                    28 | 29 => (None, false, [0xFF, 0xFF00, 0xFF0000, 0xFF000000], 32, offset),
+                    87 | 91 => (None, false, [0xFF0000, 0xFF00, 0xFF, 0xFF000000], 32, offset),
+                    88 | 93 => (None, false, [0xFF0000, 0xFF00, 0xFF, 0], 32, offset),
+                    other => return Err(format!("Unsupported dxgi format: {}", other)),
+                }
+            },
+            other => return Err(format!("Unsupported dds fourcc: {}", String::from_utf8_lossy(other))),
+        }
+    }
+    else if pf_flags & DDPF_RGB != 0 && matches!(bit_count, 8 | 16 | 24 | 32) {
+        let mut masks = masks;
+        if pf_flags & DDPF_ALPHAPIXELS == 0 {
+            masks[3] = 0;
+        }
+        (None, false, masks, bit_count, HEADER_SIZE)
+    }
+    else {
+        return Err(format!("Unsupported dds pixel format, flags: {:#x}", pf_flags));
+    };
+
+    let data = &bytes[offset..];
+    // bc decoder writes whole blocks, so decode in padded size and crop
+    let decode_bc = |decompress: fn(&[u8], usize, usize) -> Vec<u8>, block_size: usize| {
+        let (pw, ph) = (width.div_ceil(4)* 4, height.div_ceil(4)* 4);
+        let len = pw* ph / 16* block_size;
+        if data.len() < len {
+            return Err("Unexpected end of dds data".to_string());
+        }
+        let bytes = decompress(&data[..len], pw, ph);
+        if bytes.len() != pw* ph* 4 {
+            return Err("Failed to decompress dds data".into());
+        }
+        Ok(bytes.chunks_exact(pw* 4)
+            .take(height)
+            .flat_map(|row|&row[..width* 4])
+            .copied()
+            .collect::<Vec<_>>())
+    };
+    let mut rgba = match bc {
+        Some(PixelFormat::Dxt1) => decode_bc(dxt1_decompress, 8)?,
+        Some(PixelFormat::Dxt3) => decode_bc(dxt3_decompress, 16)?,
+        Some(_) => decode_bc(dxt5_decompress, 16)?,
+        None => {
+            let pixel_size = bit_count / 8;
+            let pitch = width* pixel_size;
+            if data.len() < pitch* height {
+                return Err("Unexpected end of dds data".into());
+            }
+            let mut rgba = Vec::with_capacity(width* height* 4);
+            for pixel in data[..pitch* height].chunks_exact(pixel_size) {
+                let v = pixel.iter().rev().fold(0, |v, b| (v << 8) | *b as u32);
+                rgba.extend_from_slice(&[
+                    unpack_channel(v, masks[0], 0),
+                    unpack_channel(v, masks[1], 0),
+                    unpack_channel(v, masks[2], 0),
+                    unpack_channel(v, masks[3], 255),
+                ]);
+            }
+            rgba
+        },
+    };
+    if premultiplied {
+        div_alpha_mut(&mut rgba);
+    }
+    Ok((rgba, width as u32, height as u32))
+}
+
+/// encode rgba bytes to dds file, supported formats: DXT1, DXT5, ARGB (rgba8), RGB
+pub fn encode(bytes: &[u8], width: u32, height: u32, pixel_format: PixelFormat, mipmap: bool) -> Result<Vec<u8>, String> {
+    let (width, height) = (width as usize, height as usize);
+    if bytes.len() != width* height* 4 {
+        return Err(format!("Invalid rgba bytes length: {}, expected {}x{}x4", bytes.len(), width, height));
+    }
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid texture size: {}x{}", width, height));
+    }
+    let compressed = match pixel_format {
+        PixelFormat::Dxt1 | PixelFormat::Dxt5 => true,
+        PixelFormat::Argb | PixelFormat::Rgb => false,
+        other => return Err(format!("Unsupported pixelformat for encoding: {}", other.as_str())),
+    };
+
+    let mut mipmaps = vec![];
+    let mut level = (bytes.to_vec(), width, height);
+    loop {
+        let (data, w, h) = &level;
+        mipmaps.push(match pixel_format {
+            PixelFormat::Dxt1 => dxt1_compress(data, *w, *h),
+            PixelFormat::Dxt5 => dxt5_compress(data, *w, *h),
+            PixelFormat::Rgb => data.chunks_exact(4).flat_map(|c|[c[0], c[1], c[2]]).collect(),
+            _ => data.clone(),
+        });
+        if !mipmap || (*w == 1 && *h == 1) {
+            break;
+        }
+        level = half_size(data, *w, *h);
+    }
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+    flags |= if compressed { DDSD_LINEARSIZE } else { DDSD_PITCH };
+    let mut caps = DDSCAPS_TEXTURE;
+    if mipmaps.len() > 1 {
+        flags |= DDSD_MIPMAPCOUNT;
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    let pitch_or_linear_size = if compressed { mipmaps[0].len() } else { width* pixel_format.pixel_size() };
+    let (pf_flags, fourcc, bit_count, masks) = match pixel_format {
+        PixelFormat::Dxt1 => (DDPF_FOURCC, *b"DXT1", 0, [0; 4]),
+        PixelFormat::Dxt5 => (DDPF_FOURCC, *b"DXT5", 0, [0; 4]),
+        PixelFormat::Rgb => (DDPF_RGB, [0; 4], 24, [0xFF, 0xFF00, 0xFF0000, 0]),
+        _ => (DDPF_RGB | DDPF_ALPHAPIXELS, [0; 4], 32, [0xFF, 0xFF00, 0xFF0000, 0xFF000000]),
+    };
+
+    let mut result = Vec::with_capacity(HEADER_SIZE + mipmaps.iter().map(|m| m.len()).sum::<usize>());
+    result.extend_from_slice(b"DDS ");
+    for v in [124, flags, height as u32, width as u32, pitch_or_linear_size as u32, 0, mipmaps.len() as u32] {
+        result.extend_from_slice(&u32::to_le_bytes(v));
+    }
+    result.extend_from_slice(&[0; 44]); // reserved
+    for v in [32, pf_flags, u32::from_le_bytes(fourcc), bit_count] {
+        result.extend_from_slice(&u32::to_le_bytes(v));
+    }
+    for v in masks {
+        result.extend_from_slice(&u32::to_le_bytes(v));
+    }
+    for v in [caps, 0, 0, 0, 0] {
+        result.extend_from_slice(&u32::to_le_bytes(v));
+    }
+    debug_assert_eq!(result.len(), HEADER_SIZE);
+    for m in mipmaps {
+        result.extend_from_slice(&m);
+    }
+    Ok(result)
+}
+
+#[test]
+fn check_dds() {
+    let (width, height) = (13, 6);
+    let bytes = (0..width* height).flat_map(|i| if i % 3 == 0 { [0, 0, 0, 0] } else { [200, 100, 50, 255] }).collect::<Vec<u8>>();
+    for format in ["DXT1", "DXT5", "RGB", "ARGB"] {
+        let file = encode(&bytes, width, height, PixelFormat::from_name(format), true).unwrap();
+        let (decoded, w, h) = decode(&file).unwrap();
+        assert_eq!((w, h), (width, height));
+        for (i, c) in decoded.chunks_exact(4).enumerate() {
+            if i % 3 != 0 {
+                assert!(c[0].abs_diff(200) < 8 && c[1].abs_diff(100) < 8 && c[2].abs_diff(50) < 8);
+            }
+            else if format != "RGB" {
+                assert_eq!(c[3], 0);
+            }
+        }
+    }
+}
@@ -9,11 +9,11 @@ use log::{error, warn, info};
 
 type AnimIndex = Vec<(String, u32, u8)>;
 type BuildIndex = (String, u32, (f32, f32, f32, f32));
-type HashTable = HashMap<u32, Vec<u8>>;
+pub(crate) type HashTable = HashMap<u32, Vec<u8>>;
 
 const SWAP_ICON: u32 = 4138393349;
 
-fn load_anim_zip<R>(f: R) -> Result<(Option<AnimIndex>, Option<BuildIndex>, HashTable), String> 
+pub(crate) fn load_anim_zip<R>(f: R) -> Result<(Option<AnimIndex>, Option<BuildIndex>, HashTable), String> 
 where R: Read + Seek {
     let mut archive = ZipArchive::new(f).map_err(|e| format!("Failed to read zip file: {}", e))?;
     let mut anim_index = None;
@@ -58,7 +58,7 @@ fn parse_hash_table_impl(mut f: impl Read, hash_table: &mut HashTable) -> Result
 
 /// collect hash table at the tail of file.
 /// this function skip parsing error
-fn parse_hash_table(mut f: impl Read) -> HashTable {
+pub(crate) fn parse_hash_table(mut f: impl Read) -> HashTable {
     let mut hash_table = HashTable::new();
     let failed = parse_hash_table_impl(f, &mut hash_table).is_err();
     if failed {
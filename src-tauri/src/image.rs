@@ -11,10 +11,35 @@ use log::info;
 // use std::os::windows::io::{RawHandle, AsRawHandle, OwnedHandle, FromRawHandle};
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Resampler {
     Nearest = 0,
     Bilinear = 1,
+    Bicubic = 2,
+    Lanczos3 = 3,
+}
+
+impl Resampler {
+    /// max distance (in source pixels) that a sampled point can reach
+    fn radius(&self) -> f64 {
+        match self {
+            Resampler::Nearest | Resampler::Bilinear => 1.0,
+            Resampler::Bicubic => 2.0,
+            Resampler::Lanczos3 => 3.0,
+        }
+    }
+}
+
+impl From<Resampler> for image::imageops::FilterType {
+    fn from(resampler: Resampler) -> Self {
+        use image::imageops::FilterType;
+        match resampler {
+            Resampler::Nearest => FilterType::Nearest,
+            Resampler::Bilinear => FilterType::Triangle,
+            Resampler::Bicubic => FilterType::CatmullRom,
+            Resampler::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
 }
 
 impl<'lua> FromLua<'lua> for Resampler {
@@ -22,16 +47,118 @@ impl<'lua> FromLua<'lua> for Resampler {
         match u8::from_lua(lua_value, lua) {
             Ok(0)=> Ok(Resampler::Nearest),
             Ok(1)=> Ok(Resampler::Bilinear),
+            Ok(2)=> Ok(Resampler::Bicubic),
+            Ok(3)=> Ok(Resampler::Lanczos3),
             Ok(_)=> Ok(Resampler::Nearest),
             Err(e)=> Err(e)
         }
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+    /// interpolate straight rgba channels independently, transparent pixels bleed their colors into edges
+    Straight = 0,
+    /// interpolate premultiplied rgba (colors are weighted by alpha) and divide alpha after sampling
+    Premultiplied = 1,
+}
+
+impl<'lua> FromLua<'lua> for AlphaMode {
+    fn from_lua(lua_value: Value<'lua>, lua: Context<'lua>) -> LuaResult<Self> {
+        match u8::from_lua(lua_value, lua) {
+            Ok(0)=> Ok(AlphaMode::Straight),
+            Ok(1)=> Ok(AlphaMode::Premultiplied),
+            Ok(_)=> Err(LuaError::FromLuaConversionError {
+                from: "(lua)",
+                to: "AlphaMode",
+                message: Some("AlphaMode must be Image.ALPHA_STRAIGHT or Image.ALPHA_PREMULTIPLIED".to_string())
+            }),
+            Err(e)=> Err(e)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// blend sRGB-encoded bytes directly
+    Srgb = 0,
+    /// decode to linear light before blending, and encode back to sRGB
+    Linear = 1,
+}
+
+impl<'lua> FromLua<'lua> for ColorSpace {
+    fn from_lua(lua_value: Value<'lua>, lua: Context<'lua>) -> LuaResult<Self> {
+        match u8::from_lua(lua_value, lua) {
+            Ok(0)=> Ok(ColorSpace::Srgb),
+            Ok(1)=> Ok(ColorSpace::Linear),
+            Ok(_)=> Err(LuaError::FromLuaConversionError {
+                from: "(lua)",
+                to: "ColorSpace",
+                message: Some("ColorSpace must be Image.COLORSPACE_SRGB or Image.COLORSPACE_LINEAR".to_string())
+            }),
+            Err(e)=> Err(e)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Normal = 0,
+    Additive = 1,
+    Multiply = 2,
+    Screen = 3,
+}
+
+impl<'lua> FromLua<'lua> for BlendMode {
+    fn from_lua(lua_value: Value<'lua>, lua: Context<'lua>) -> LuaResult<Self> {
+        match u8::from_lua(lua_value, lua) {
+            Ok(0)=> Ok(BlendMode::Normal),
+            Ok(1)=> Ok(BlendMode::Additive),
+            Ok(2)=> Ok(BlendMode::Multiply),
+            Ok(3)=> Ok(BlendMode::Screen),
+            Ok(_)=> Err(LuaError::FromLuaConversionError {
+                from: "(lua)",
+                to: "BlendMode",
+                message: Some("BlendMode must be one of Image.BLEND_NORMAL|ADDITIVE|MULTIPLY|SCREEN".to_string())
+            }),
+            Err(e)=> Err(e)
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FitMode {
+    /// keep aspect ratio, fit inside width x height
+    Contain = 0,
+    /// keep aspect ratio, fill width x height and crop the overflow
+    Cover = 1,
+    /// stretch to width x height
+    Exact = 2,
+    /// keep aspect ratio, scale longer side to width
+    MaxSide = 3,
+}
+
+impl<'lua> FromLua<'lua> for FitMode {
+    fn from_lua(lua_value: Value<'lua>, lua: Context<'lua>) -> LuaResult<Self> {
+        match u8::from_lua(lua_value, lua) {
+            Ok(0)=> Ok(FitMode::Contain),
+            Ok(1)=> Ok(FitMode::Cover),
+            Ok(2)=> Ok(FitMode::Exact),
+            Ok(3)=> Ok(FitMode::MaxSide),
+            Ok(_)=> Ok(FitMode::Contain),
+            Err(e)=> Err(e)
+        }
+    }
+}
+
 /// dontstarve affine transform
 /// a, b, c, d, tx, ty
 #[derive(Debug, PartialEq, Clone, Copy)]
-struct AffineTransform {
+pub struct AffineTransform {
     a: f64,
     b: f64,
     c: f64,
@@ -63,7 +190,7 @@ impl AffineTransform {
     }
 
     /// build a transform from vec
-    fn from_vec(vec: Vec<f64>) -> Self {
+    pub fn from_vec(vec: Vec<f64>) -> Self {
         AffineTransform {
             a: vec[0], 
             b: vec[1], 
@@ -108,12 +235,51 @@ impl AffineTransform {
         }
     }
 
+    /// determinant, area scale of the transform
+    pub fn det(&self) -> f64 {
+        self.a*self.d - self.b*self.c
+    }
+
+    /// mip level to sample, like GPU lod selection but from the area scale (determinant),
+    /// `scale` is the extra output scale (eg. export scale, supersample)
+    pub fn mip_level(&self, scale: f64) -> u32 {
+        let s = self.det().abs().sqrt()* scale;
+        if s.is_finite() && s > 0.0 && s < 1.0 {
+            (-s.log2()).floor() as u32
+        }
+        else {
+            0
+        }
+    }
+
+    /// matrix for sampling a mip of source, which is `sx`, `sy` times smaller than source,
+    /// pixel (x, y) of the mip covers source area from (x, y)* (sx, sy) to (x + 1, y + 1)* (sx, sy)
+    pub fn on_mip(&self, sx: f64, sy: f64) -> Self {
+        let (ox, oy) = ((sx - 1.0) / 2.0, (sy - 1.0) / 2.0);
+        AffineTransform {
+            a: self.a* sx,
+            b: self.b* sx,
+            c: self.c* sy,
+            d: self.d* sy,
+            tx: self.tx + self.a* ox + self.c* oy,
+            ty: self.ty + self.b* ox + self.d* oy,
+        }
+    }
+
     /// apply affine transform on coord xy
     fn onpoint(&self, px: f64, py: f64) -> (f64, f64) {
         (self.tx + self.a * px + self.c * py, 
          self.ty + self.b * px + self.d * py)
     }
 
+    /// apply affine transform on rect (x, y, right, bottom), return the bounding box
+    pub fn onrect(&self, x: f64, y: f64, right: f64, bottom: f64) -> (f64, f64, f64, f64) {
+        [(x, y), (right, y), (x, bottom), (right, bottom)].iter()
+            .map(|&(px, py)| self.onpoint(px, py))
+            .fold((f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+                |(x0, y0, x1, y1), (px, py)| (x0.min(px), y0.min(py), x1.max(px), y1.max(py)))
+    }
+
     /// check if any NAN is not in matrix
     fn is_valid(&self) -> bool {
         f64::is_finite(self.a) &&
@@ -124,8 +290,13 @@ impl AffineTransform {
         f64::is_finite(self.ty)
     }
 
+    /// convert to unique bits, usable as hash key
+    pub fn to_bits(self) -> [u64; 6] {
+        [self.a, self.b, self.c, self.d, self.tx, self.ty].map(f64::to_bits)
+    }
+
     /// convert to unique bytes
-    fn to_bytes(self) -> Vec<u8> {
+    pub fn to_bytes(self) -> Vec<u8> {
         [f64::to_le_bytes(self.a),
         f64::to_le_bytes(self.b),
         f64::to_le_bytes(self.c),
@@ -154,43 +325,389 @@ impl Index<usize> for AffineTransform {
     }
 }
 
+/// bounding box (x, y, width, height) of pixels with alpha > threshold, None if fully transparent
+pub fn alpha_bbox(rgba: &[u8], width: u32, height: u32, threshold: u8) -> Option<(u32, u32, u32, u32)> {
+    let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+    for y in 0..height {
+        let row = &rgba[(y* width* 4) as usize..((y + 1)* width* 4) as usize];
+        for x in 0..width {
+            if row[(x* 4 + 3) as usize] > threshold {
+                x0 = x0.min(x);
+                x1 = x1.max(x);
+                y0 = y0.min(y);
+                y1 = y1.max(y);
+            }
+        }
+    }
+    if x0 > x1 {
+        None
+    }
+    else {
+        Some((x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+    }
+}
+
+/// indices of 8 neighbours of pixel i
+fn neighbours(i: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((i % width) as i64, (i / width) as i64);
+    [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)].into_iter()
+        .map(move |(dx, dy)| (x + dx, y + dy))
+        .filter(move |&(nx, ny)| nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64)
+        .map(move |(nx, ny)| ny as usize* width + nx as usize)
+}
+
+static SRGB_TO_LINEAR: Lazy<[f32; 256]> = Lazy::new(||{
+    let mut table = [0.0; 256];
+    for (i, v) in table.iter_mut().enumerate() {
+        let c = i as f32 / 255.0;
+        *v = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+    }
+    table
+});
+
+/// linear light is quantized to 12 bits, fine enough to roundtrip all sRGB bytes
+const LINEAR_LUT_SIZE: usize = 4096;
+
+static LINEAR_TO_SRGB: Lazy<[u8; LINEAR_LUT_SIZE]> = Lazy::new(||{
+    let mut table = [0; LINEAR_LUT_SIZE];
+    for (i, v) in table.iter_mut().enumerate() {
+        let c = i as f32 / (LINEAR_LUT_SIZE - 1) as f32;
+        let s = if c <= 0.0031308 { c* 12.92 } else { 1.055* c.powf(1.0 / 2.4) - 0.055 };
+        *v = (s* 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    table
+});
+
+/// decode sRGB byte to linear light (0-1)
+#[inline]
+pub fn srgb_to_linear(v: u8) -> f32 {
+    SRGB_TO_LINEAR[v as usize]
+}
+
+/// encode linear light (0-1) to sRGB byte
+#[inline]
+pub fn linear_to_srgb(v: f32) -> u8 {
+    LINEAR_TO_SRGB[(v.clamp(0.0, 1.0)* (LINEAR_LUT_SIZE - 1) as f32 + 0.5) as usize]
+}
+
+/// source pixels (index, weight) covered by each target pixel when shrinking `from` pixels to `to`
+fn area_weights(from: usize, to: usize) -> Vec<Vec<(usize, f32)>> {
+    let f = from as f64 / to as f64;
+    (0..to).map(|i| {
+        let (start, end) = (i as f64* f, (i + 1) as f64* f);
+        (start.floor() as usize..(end.ceil() as usize).min(from))
+            .map(|j| (j, ((end.min(j as f64 + 1.0) - start.max(j as f64)) / f) as f32))
+            .filter(|(_, k)| *k > 0.0)
+            .collect()
+    }).collect()
+}
+
+/// downsample rgba bytes to half size rounding up (eg. 5x3 -> 3x2) with a box filter,
+/// each target pixel averages the source area it covers, so odd sizes are scaled exactly
+pub fn half_size_ceil(bytes: &[u8], width: usize, height: usize) -> (Vec<u8>, usize, usize) {
+    let (w, h) = (width.div_ceil(2), height.div_ceil(2));
+    let (weights_x, weights_y) = (area_weights(width, w), area_weights(height, h));
+    let mut rows = vec![0.0_f32; w* height* 4];
+    for y in 0..height {
+        for (x, weights) in weights_x.iter().enumerate() {
+            for &(i, k) in weights {
+                for c in 0..4 {
+                    rows[(y* w + x)* 4 + c] += bytes[(y* width + i)* 4 + c] as f32* k;
+                }
+            }
+        }
+    }
+    let mut result = vec![0; w* h* 4];
+    for (y, weights) in weights_y.iter().enumerate() {
+        for i in 0..w* 4 {
+            let v = weights.iter().map(|&(j, k)| rows[j* w* 4 + i]* k).sum::<f32>();
+            result[y* w* 4 + i] = (v + 0.5).min(255.0) as u8;
+        }
+    }
+    (result, w, h)
+}
+
+/// fill rgb of fully transparent pixels with average color of their visible neighbours,
+/// repeat `radius` times, each pass grows the bleeding area by 1 pixel
+pub fn alpha_bleed(rgba: &mut [u8], width: u32, height: u32, radius: u32) {
+    let (width, height) = (width as usize, height as usize);
+    let mut filled = rgba.chunks_exact(4).map(|p| p[3] > 0).collect::<Vec<_>>();
+    let mut queued = filled.clone();
+    let mut frontier = (0..width* height)
+        .filter(|&i| !filled[i] && neighbours(i, width, height).any(|j| filled[j]))
+        .collect::<Vec<_>>();
+    frontier.iter().for_each(|&i| queued[i] = true);
+    for _ in 0..radius {
+        if frontier.is_empty() {
+            break;
+        }
+        let colors = frontier.iter().map(|&i| {
+            let (mut sum, mut n) = ([0_u32; 3], 0);
+            for j in neighbours(i, width, height).filter(|&j| filled[j]) {
+                (0..3).for_each(|c| sum[c] += rgba[j* 4 + c] as u32);
+                n += 1;
+            }
+            sum.map(|v| ((v + n / 2) / n.max(1)) as u8)
+        }).collect::<Vec<_>>();
+        for (&i, color) in frontier.iter().zip(colors) {
+            rgba[i* 4..i* 4 + 3].copy_from_slice(&color);
+            filled[i] = true;
+        }
+        let mut next = vec![];
+        for &i in frontier.iter() {
+            for j in neighbours(i, width, height) {
+                if !queued[j] {
+                    queued[j] = true;
+                    next.push(j);
+                }
+            }
+        }
+        frontier = next;
+    }
+}
+
+#[test]
+fn check_alpha_bleed() {
+    let mut rgba = vec![0; 5* 3* 4];
+    // single visible pixel at (1, 1)
+    rgba[24..28].copy_from_slice(&[200, 100, 50, 255]);
+    assert_eq!(alpha_bbox(&rgba, 5, 3, 0), Some((1, 1, 1, 1)));
+    alpha_bleed(&mut rgba, 5, 3, 1);
+    assert_eq!(&rgba[0..4], &[200, 100, 50, 0]);
+    assert_eq!(&rgba[3* 4..4* 4], &[0, 0, 0, 0]);
+    alpha_bleed(&mut rgba, 5, 3, u32::MAX);
+    assert!(rgba.chunks_exact(4).all(|p| p[..3] == [200, 100, 50]));
+    assert_eq!(alpha_bbox(&rgba, 5, 3, 0), Some((1, 1, 1, 1)));
+}
+
+#[test]
+fn check_copy_on_write() {
+    let mut a = lua_image::Image::from_rgba(vec![0; 4* 4* 4], 4, 4).unwrap();
+    let b = a.clone();
+    assert_eq!(a.as_bytes().as_ptr(), b.as_bytes().as_ptr());
+    a.copy_from(&lua_image::Image::from_rgba(vec![255; 2* 2* 4], 2, 2).unwrap(), 1, 1);
+    assert_ne!(a.as_bytes().as_ptr(), b.as_bytes().as_ptr());
+    assert!(b.as_bytes().iter().all(|v| *v == 0));
+    assert_eq!(a.as_bytes().iter().filter(|v| **v == 255).count(), 2* 2* 4);
+}
+
+#[test]
+fn check_premultiplied_sampling() {
+    // opaque color next to transparent black, edge must not darken
+    let bytes = [[200, 100, 50, 255], [200, 100, 50, 255], [0; 4], [0; 4]].concat();
+    let img = lua_image::Image::from_rgba(bytes, 4, 1).unwrap();
+    let matrix = AffineTransform::from_vec(vec![3.0, 0.0, 0.0, 3.0, 0.0, 0.0]);
+    for (resampler, supersample) in [(Resampler::Bilinear, 1), (Resampler::Bilinear, 3), (Resampler::Bicubic, 1)] {
+        let result = img.affine_transform(12, 3, matrix, resampler, supersample, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+        for c in result.as_bytes().chunks_exact(4).filter(|c| c[3] >= 64) {
+            assert!(c[0].abs_diff(200) < 6 && c[1].abs_diff(100) < 6 && c[2].abs_diff(50) < 6, "{:?}", c);
+        }
+    }
+    // straight sampling mixes in the black of transparent pixels
+    let straight = img.affine_transform(12, 3, matrix, Resampler::Bilinear, 1, AlphaMode::Straight, ColorSpace::Srgb).unwrap();
+    let premultiplied = img.affine_transform(12, 3, matrix, Resampler::Bilinear, 1, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+    let index = (12 + 5) * 4;
+    let (s, p) = (&straight.as_bytes()[index..index + 4], &premultiplied.as_bytes()[index..index + 4]);
+    assert_eq!(s[3], p[3]);
+    assert!(s[3] > 0 && s[3] < 255, "{:?}", s);
+    assert!(s[0] < 100 && p[0].abs_diff(200) < 6, "{:?} {:?}", s, p);
+}
+
+#[test]
+fn check_kernel_sampling() {
+    // opaque step edge sampled halfway, symmetric kernels give the midpoint and keep alpha opaque
+    let bytes = [0, 0, 0, 0, 255, 255, 255, 255].iter().flat_map(|&v| [v, v, v, 255]).collect::<Vec<_>>();
+    let img = lua_image::Image::from_rgba(bytes, 8, 1).unwrap();
+    let shift = AffineTransform::from_vec(vec![1.0, 0.0, 0.0, 1.0, -0.5, 0.0]);
+    for resampler in [Resampler::Bicubic, Resampler::Lanczos3] {
+        let result = img.affine_transform(8, 1, shift, resampler, 1, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+        let c = &result.as_bytes()[3* 4..4* 4];
+        assert!(c[0].abs_diff(128) <= 1 && c[3] == 255, "{:?} {:?}", resampler, c);
+        // interpolating kernels reproduce source at integer positions
+        let identity = img.affine_transform(8, 1, AffineTransform::from_vec(vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0]),
+            resampler, 1, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+        assert_eq!(identity.as_bytes(), img.as_bytes());
+    }
+    // lanczos weights do not sum to 1 at fractional offsets, flat area must stay opaque
+    let img = lua_image::Image::from_rgba([200, 100, 50, 255].repeat(16* 16), 16, 16).unwrap();
+    let scale = AffineTransform::from_vec(vec![1.5, 0.0, 0.0, 1.5, 0.0, 0.0]);
+    let result = img.affine_transform(24, 24, scale, Resampler::Lanczos3, 1, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+    for y in 6..18 {
+        for x in 6..18 {
+            let i = (y* 24 + x)* 4;
+            assert_eq!(&result.as_bytes()[i..i + 4], &[200, 100, 50, 255]);
+        }
+    }
+}
+
+#[test]
+fn check_supersampling() {
+    // 2x2 black/white columns shrinked to one pixel, 2x2 samples are averaged
+    let bytes = [[0, 0, 0, 255], [255, 255, 255, 255]].concat().repeat(2);
+    let img = lua_image::Image::from_rgba(bytes, 2, 2).unwrap();
+    let half = AffineTransform::from_vec(vec![0.5, 0.0, 0.0, 0.5, -0.25, -0.25]);
+    let result = img.affine_transform(1, 1, half, Resampler::Nearest, 2, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+    let c = result.as_bytes();
+    assert!(c[0].abs_diff(128) <= 1 && c[3] == 255, "{:?}", c);
+    let result = img.affine_transform(1, 1, half, Resampler::Nearest, 1, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+    assert!(result.as_bytes()[0] == 0 || result.as_bytes()[0] == 255);
+}
+
+#[test]
+fn check_tiled_transform() {
+    // larger than one tile, translated copy must match source and leave outside transparent
+    let (w, h) = (150, 100);
+    let bytes = (0..w* h).flat_map(|i| [(i* 7 % 256) as u8, (i* 13 % 256) as u8, (i / w) as u8, 255]).collect::<Vec<_>>();
+    let img = lua_image::Image::from_rgba(bytes.clone(), w, h).unwrap();
+    let matrix = AffineTransform::from_vec(vec![1.0, 0.0, 0.0, 1.0, 10.0, 5.0]);
+    for resampler in [Resampler::Nearest, Resampler::Bilinear, Resampler::Bicubic] {
+        let result = img.affine_transform(w + 20, h + 10, matrix, resampler, 1, AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+        let out = result.as_bytes();
+        for y in 0..h + 10 {
+            for x in 0..w + 20 {
+                let i = ((y* (w + 20) + x)* 4) as usize;
+                if (10..w + 10).contains(&x) && (5..h + 5).contains(&y) {
+                    let j = (((y - 5)* w + x - 10)* 4) as usize;
+                    assert_eq!(&out[i..i + 4], &bytes[j..j + 4], "{:?} {} {}", resampler, x, y);
+                }
+                else {
+                    assert_eq!(out[i + 3], 0, "{:?} {} {}", resampler, x, y);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn check_linear_light() {
+    for v in 0..=255 {
+        assert_eq!(linear_to_srgb(srgb_to_linear(v)), v);
+    }
+    // half transparent white over black is brighter in linear light
+    let mut srgb = lua_image::Image::from_rgba([0, 0, 0, 255].repeat(2), 2, 1).unwrap();
+    let mut linear = srgb.clone();
+    let white = lua_image::Image::from_rgba([255, 255, 255, 128, 255, 255, 255, 255].to_vec(), 2, 1).unwrap();
+    srgb.paste(&white, 0, 0, BlendMode::Normal, ColorSpace::Srgb);
+    linear.paste(&white, 0, 0, BlendMode::Normal, ColorSpace::Linear);
+    assert_eq!(&srgb.as_bytes()[..4], &[128, 128, 128, 255]);
+    assert_eq!(&linear.as_bytes()[..4], &[188, 188, 188, 255]);
+    assert_eq!(&linear.as_bytes()[4..], &[255; 4]);
+}
+
+#[test]
+fn check_mipmap() {
+    // 1px checkerboard aliases to black or white without mipmap
+    let bytes = (0..8* 8).flat_map(|i| if (i + i / 8) % 2 == 0 { [0, 0, 0, 255] } else { [255; 4] }).collect();
+    let img = lua_image::Image::from_rgba(bytes, 8, 8).unwrap();
+    let matrix = AffineTransform::from_vec(vec![0.25, 0.0, 0.0, 0.25, 0.0, 0.0]);
+    let level = img.mip_level(&matrix, 1.0);
+    assert_eq!(level, 2);
+    assert_eq!(img.mip_level(&matrix, 2.0), 1);
+    let mip = img.mipmap(level);
+    assert_eq!((mip.width, mip.height), (2, 2));
+    let result = mip.affine_transform(2, 2, img.mip_matrix(&matrix, &mip), Resampler::Bilinear, 1,
+        AlphaMode::Premultiplied, ColorSpace::Srgb).unwrap();
+    for c in result.as_bytes().chunks_exact(4).filter(|c| c[3] > 0) {
+        assert!(c[0].abs_diff(128) < 4, "{:?}", c);
+    }
+    // odd sizes are rounded up, flat color stays flat and mip covers the same area as source
+    let img = lua_image::Image::from_rgba([200, 100, 50, 255].repeat(5* 3), 5, 3).unwrap();
+    assert_eq!(img.max_mip_level(), 3);
+    let mip = img.mipmap(1);
+    assert_eq!((mip.width, mip.height), (3, 2));
+    assert!(mip.as_bytes().chunks_exact(4).all(|c| c == [200, 100, 50, 255]));
+    assert_eq!((img.mipmap(3).width, img.mipmap(3).height), (1, 1));
+    let identity = AffineTransform::from_vec(vec![1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    let m = img.mip_matrix(&identity, &mip);
+    assert_eq!(m.onpoint(-0.5, -0.5), (-0.5, -0.5));
+    assert_eq!(m.onpoint(2.5, 1.5), (4.5, 2.5));
+}
+
+#[test]
+fn check_metadata_roundtrip() {
+    let path = std::env::temp_dir().join(format!("metadata-{}.png", std::process::id()));
+    let lua = rlua::Lua::new();
+    let result = lua.context(|lua_ctx| -> rlua::Result<()> {
+        lua_image::init(lua_ctx)?;
+        lua_ctx.globals().set("path", path.to_string_lossy().to_string())?;
+        lua_ctx.load(r#"
+            local img = Image.From_RGBA(string.rep("\0\0\0\255", 4), 2, 2)
+            img:save(path, { software = "Asset Archive", build = "wilson", frame = 3, facing = "正面" })
+            local m = Image.ReadMetadata(path)
+            assert(m.software == "Asset Archive", m.software)
+            assert(m.build == "wilson", m.build)
+            assert(m.frame == "3", m.frame)
+            assert(m.facing == "正面", m.facing)
+        "#).exec()
+    });
+    let _ = std::fs::remove_file(&path);
+    result.unwrap();
+}
+
+#[test]
+fn check_transform_error() {
+    let lua = rlua::Lua::new();
+    lua.context(|lua_ctx| -> rlua::Result<()> {
+        lua_image::init(lua_ctx)?;
+        lua_ctx.load(r#"
+            local img = Image.From_RGBA(string.rep("\255", 4* 4* 4), 4, 4)
+            local tasks = { ["@thread"] = 2 }
+            for i = 1, 4 do
+                tasks["task"..i] = { img = img, render_width = 4, render_height = 4, matrix = {1, 0, 0, 1, 0, 0} }
+            end
+            tasks.task3.matrix = {0/0, 0, 0, 1, 0, 0}
+            local success, err = pcall(Image.MultiThreadedTransform, tasks)
+            assert(not success and tostring(err):find("task3"), tostring(err))
+        "#).exec()
+    }).unwrap();
+}
+
 pub mod lua_image {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::mpsc::sync_channel;
+    use std::collections::HashMap;
     use std::sync::{Arc, Condvar, Mutex};
     use std::thread::spawn;
-    use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, ImageEncoder, Pixel, Rgb, Rgba};
+    use std::time::Duration;
+    use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, Pixel, Rgb, Rgba};
     use image::ColorType;
     use rlua::{AnyUserData, Context};
     use rlua::Value;
     use rlua::{Function, MetaMethod, UserData, UserDataMethods, Variadic, Table};
 
     use crate::filesystem::lua_filesystem::ConvertArgToString;
+    use crate::colorcube::ColorCube;
+    use crate::algorithm::lua_algorithm::{mult_alpha, div_alpha_mut};
+    use crate::imagehash::{self, lua_imagehash::{to_hex, from_hex, get_kind, LuaHashIndex}};
 
     use super::*;
 
+    /// image, path, png metadata
+    type SaveTask = (Image, String, Vec<(String, String)>);
+
     struct AsyncEncoder {
         condvar: Arc<Condvar>,
-        tasks: Arc<Mutex<Vec<(Image, String)>>>,
+        tasks: Arc<Mutex<Vec<SaveTask>>>,
     }
 
     impl AsyncEncoder {
         fn new() -> Self {
             let num_threads = num_cpus::get().min(16);
             let condvar = Arc::new(Condvar::new());
-            let tasks = Arc::new(Mutex::new(Vec::<(Image, String)>::new()));
+            let tasks = Arc::new(Mutex::new(Vec::<SaveTask>::new()));
             let _workers = (0..num_threads).map(|_|{
                 let condvar = Arc::clone(&condvar);
                 let tasks = Arc::clone(&tasks);
                 spawn(move ||{
                     loop {
-                        let (img, path) = {
+                        let (img, path, metadata) = {
                             let mut tasks = tasks.lock().unwrap();
                             while tasks.is_empty() {
                                 tasks = condvar.wait(tasks).unwrap();
                             }
                             tasks.pop().unwrap()
                         };
-                        if let Err(err) = img.save(path.as_str()) {
+                        if let Err(err) = img.save(path.as_str(), &metadata) {
                             eprintln!("Failed to save image `{}` because of Error: {}", path, err);
                         }
                         condvar.notify_all(); // notify main thread to check if tasks cleared
@@ -205,37 +722,151 @@ pub mod lua_image {
         }
 
         /// add a new image encoding task to thread pool
-        fn add_task(&self, img: Image, path: String) {
+        fn add_task(&self, img: Image, path: String, metadata: Vec<(String, String)>) {
             let mut tasks = self.tasks.lock().unwrap();
-            tasks.push((img, path));
+            tasks.push((img, path, metadata));
             self.condvar.notify_all();
         }
 
-        /// block until all tasks finished
-        fn wait(&self) {
+        /// block until all tasks finished, `interrupted` is polled periodically,
+        /// return false if interrupted (pending tasks are dropped)
+        fn wait(&self, mut interrupted: impl FnMut()-> bool) -> bool {
             let mut tasks = self.tasks.lock().unwrap();
             while !tasks.is_empty() {
-                tasks = self.condvar.wait(tasks).unwrap();
+                if interrupted() {
+                    tasks.clear();
+                    return false;
+                }
+                tasks = self.condvar.wait_timeout(tasks, Duration::from_millis(100)).unwrap().0;
             }
+            true
         }
     }
 
+    /// check the interrupt flag of current ipc call (`IpcInterrupted`), always false in CLI mode
+    pub fn is_interrupted(lua: Context) -> bool {
+        lua.globals().get::<_, Function>("IpcInterrupted")
+            .and_then(|f| f.call::<_, Option<bool>>(()))
+            .map(|v| v.unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    /// convert lua table {[key] = value} to png text chunks, sorted by key
+    fn get_metadata(t: Option<Table>) -> LuaResult<Vec<(String, String)>> {
+        let mut result = vec![];
+        if let Some(t) = t {
+            for pair in t.pairs::<String, Value>() {
+                let (key, value) = pair?;
+                // keyword of text chunk is 1-79 bytes
+                if key.is_empty() || key.len() > 79 {
+                    return Err(LuaError::RuntimeError(format!("Invalid metadata key: `{}`", key)));
+                }
+                let value = match value {
+                    Value::String(s)=> s.to_str()?.to_string(),
+                    Value::Number(n)=> n.to_string(),
+                    Value::Boolean(b)=> b.to_string(),
+                    _=> return Err(LuaError::RuntimeError(format!("Invalid metadata value of `{}`", key))),
+                };
+                result.push((key, value));
+            }
+        }
+        result.sort();
+        Ok(result)
+    }
+
+    /// read png text chunks (tEXt, zTXt, iTXt)
+    pub fn read_metadata(path: &str) -> Result<Vec<(String, String)>, String> {
+        let fs = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut reader = png::Decoder::new(std::io::BufReader::new(fs)).read_info()
+            .map_err(|e| e.to_string())?;
+        // text chunks may be placed after image data
+        reader.finish().map_err(|e| e.to_string())?;
+        let info = reader.info();
+        let mut result = vec![];
+        for chunk in info.uncompressed_latin1_text.iter() {
+            result.push((chunk.keyword.clone(), chunk.text.clone()));
+        }
+        for chunk in info.compressed_latin1_text.iter() {
+            result.push((chunk.keyword.clone(), chunk.get_text().map_err(|e| e.to_string())?));
+        }
+        for chunk in info.utf8_text.iter() {
+            result.push((chunk.keyword.clone(), chunk.get_text().map_err(|e| e.to_string())?));
+        }
+        Ok(result)
+    }
+
+    /// same as `error(ERROR.IPC_INTERRUPTED)` in lua
+    pub fn interrupted_error() -> LuaError {
+        LuaError::RuntimeError("IPC_INTERRUPTED".into())
+    }
+
     static ASYNC_SAVER: Lazy<Mutex<AsyncEncoder>> = Lazy::new(||{
         Mutex::new(AsyncEncoder::new())
     });
 
+    /// width, height, matrix, resampler, supersample?, alpha?, colorspace?
+    type AffineTransformArgs = (u32, u32, Vec<f64>, Resampler, Option<u32>, Option<AlphaMode>, Option<ColorSpace>);
+
+    /// how source pixels are interpolated in transform
+    #[derive(Clone, Copy)]
+    struct SampleMode {
+        resampler: Resampler,
+        /// interpolate premultiplied colors
+        premultiplied: bool,
+        /// interpolate colors in linear light
+        linear: bool,
+    }
+
+    /// mip levels of a source built on demand, level 0 is the source itself and not stored
+    #[derive(Default)]
+    pub struct MipChain {
+        levels: Mutex<Vec<Image>>,
+    }
+
+    impl MipChain {
+        /// get mip `level` of `source`, missing levels are built,
+        /// and kept while total size counted in `bytes` is within `budget`
+        pub fn get(&self, source: &Image, level: u32, bytes: &AtomicUsize, budget: usize) -> Image {
+            if level == 0 {
+                return source.clone();
+            }
+            let mut levels = self.levels.lock().unwrap();
+            if let Some(img) = levels.get(level as usize - 1) {
+                return img.clone();
+            }
+            let mut img = levels.last().unwrap_or(source).clone();
+            for i in levels.len()..level as usize {
+                img = img.half_size();
+                let size = img.as_bytes().len();
+                if levels.len() == i && bytes.load(Ordering::Relaxed) + size <= budget {
+                    bytes.fetch_add(size, Ordering::Relaxed);
+                    levels.push(img.clone());
+                }
+            }
+            img
+        }
+    }
+
+    /// pixels are reference counted and copied on write, so clone is cheap
     pub struct Image {
         pub width: u32, 
         pub height: u32,
-        inner: DynamicImage,
+        inner: Arc<DynamicImage>,
     }
 
     impl Image {
         pub fn open(path: &str) -> Result<Self, &'static str> {
-            let reader = match ImageReader::open(path) {
+            let reader = match ImageReader::open(path).and_then(|r| r.with_guessed_format()) {
                 Ok(r)=> r,
                 Err(_)=> return Err("Failed to open image file"),
             };
+            // dds is decoded by bcndecode
+            if reader.format() == Some(image::ImageFormat::Dds) {
+                let bytes = std::fs::read(path).map_err(|_| "Failed to open image file")?;
+                return crate::dds::decode(&bytes).ok()
+                    .and_then(|(rgba, width, height)| Self::from_rgba(rgba, width, height))
+                    .ok_or("Failed to decode image data");
+            }
             let img = match reader.decode() {
                 Ok(r)=> r,
                 Err(_)=> return Err("Failed to decode image data"),
@@ -243,12 +874,23 @@ pub mod lua_image {
             Ok(Self::from_img(img))  
         }
 
+        /// address of shared pixels, equal for clones until one of them is written
+        #[inline]
+        pub fn storage_id(&self) -> usize {
+            Arc::as_ptr(&self.inner) as usize
+        }
+
         #[inline]
         pub fn as_bytes(&self) -> &[u8]{
             self.inner.as_bytes()
         }
 
-        fn from_rgba(bytes: Vec<u8>, width: u32, height: u32) -> Option<Self> {
+        #[inline]
+        pub fn to_rgba8(&self) -> Vec<u8> {
+            self.inner.to_rgba8().into_raw()
+        }
+
+        pub fn from_rgba(bytes: Vec<u8>, width: u32, height: u32) -> Option<Self> {
             if (bytes.len() as u32) < width * height * 4 {
                 None
             }
@@ -258,7 +900,7 @@ pub mod lua_image {
             }
         }
 
-        fn from_rgb(bytes: Vec<u8>, width: u32, height: u32) -> Option<Self> {
+        pub fn from_rgb(bytes: Vec<u8>, width: u32, height: u32) -> Option<Self> {
             if (bytes.len() as u32) < width * height * 3 {
                 None
             }
@@ -268,11 +910,30 @@ pub mod lua_image {
             }
         }
 
-        fn clone(&self) -> Self {
+        /// 0x0 placeholder, eg. for pixels moved out of lua userdata
+        pub fn empty() -> Self {
+            Self::from_img(DynamicImage::new_rgba8(0, 0))
+        }
+
+        /// share pixels with self, no copy until one of them is mutated
+        pub fn clone(&self) -> Self {
             Self {
                 width: self.width,
                 height: self.height,
-                inner: self.inner.clone(),
+                inner: Arc::clone(&self.inner),
+            }
+        }
+
+        /// mutable pixels, copied if shared with other images
+        fn inner_mut(&mut self) -> &mut DynamicImage {
+            Arc::make_mut(&mut self.inner)
+        }
+
+        /// overwrite pixels with other image, reuse the buffer if possible
+        pub fn reset_from(&mut self, other: &Image) {
+            match (Arc::get_mut(&mut self.inner).and_then(|i| i.as_mut_rgba8()), other.inner.as_rgba8()) {
+                (Some(a), Some(b)) if a.dimensions() == b.dimensions() => a.copy_from_slice(b),
+                _ => *self = other.clone(),
             }
         }
 
@@ -288,7 +949,7 @@ pub mod lua_image {
             Image {
                 width: inner.width(),
                 height: inner.height(),
-                inner
+                inner: Arc::new(inner),
             }
         }
 
@@ -300,15 +961,6 @@ pub mod lua_image {
             }
         }
 
-        fn get_pixel(&self, x: u32, y: u32) -> Option<Rgba<u8>> {
-            if x >= self.width || y >= self.height {
-                None
-            }
-            else {
-                Some(unsafe { self.inner.unsafe_get_pixel(x, y) })
-            }
-        }
-
         fn get_pixel_float(&self, x: f64, y: f64) -> Option<Rgba<u8>> {
             if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
                 None
@@ -320,106 +972,280 @@ pub mod lua_image {
             }
         }
 
-        fn set_pixel(&mut self, x: u32, y: u32, pixel: Rgba<u8>) {
-            unsafe { self.inner.unsafe_put_pixel(x, y, pixel) };
+        /// read source pixel as float color (0-255), in linear mode rgb is decoded to linear light,
+        /// in premultiplied mode rgb is multiplied by alpha (after decoding)
+        fn fetch(&self, x: f64, y: f64, mode: SampleMode) -> Option<[f64; 4]> {
+            let c = self.get_pixel_float(x, y)?;
+            let k = if mode.premultiplied { c[3] as f64 / 255.0 } else { 1.0 };
+            let channel = |v: u8| if mode.linear { srgb_to_linear(v) as f64 * 255.0 } else { v as f64 } * k;
+            Some([channel(c[0]), channel(c[1]), channel(c[2]), c[3] as f64])
         }
-        
-        /// merge two rgba colors
-        fn merge_color(c1: Option<Rgba<u8>>, c2: Option<Rgba<u8>>, percent: f64) -> Option<Rgba<u8>> {
-            match (c1, c2) {
-                (Some(c1), Some(c2))=> {
-                    let (a1, a2) = (c1[3] as f64 * percent, c2[3] as f64 * (1.0 - percent));
-                    let alpha = a1 + a2;
-                    Some(Rgba::from([
-                        Self::normalize((c1[0] as f64 * a1 + c2[0] as f64 * a2)/alpha),
-                        Self::normalize((c1[1] as f64 * a1 + c2[1] as f64 * a2)/alpha),
-                        Self::normalize((c1[2] as f64 * a1 + c2[2] as f64 * a2)/alpha),
-                        Self::normalize(alpha)
-                    ]))
-                },
-                (Some(c1), None)=> {
-                    Some(Rgba::from([c1[0], c1[1], c1[2], Self::normalize(c1[3] as f64 * percent)]))
-                },
-                (None, Some(c2))=> {
-                    Some(Rgba::from([c2[0], c2[1], c2[2], Self::normalize(c2[3] as f64 * (1.0 - percent))]))
-                },
-                (None, None)=> None,
+
+        /// convert sampled float color to rgba bytes, inverse of `fetch`
+        fn encode(c: [f64; 4], mode: SampleMode) -> Rgba<u8> {
+            let k = if !mode.premultiplied { 1.0 } else if c[3] > 0.0 { 255.0 / c[3] } else { 0.0 };
+            let channel = |v: f64| if mode.linear { linear_to_srgb((v* k / 255.0) as f32) } else { Self::normalize(v* k) };
+            Rgba::from([channel(c[0]), channel(c[1]), channel(c[2]), Self::normalize(c[3])])
+        }
+
+        /// linear interpolation of two colors, missing pixel is transparent black
+        fn merge_color(c1: Option<[f64; 4]>, c2: Option<[f64; 4]>, percent: f64) -> Option<[f64; 4]> {
+            if c1.is_none() && c2.is_none() {
+                return None;
             }
+            let (c1, c2) = (c1.unwrap_or([0.0; 4]), c2.unwrap_or([0.0; 4]));
+            Some([0, 1, 2, 3].map(|i| c1[i] * percent + c2[i] * (1.0 - percent)))
         }
         /// convert float color to u8 (0-255)
         #[inline]
         fn normalize(c: f64) -> u8 {
            f64::clamp(c.round(), 0.0, 255.0) as u8
         }
+        /// catmull-rom cubic kernel (a = -0.5)
+        #[inline]
+        fn bicubic_kernel(x: f64) -> f64 {
+            const A: f64 = -0.5;
+            let x = x.abs();
+            if x < 1.0 {
+                ((A + 2.0)* x - (A + 3.0))* x* x + 1.0
+            }
+            else if x < 2.0 {
+                ((A* x - 5.0* A)* x + 8.0* A)* x - 4.0* A
+            }
+            else {
+                0.0
+            }
+        }
+
+        #[inline]
+        fn lanczos3_kernel(x: f64) -> f64 {
+            let sinc = |x: f64| if x == 0.0 { 1.0 } else {
+                let x = x* std::f64::consts::PI;
+                x.sin() / x
+            };
+            if x.abs() < 3.0 {
+                sinc(x)* sinc(x / 3.0)
+            }
+            else {
+                0.0
+            }
+        }
+
+        /// sample with a separable kernel, normalized by the summed weights of all taps
+        /// (pixels outside of image are transparent)
+        fn sample_kernel(&self, sx: f64, sy: f64, radius: i32, kernel: fn(f64)-> f64, mode: SampleMode) -> Option<[f64; 4]> {
+            let r = radius as f64;
+            if sx < -r || sy < -r || sx > self.width as f64 + r || sy > self.height as f64 + r {
+                return None;
+            }
+            let (fx, fy) = (f64::floor(sx), f64::floor(sy));
+            let mut acc = [0.0; 4];
+            let mut weight = 0.0;
+            for j in 1-radius..=radius {
+                let py = fy + j as f64;
+                let wy = kernel(sy - py);
+                if wy == 0.0 {
+                    continue;
+                }
+                for i in 1-radius..=radius {
+                    let px = fx + i as f64;
+                    let k = kernel(sx - px)* wy;
+                    weight += k;
+                    if let Some(c) = self.fetch(px, py, mode) {
+                        acc.iter_mut().zip(c).for_each(|(a, c)| *a += c* k);
+                    }
+                }
+            }
+            let mut acc = acc.map(|v| v / weight);
+            if !weight.is_normal() || acc[3] < 0.5 {
+                return None;
+            }
+            if mode.premultiplied {
+                // negative lobes may exceed opaque, scale back to keep the straight color
+                let scale = f64::min(1.0, 255.0 / acc[3]);
+                acc = acc.map(|v| v* scale);
+                let alpha = acc[3];
+                acc[..3].iter_mut().for_each(|v| *v = v.clamp(0.0, alpha));
+            }
+            Some(acc)
+        }
+
+        /// sample source color at float coord (pixel center is integer coord)
+        fn sample(&self, sx: f64, sy: f64, mode: SampleMode) -> Option<[f64; 4]> {
+            match mode.resampler {
+                Resampler::Nearest => {
+                    let sx = f64::round(sx);
+                    let sy = f64::round(sy);
+                    self.fetch(sx, sy, mode)
+                },
+                Resampler::Bilinear => {
+                    if sx < -3.0 || sy < -3.0 || sx > self.width as f64 + 3.0 || sy > self.height as f64 + 3.0 {
+                        return None;
+                    }
+                    // left right top bottom
+                    let sx_left = f64::floor(sx);
+                    let sx_right = sx_left + 1.0;
+                    let sy_top = f64::floor(sy);
+                    let sy_bottom = sy_top + 1.0;
+                    let rgba_left = Image::merge_color(
+                        self.fetch(sx_left,  sy_top, mode),
+                        self.fetch(sx_left, sy_bottom, mode),
+                        1.0 - (sy - sy_top));
+                    let rgba_right = Image::merge_color(
+                        self.fetch(sx_right,  sy_top, mode),
+                        self.fetch(sx_right, sy_bottom, mode),
+                        1.0 - (sy - sy_top));
+                    Image::merge_color(
+                        rgba_left,
+                        rgba_right,
+                        1.0 - (sx - sx_left))
+                },
+                Resampler::Bicubic => self.sample_kernel(sx, sy, 2, Self::bicubic_kernel, mode),
+                Resampler::Lanczos3 => self.sample_kernel(sx, sy, 3, Self::lanczos3_kernel, mode),
+            }
+        }
+
+        /// sample one target pixel, each target pixel is sampled `n`x`n` times and averaged
+        fn sample_target(&self, x: u32, y: u32,
+            transformer: &impl Fn(f64, f64)-> (f64, f64), mode: SampleMode, n: u32) -> Option<Rgba<u8>> {
+            if n == 1 {
+                // sampler point
+                let (sx, sy) = transformer(x as f64, y as f64);
+                return self.sample(sx, sy, mode).map(|c| Self::encode(c, mode));
+            }
+            let mut acc = None;
+            for j in 0..n {
+                for i in 0..n {
+                    let ox = (i as f64 + 0.5) / n as f64 - 0.5;
+                    let oy = (j as f64 + 0.5) / n as f64 - 0.5;
+                    let (sx, sy) = transformer(x as f64 + ox, y as f64 + oy);
+                    if let Some(c) = self.sample(sx, sy, mode) {
+                        let acc = acc.get_or_insert([0.0; 4]);
+                        acc.iter_mut().zip(c).for_each(|(a, c)| *a += c);
+                    }
+                }
+            }
+            acc.map(|acc| Self::encode(acc.map(|v| v / (n* n) as f64), mode))
+        }
+
         /// apply transform on the image, return new one
         /// transforming method is define as a closure, eg: |(px, py)| -> (sx*2.0, sy*2.0)
-        /// NOTE: bbox not calculated
+        /// each target pixel is sampled `supersample`x`supersample` times and averaged
+        /// NOTE: bbox not calculated, use `transform_region` if it is known
+        #[allow(clippy::too_many_arguments)]
         pub fn transform(&self, width: u32, height: u32,
-            transformer: impl Fn(u32, u32)-> (f64, f64), resampler: Resampler) -> Self {
+            transformer: impl Fn(f64, f64)-> (f64, f64), resampler: Resampler, supersample: u32,
+            alpha: AlphaMode, colorspace: ColorSpace) -> Self {
+            self.transform_region(width, height, (0, 0, width, height), transformer, resampler, supersample, alpha, colorspace)
+        }
+
+        /// apply transform on the image, only pixels inside region (x, y, right, bottom) are sampled,
+        /// the region is visited in square tiles to keep source reads local
+        /// in premultiplied mode, transparent pixels do not bleed their colors into edges
+        /// in linear mode, colors are interpolated in linear light
+        #[allow(clippy::too_many_arguments)]
+        pub fn transform_region(&self, width: u32, height: u32, region: (u32, u32, u32, u32),
+            transformer: impl Fn(f64, f64)-> (f64, f64), resampler: Resampler, supersample: u32,
+            alpha: AlphaMode, colorspace: ColorSpace) -> Self {
+            const TILE: u32 = 64;
+            let n = supersample.max(1);
+            let mode = SampleMode {
+                resampler,
+                premultiplied: alpha == AlphaMode::Premultiplied,
+                linear: colorspace == ColorSpace::Linear,
+            };
             let mut imgbuf = image::ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
-            let source_width = self.width;
-            let source_height = self.height;
-            for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-                // sampler point
-                let (sx, sy) = transformer(x, y);
-                let sp = match resampler {
-                    Resampler::Nearest => {
-                        let sx = f64::round(sx);
-                        let sy = f64::round(sy);
-                        self.get_pixel_float(sx, sy)
-                    },
-                    Resampler::Bilinear => {
-                        if sx < -3.0 || sy < -3.0 || sx > source_width as f64 + 3.0 || sy > source_height as f64 + 3.0 {
-                            continue;
+            let (x0, y0) = (region.0.min(width), region.1.min(height));
+            let (x1, y1) = (region.2.min(width), region.3.min(height));
+            for ty in (y0..y1).step_by(TILE as usize) {
+                for tx in (x0..x1).step_by(TILE as usize) {
+                    for y in ty..(ty + TILE).min(y1) {
+                        for x in tx..(tx + TILE).min(x1) {
+                            if let Some(color) = self.sample_target(x, y, &transformer, mode, n) {
+                                imgbuf.put_pixel(x, y, color);
+                            }
                         }
-                        // left right top bottom
-                        let sx_left = f64::floor(sx);
-                        let sx_right = sx_left + 1.0;
-                        let sy_top = f64::floor(sy);
-                        let sy_bottom = sy_top + 1.0;
-                        let rgba_left = Image::merge_color(
-                            self.get_pixel_float(sx_left,  sy_top),
-                            self.get_pixel_float(sx_left, sy_bottom),
-                            1.0 - (sy - sy_top));
-                        let rgba_right = Image::merge_color(
-                            self.get_pixel_float(sx_right,  sy_top),
-                            self.get_pixel_float(sx_right, sy_bottom),
-                            1.0 - (sy - sy_top));
-                        Image::merge_color(
-                            rgba_left,
-                            rgba_right,
-                            1.0 - (sx - sx_left))
                     }
-                };
-                if let Some(color) = sp {
-                    *pixel = color
                 }
             }
             Self::from_img(DynamicImage::from(imgbuf))
         }
 
         /// apply affine transform on an image, return the new image
-        fn affine_transform(&self, width: u32, height: u32, matrix: AffineTransform, resampler: Resampler) -> Result<Self, &'static str> {
+        /// only the bounding box of transformed source is processed
+        #[allow(clippy::too_many_arguments)]
+        pub fn affine_transform(&self, width: u32, height: u32, matrix: AffineTransform,
+            resampler: Resampler, supersample: u32, alpha: AlphaMode, colorspace: ColorSpace) -> Result<Self, &'static str> {
             let rev_matrix = matrix.reverse();
             if rev_matrix.is_valid() {
-                let transformer = |x, y|rev_matrix.onpoint(x as f64, y as f64);
-                Ok(self.transform(width, height, transformer, resampler))
+                // pixel centers are at integer coords, expand by sampler reach
+                let r = resampler.radius();
+                let (x0, y0, x1, y1) = matrix.onrect(
+                    -r, -r, self.width as f64 - 1.0 + r, self.height as f64 - 1.0 + r);
+                // supersample offsets are within half pixel
+                let region = (
+                    f64::clamp(x0.floor() - 1.0, 0.0, width as f64) as u32,
+                    f64::clamp(y0.floor() - 1.0, 0.0, height as f64) as u32,
+                    f64::clamp(x1.ceil() + 2.0, 0.0, width as f64) as u32,
+                    f64::clamp(y1.ceil() + 2.0, 0.0, height as f64) as u32,
+                );
+                let transformer = |x, y|rev_matrix.onpoint(x, y);
+                Ok(self.transform_region(width, height, region, transformer, resampler, supersample, alpha, colorspace))
             }
             else {
                 Err("Invalid affine matrix: nan")
             }
         }
 
+        /// blend two rgba colors (source over background) with a separable blend mode
+        /// computed in premultiplied space, ref to W3C Compositing and Blending
+        fn blend_color(background: Rgba<u8>, pixel: Rgba<u8>, blend: BlendMode, colorspace: ColorSpace) -> Rgba<u8> {
+            let (ab, as_) = (background[3] as f32 / 255.0, pixel[3] as f32 / 255.0);
+            let alpha = as_ + ab - as_* ab;
+            if alpha <= 0.0 {
+                return background;
+            }
+            let linear = colorspace == ColorSpace::Linear;
+            let decode = |v: u8| if linear { srgb_to_linear(v) } else { v as f32 / 255.0 };
+            let mut result = [0; 4];
+            for i in 0..3 {
+                let pb = decode(background[i])* ab;
+                let ps = decode(pixel[i])* as_;
+                let po = match blend {
+                    BlendMode::Normal => ps + pb* (1.0 - as_),
+                    BlendMode::Additive => (ps + pb).min(1.0),
+                    BlendMode::Multiply => ps* (1.0 - ab) + pb* (1.0 - as_) + ps* pb,
+                    BlendMode::Screen => ps + pb - ps* pb,
+                };
+                result[i] = if linear {
+                    linear_to_srgb(po / alpha)
+                }
+                else {
+                    Self::normalize((po / alpha* 255.0) as f64)
+                };
+            }
+            result[3] = Self::normalize((alpha* 255.0) as f64);
+            Rgba::from(result)
+        }
+
         /// paste another image on this, this method will mutate dest image pixels
-        pub fn paste(&mut self, other: Image, px: i64, py: i64) {
+        /// in linear mode, semi-transparent pixels are blended in linear light
+        pub fn paste(&mut self, other: &Image, px: i64, py: i64, blend: BlendMode, colorspace: ColorSpace) {
             let (width, height) = (self.width as i64, self.height as i64);
+            let dest = self.inner_mut();
             for (x, y, pixel) in other.inner.pixels() {
                 let ox = px + x as i64;
                 let oy = py + y as i64;
                 if ox < 0 || oy < 0 || ox >= width || oy >= height {
                     continue;
                 }
-                let background = self.get_pixel(ox as u32, oy as u32).unwrap();
+                let background = unsafe { dest.unsafe_get_pixel(ox as u32, oy as u32) };
+                if blend != BlendMode::Normal || colorspace == ColorSpace::Linear {
+                    if pixel[3] != 0 {
+                        unsafe { dest.unsafe_put_pixel(ox as u32, oy as u32, Self::blend_color(background, pixel, blend, colorspace)) };
+                    }
+                    continue;
+                }
                 let merge = match pixel[3] as u32 {
                     255=> pixel,
                     0=> background,
@@ -468,30 +1294,175 @@ pub mod lua_image {
                         ])
                     }
                 };
-                self.set_pixel(ox as u32, oy as u32, merge);
+                unsafe { dest.unsafe_put_pixel(ox as u32, oy as u32, merge) };
             }
         }
 
+        /// copy pixels of another image to position (x, y), alpha is not blended
+        pub fn copy_from(&mut self, other: &Image, x: u32, y: u32) {
+            if let Err(e) = self.inner_mut().copy_from(other.inner.as_ref(), x, y) {
+                eprintln!("Failed to copy image: {}", e);
+            }
+        }
+
+        /// downsample to half size (rounding up) with box filter in premultiplied alpha
+        pub fn half_size(&self) -> Self {
+            let (mut bytes, w, h) = half_size_ceil(&mult_alpha(&self.to_rgba8()), self.width as usize, self.height as usize);
+            div_alpha_mut(&mut bytes);
+            Self::from_rgba(bytes, w as u32, h as u32).unwrap()
+        }
+
+        /// mip level at which the image is shrinked to 1x1
+        pub fn max_mip_level(&self) -> u32 {
+            self.width.max(self.height).max(1).next_power_of_two().ilog2()
+        }
+
+        /// matrix for sampling `mip` of this image, scaled by the real size of the level
+        pub fn mip_matrix(&self, matrix: &AffineTransform, mip: &Image) -> AffineTransform {
+            matrix.on_mip(self.width as f64 / mip.width as f64, self.height as f64 / mip.height as f64)
+        }
+
+        /// mip level to sample with `matrix`, see `AffineTransform::mip_level`
+        pub fn mip_level(&self, matrix: &AffineTransform, scale: f64) -> u32 {
+            matrix.mip_level(scale).min(self.max_mip_level())
+        }
+
+        /// build mip `level` on the fly
+        pub fn mipmap(&self, level: u32) -> Self {
+            (0..level).fold(self.clone(), |img, _| img.half_size())
+        }
+
         #[inline]
-        pub fn save(&self, path: &str) -> image::ImageResult<()> {
-            self.inner.save(path)
+        pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Self {
+            Self::from_img(self.inner.crop_imm(x, y, width, height))
         }
 
-        pub fn save_smallest(&self, path: &str) -> image::ImageResult<()> {
-            use image::codecs::png::{PngEncoder, CompressionType, FilterType};
+        /// bounding box (x, y, width, height) of pixels with alpha > threshold
+        pub fn alpha_bbox(&self, threshold: u8) -> Option<(u32, u32, u32, u32)> {
+            match self.inner.as_rgba8() {
+                Some(buf) => super::alpha_bbox(buf.as_raw(), self.width, self.height, threshold),
+                None if self.inner.color().has_alpha() => super::alpha_bbox(&self.to_rgba8(), self.width, self.height, threshold),
+                None => Some((0, 0, self.width, self.height)),
+            }
+        }
+
+        /// crop to alpha bounding box, return cropped image and offset, None if fully transparent
+        pub fn trim(&self, threshold: u8) -> Option<(Self, u32, u32)> {
+            self.alpha_bbox(threshold).map(|(x, y, w, h)| (self.crop(x, y, w, h), x, y))
+        }
+
+        /// bleed edge colors into fully transparent pixels, this method will mutate image pixels
+        pub fn alpha_bleed(&mut self, radius: u32) {
+            if !self.inner.color().has_alpha() {
+                return;
+            }
+            if self.inner.as_rgba8().is_none() {
+                self.inner = Arc::new(DynamicImage::ImageRgba8(self.inner.to_rgba8()));
+            }
+            let (width, height) = (self.width, self.height);
+            if let Some(buf) = self.inner_mut().as_mut_rgba8() {
+                super::alpha_bleed(buf, width, height, radius);
+            }
+        }
+
+        /// downscale to width x height and convert to luma (0-1), transparent pixel is black
+        pub fn luma_thumbnail(&self, width: u32, height: u32) -> Vec<f64> {
+            self.inner.resize_exact(width, height, image::imageops::FilterType::Triangle)
+                .to_rgba8()
+                .pixels()
+                .map(|p| (0.299* p[0] as f64 + 0.587* p[1] as f64 + 0.114* p[2] as f64)
+                    * p[3] as f64 / 255.0 / 255.0)
+                .collect()
+        }
+
+        /// resize the image with filter and fit mode, return a new one
+        pub fn resize(&self, width: u32, height: u32, resampler: Resampler, mode: FitMode) -> Self {
+            let filter = resampler.into();
+            let width = width.max(1);
+            let height = height.max(1);
+            Self::from_img(match mode {
+                FitMode::Contain => self.inner.resize(width, height, filter),
+                FitMode::Cover => self.inner.resize_to_fill(width, height, filter),
+                FitMode::Exact => self.inner.resize_exact(width, height, filter),
+                FitMode::MaxSide => {
+                    let scale = width as f64 / self.width.max(self.height).max(1) as f64;
+                    let w = (self.width as f64 * scale).round().max(1.0) as u32;
+                    let h = (self.height as f64 * scale).round().max(1.0) as u32;
+                    self.inner.resize_exact(w, h, filter)
+                },
+            })
+        }
+
+        /// downscale the image to fit inside max_w x max_h, never upscale
+        pub fn thumbnail(&self, max_w: u32, max_h: u32) -> Self {
+            if self.width <= max_w && self.height <= max_h {
+                self.clone()
+            }
+            else {
+                self.resize(max_w, max_h, Resampler::Bilinear, FitMode::Contain)
+            }
+        }
+
+        /// save image to path, metadata is written as text chunks if format is png
+        pub fn save(&self, path: &str, metadata: &[(String, String)]) -> image::ImageResult<()> {
+            let is_png = std::path::Path::new(path).extension()
+                .map(|ext| ext.eq_ignore_ascii_case("png"))
+                .unwrap_or(false);
+            if is_png && !metadata.is_empty() {
+                self.save_png(path, metadata, png::Compression::Default)
+            }
+            else {
+                self.inner.save(path)
+            }
+        }
+
+        pub fn save_smallest(&self, path: &str, metadata: &[(String, String)]) -> image::ImageResult<()> {
+            self.save_png(path, metadata, png::Compression::Best)
+        }
+
+        fn save_png(&self, path: &str, metadata: &[(String, String)], compression: png::Compression) -> image::ImageResult<()> {
+            let to_err = |e: png::EncodingError| image::ImageError::IoError(std::io::Error::other(e.to_string()));
             let fs = std::fs::File::create(path).map_err(image::ImageError::IoError)?;
-            let encoder = PngEncoder::new_with_quality(
-                fs, 
-                CompressionType::Best, 
-                FilterType::default());
-            encoder.write_image(self.inner.as_bytes(), self.width, self.height, self.inner.color())
+            let mut encoder = png::Encoder::new(std::io::BufWriter::new(fs), self.width, self.height);
+            let rgba;
+            let bytes = match self.inner.color() {
+                ColorType::Rgb8 => {
+                    encoder.set_color(png::ColorType::Rgb);
+                    self.inner.as_bytes()
+                },
+                ColorType::Rgba8 => {
+                    encoder.set_color(png::ColorType::Rgba);
+                    self.inner.as_bytes()
+                },
+                _ => {
+                    encoder.set_color(png::ColorType::Rgba);
+                    rgba = self.inner.to_rgba8();
+                    rgba.as_raw().as_slice()
+                },
+            };
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_compression(compression);
+            encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+            for (key, value) in metadata.iter() {
+                // tEXt only supports latin-1
+                if value.is_ascii() {
+                    encoder.add_text_chunk(key.clone(), value.clone()).map_err(to_err)?;
+                }
+                else {
+                    encoder.add_itxt_chunk(key.clone(), value.clone()).map_err(to_err)?;
+                }
+            }
+            let mut writer = encoder.write_header().map_err(to_err)?;
+            writer.write_image_data(bytes).map_err(to_err)?;
+            writer.finish().map_err(to_err)
         }
 
         #[inline]
-        pub fn save_async(&self, path: &str) {
+        pub fn save_async(&self, path: &str, metadata: &[(String, String)]) {
             ASYNC_SAVER.lock().unwrap().add_task(
                 self.clone(),
-                path.to_string()
+                path.to_string(),
+                metadata.to_vec(),
             );
         }
 
@@ -505,88 +1476,63 @@ pub mod lua_image {
             writer.get_ref().to_vec()
         }
 
-        pub fn apply_filter(&mut self, filter: &Filter) {
-            match &mut self.inner {
+        /// encode image to klei texture file bytes
+        pub fn save_tex_bytes(&self, options: &crate::ktex::EncodeOptions) -> Result<Vec<u8>, String> {
+            let rgba = self.inner.to_rgba8();
+            crate::ktex::KTex::encode(rgba.as_raw(), self.width, self.height, options)
+        }
+
+        /// encode image to dds file bytes
+        pub fn save_dds_bytes(&self, options: &crate::ktex::EncodeOptions) -> Result<Vec<u8>, String> {
+            let rgba = self.inner.to_rgba8();
+            crate::dds::encode(rgba.as_raw(), self.width, self.height, options.pixel_format, options.mipmap)
+        }
+
+        pub fn apply_filter(&mut self, filter: &Filter, colorspace: ColorSpace) {
+            match self.inner_mut() {
                 DynamicImage::ImageRgba8(buffer)=> {
-                    buffer.pixels_mut().for_each(|pixel|{
-                        pixel[0] = filter.r[pixel[0] as usize];
-                        pixel[1] = filter.g[pixel[1] as usize];
-                        pixel[2] = filter.b[pixel[2] as usize];
-                        pixel[3] = filter.a[pixel[3] as usize];
-                    })
+                    buffer.chunks_exact_mut(4).for_each(|pixel| filter.apply(pixel, colorspace))
                 },
                 DynamicImage::ImageRgb8(buffer)=> {
-                    buffer.pixels_mut().for_each(|pixel|{
-                        pixel[0] = filter.r[pixel[0] as usize];
-                        pixel[1] = filter.g[pixel[1] as usize];
-                        pixel[2] = filter.b[pixel[2] as usize];
-                    })
+                    buffer.chunks_exact_mut(3).for_each(|pixel| filter.apply(pixel, colorspace))
                 },
                 _ => panic!("apply_filter only support rgb/rgba image")
             }
         }
 
-        pub fn apply_cc(&mut self, cc: &[u8], percent: f64) -> Result<(), &'static str> {
-            if cc.len() < 32*32*32*3 {
-                return Err("cc must contain 32,768 pixels")
-            }
-            let sampler = |c: u8| {
-                // 0-255 -> 0-31
-                let f = c as f64 / 255.0 * 31.0;
-                let floor = f64::floor(f) as usize;
-                let ceil  = f64::ceil(f) as usize;
-                let percent = f64::fract(f);
-                (floor, ceil, percent)
-            };
-            let get_offset = |r: usize, g: usize, b: usize|{
-                r + b*32 + g*1024
-            };
-            let apply_cc_impl = |channels: &mut[u8]|{
-                // let mut pixel = channels;
-                let rs = sampler(channels[0]);
-                let gs = sampler(channels[1]);
-                let bs = sampler(channels[2]);
-                let offset = (
-                    get_offset(rs.0, gs.0, bs.0),
-                    get_offset(rs.1, gs.1, bs.1)
-                );
-                let c1 = &cc[offset.0*3..offset.0*3+2];
-                let c2 = &cc[offset.1*3..offset.1*3+2];
-                c1.iter().zip(c2)
-                    .enumerate()
-                    .for_each(|(index, (v1, v2))|{
-                        // blue is used as blend percent
-                        channels[index] = f64::clamp(
-                            percent * (*v1 as f64 * (1.0 - bs.2) + *v2 as f64 * bs.2) +
-                            (1.0 - percent) * channels[index] as f64, 
-                            0.0, 255.0
-                        ) as u8
-                    });
-            };
+        pub fn apply_cc(&mut self, cc: &[u8], percent: f64, colorspace: ColorSpace) -> Result<(), String> {
+            let cube = ColorCube::from_cc_bytes(cc, 3, 32)?;
+            self.apply_color_cube(&cube, percent, colorspace);
+            Ok(())
+        }
 
-            match &mut self.inner {
-                DynamicImage::ImageRgba8(buffer) => {
-                    buffer.pixels_mut().for_each(|pixel|{
-                        apply_cc_impl(pixel.channels_mut());
-                    });
-                },
-                DynamicImage::ImageRgb8(buffer) => {
-                    buffer.pixels_mut().for_each(|pixel|{
-                        apply_cc_impl(pixel.channels_mut());
-                    });
-                },
+        /// apply color cube with trilinear interpolation, alpha is unchanged
+        pub fn apply_color_cube(&mut self, cube: &ColorCube, percent: f64, colorspace: ColorSpace) {
+            match self.inner_mut() {
+                DynamicImage::ImageRgba8(buffer) => cube.apply(buffer, 4, percent, colorspace),
+                DynamicImage::ImageRgb8(buffer) => cube.apply(buffer, 3, percent, colorspace),
                 _ => panic!("apply_cc only support rgb/rgba image")
-            };
-            Ok(())
+            }
         }
     }
 
     #[derive(Clone)]
-    pub struct Filter {
-        r: Vec<u8>,
-        g: Vec<u8>,
-        b: Vec<u8>,
-        a: Vec<u8>,
+    pub enum Filter {
+        /// per-channel lookup table
+        Map {
+            r: Vec<u8>,
+            g: Vec<u8>,
+            b: Vec<u8>,
+            a: Vec<u8>,
+        },
+        /// 4x5 row-major color matrix, [r', g', b', a'] = M * [r, g, b, a, 1], values in 0-1
+        Matrix([f64; 20]),
+        /// hue shift (degree), saturation and value multiplier
+        Hsv(f64, f64, f64),
+        /// hue shift (degree), saturation and lightness multiplier
+        Hsl(f64, f64, f64),
+        /// apply filters in order
+        Chain(Vec<Filter>),
     }
 
     impl Filter {
@@ -595,7 +1541,7 @@ pub mod lua_image {
             let g = Filter::prepare_map(fg)?;
             let b = Filter::prepare_map(fb)?;
             let a = Filter::prepare_map(fa)?;
-            Ok(Filter { r, g, b, a })
+            Ok(Filter::Map { r, g, b, a })
         }
 
         #[inline]
@@ -606,11 +1552,144 @@ pub mod lua_image {
             }
             Ok(result)
         }
+
+        /// convert to unique bits, floats are compared by bits so that equal keys hash equally
+        pub fn to_bits(&self) -> Vec<u64> {
+            match self {
+                Filter::Map { r, g, b, a } => [0].into_iter()
+                    .chain([r, g, b, a].into_iter().flatten().map(|v| *v as u64))
+                    .collect(),
+                Filter::Matrix(m) => [1].into_iter().chain(m.iter().map(|v| v.to_bits())).collect(),
+                Filter::Hsv(h, s, v) => vec![2, h.to_bits(), s.to_bits(), v.to_bits()],
+                Filter::Hsl(h, s, v) => vec![3, h.to_bits(), s.to_bits(), v.to_bits()],
+                Filter::Chain(filters) => [4, filters.len() as u64].into_iter()
+                    .chain(filters.iter().flat_map(|f| { let bits = f.to_bits(); [bits.len() as u64].into_iter().chain(bits) }))
+                    .collect(),
+            }
+        }
+
+        /// same as SetMultColour + SetAddColour, add color is scaled by its alpha
+        pub fn mult_add(mult: [f64; 4], add: [f64; 4]) -> Self {
+            Filter::Matrix([
+                mult[0], 0.0, 0.0, 0.0, add[0]* add[3],
+                0.0, mult[1], 0.0, 0.0, add[1]* add[3],
+                0.0, 0.0, mult[2], 0.0, add[2]* add[3],
+                0.0, 0.0, 0.0, mult[3], 0.0,
+            ])
+        }
+
+        /// apply on one rgb/rgba pixel, in linear mode color matrix works on linear light,
+        /// map and hsv/hsl filters always work on sRGB bytes
+        pub fn apply(&self, pixel: &mut [u8], colorspace: ColorSpace) {
+            let linear = colorspace == ColorSpace::Linear;
+            match self {
+                Filter::Map { r, g, b, a } => {
+                    pixel[0] = r[pixel[0] as usize];
+                    pixel[1] = g[pixel[1] as usize];
+                    pixel[2] = b[pixel[2] as usize];
+                    if pixel.len() > 3 {
+                        pixel[3] = a[pixel[3] as usize];
+                    }
+                },
+                Filter::Matrix(m) => {
+                    let c = Self::to_float(pixel, linear);
+                    let mut result = [0.0; 4];
+                    for (i, v) in result.iter_mut().enumerate() {
+                        let row = &m[i* 5..i* 5 + 5];
+                        *v = row[0]* c[0] + row[1]* c[1] + row[2]* c[2] + row[3]* c[3] + row[4];
+                    }
+                    Self::from_float(pixel, result, linear);
+                },
+                Filter::Hsv(dh, ds, dv) => {
+                    let c = Self::to_float(pixel, false);
+                    let (h, s, v) = rgb_to_hsv(c[0], c[1], c[2]);
+                    let (r, g, b) = hsv_to_rgb(
+                        (h + dh).rem_euclid(360.0), (s* ds).clamp(0.0, 1.0), (v* dv).clamp(0.0, 1.0));
+                    Self::from_float(pixel, [r, g, b, c[3]], false);
+                },
+                Filter::Hsl(dh, ds, dl) => {
+                    let c = Self::to_float(pixel, false);
+                    let (h, s, l) = rgb_to_hsl(c[0], c[1], c[2]);
+                    let (r, g, b) = hsl_to_rgb(
+                        (h + dh).rem_euclid(360.0), (s* ds).clamp(0.0, 1.0), (l* dl).clamp(0.0, 1.0));
+                    Self::from_float(pixel, [r, g, b, c[3]], false);
+                },
+                Filter::Chain(filters) => {
+                    filters.iter().for_each(|f| f.apply(pixel, colorspace));
+                },
+            }
+        }
+
+        #[inline]
+        fn to_float(pixel: &[u8], linear: bool) -> [f64; 4] {
+            let decode = |v: u8| if linear { srgb_to_linear(v) as f64 } else { v as f64 / 255.0 };
+            let a = if pixel.len() > 3 { pixel[3] as f64 / 255.0 } else { 1.0 };
+            [decode(pixel[0]), decode(pixel[1]), decode(pixel[2]), a]
+        }
+
+        #[inline]
+        fn from_float(pixel: &mut [u8], c: [f64; 4], linear: bool) {
+            for (i, (p, v)) in pixel.iter_mut().zip(c).enumerate() {
+                *p = if linear && i < 3 { linear_to_srgb(v as f32) } else { Image::normalize(v* 255.0) };
+            }
+        }
+    }
+
+    /// h in 0-360, others in 0-1
+    fn rgb_to_hsv(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let d = max - min;
+        let h = if d == 0.0 { 0.0 }
+            else if max == r { 60.0* ((g - b) / d).rem_euclid(6.0) }
+            else if max == g { 60.0* ((b - r) / d + 2.0) }
+            else { 60.0* ((r - g) / d + 4.0) };
+        let s = if max == 0.0 { 0.0 } else { d / max };
+        (h, s, max)
+    }
+
+    fn hsv_to_rgb(h: f64, s: f64, v: f64) -> (f64, f64, f64) {
+        let c = v* s;
+        let x = c* (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+        let m = v - c;
+        let (r, g, b) = match (h / 60.0) as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        (r + m, g + m, b + m)
+    }
+
+    fn rgb_to_hsl(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+        let (h, _, max) = rgb_to_hsv(r, g, b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let s = if max == min { 0.0 } else { (max - min) / (1.0 - (2.0* l - 1.0).abs()) };
+        (h, s, l)
+    }
+
+    fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (f64, f64, f64) {
+        let v = l + s* l.min(1.0 - l);
+        let sv = if v == 0.0 { 0.0 } else { 2.0* (1.0 - l / v) };
+        hsv_to_rgb(h, sv, v)
     }
 
     impl UserData for Filter {
         fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {
-            
+            // return a new filter which applies this and then other
+            _methods.add_method("chain", |_, filter: &Self, other: AnyUserData|{
+                let other = other.borrow::<Filter>()?.clone();
+                Ok(match filter.clone() {
+                    Filter::Chain(mut filters)=> {
+                        filters.push(other);
+                        Filter::Chain(filters)
+                    },
+                    f=> Filter::Chain(vec![f, other]),
+                })
+            });
         }
     }
 
@@ -634,22 +1713,46 @@ pub mod lua_image {
             // crop the image, return subregion (a new image userdata)
             _methods.add_method("crop", |_, img: &Self, 
                 (x, y, width, height): (u32, u32, u32, u32)|{
-                let img = img.inner.crop_imm(x, y, width, height);
-                Ok(Image::from_img(img))
+                Ok(img.crop(x, y, width, height))
+            });
+            // return x, y, width, height of pixels with alpha > threshold (default 0), nil if fully transparent
+            _methods.add_method("alpha_bbox", |_, img: &Self, threshold: Option<u8>|{
+                Ok(match img.alpha_bbox(threshold.unwrap_or(0)) {
+                    Some((x, y, w, h))=> Variadic::from_iter([x, y, w, h]),
+                    None=> Variadic::new(),
+                })
+            });
+            // crop transparent borders, return new image and crop offset x, y, nil if fully transparent
+            _methods.add_method("trim", |_, img: &Self, threshold: Option<u8>|{
+                Ok(match img.trim(threshold.unwrap_or(0)) {
+                    Some((img, x, y))=> (Some(img), Some(x), Some(y)),
+                    None=> (None, None, None),
+                })
             });
             // resize the image, return a new one
-            _methods.add_method("resize", |_, img: &Self, (width, height): (u32, u32)|{
-                Ok(Image::from_img(img.inner.resize(width, height, image::imageops::FilterType::Nearest)))
+            // default filter is nearest and default mode is contain
+            _methods.add_method("resize", |_, img: &Self, 
+                (width, height, resampler, mode): (u32, u32, Option<Resampler>, Option<FitMode>)|{
+                Ok(img.resize(width, height, 
+                    resampler.unwrap_or(Resampler::Nearest), 
+                    mode.unwrap_or(FitMode::Contain)))
+            });
+            // downscale the image for preview, keep aspect ratio
+            _methods.add_method("thumbnail", |_, img: &Self, (max_w, max_h): (u32, Option<u32>)|{
+                Ok(img.thumbnail(max_w, max_h.unwrap_or(max_w)))
             });
             // clone the image
             _methods.add_method("clone", |_, img: &Self, ()|{
-                Ok(Image::from_img(img.inner.clone()))
+                Ok(img.clone())
             });
             // apply an affine transform on image, return new image
+            // alpha: Image.ALPHA_PREMULTIPLIED (default) | Image.ALPHA_STRAIGHT
+            // colorspace: Image.COLORSPACE_SRGB (default) | Image.COLORSPACE_LINEAR
             _methods.add_method("affine_transform", |_, img: &Self, 
-                (width, height, matrix, resampler): (u32, u32, Vec<f64>, Resampler)|{
+                (width, height, matrix, resampler, supersample, alpha, colorspace): AffineTransformArgs|{
                 let matrix = AffineTransform::from_vec(matrix);
-                img.affine_transform(width, height, matrix, resampler)
+                img.affine_transform(width, height, matrix, resampler, supersample.unwrap_or(1).clamp(1, 8),
+                    alpha.unwrap_or(AlphaMode::Premultiplied), colorspace.unwrap_or(ColorSpace::Srgb))
                     .map_err(|e|LuaError::RuntimeError(e.to_string()))
             });
             // get pixel rgba of coord (x, y) (starts from left-top)
@@ -673,21 +1776,30 @@ pub mod lua_image {
                         Err(LuaError::RuntimeError(format!("pixel size not match, expected {}, got {}", size, pixel.len())))
                     }
                     else {
-                        unsafe { img.inner.unsafe_put_pixel(x, y, *Pixel::from_slice(pixel.as_slice())) }
+                        unsafe { img.inner_mut().unsafe_put_pixel(x, y, *Pixel::from_slice(pixel.as_slice())) }
                         Ok(())
                     }
                 }
             });
             // save image to path
-            _methods.add_method("save", |_, img: &Self, (path, smallest): (Value, Option<bool>)|{
+            //   img:save(path, smallest?, metadata?) or img:save(path, metadata)
+            //   metadata: {[key] = value}, written as png text chunks
+            _methods.add_method("save", |_, img: &Self, (path, smallest, metadata): (Value, Value, Option<Table>)|{
                 let path = match path.to_string() {
                     Ok(s)=> s,
                     Err(_)=> return Err(LuaError::ToLuaConversionError { from: "(lua)", to: "Path | string", message: None }),
                 };
+                let (smallest, metadata) = match smallest {
+                    Value::Table(t)=> (false, get_metadata(Some(t))?),
+                    Value::Boolean(b)=> (b, get_metadata(metadata)?),
+                    _=> (false, get_metadata(metadata)?),
+                };
                 if let Err(err) = {
-                    match smallest {
-                        Some(true)=> img.save_smallest(path.as_str()),
-                        _=> img.save(path.as_str())
+                    if smallest {
+                        img.save_smallest(path.as_str(), &metadata)
+                    }
+                    else {
+                        img.save(path.as_str(), &metadata)
                     }
                 } {
                     eprintln!("Failed to save image `{}` because of Error: {}", path, err);
@@ -698,14 +1810,52 @@ pub mod lua_image {
                 }
             });
             // async save image to path
-            _methods.add_method("save_async", |_, img: &Self, path: Value|{
+            _methods.add_method("save_async", |_, img: &Self, (path, metadata): (Value, Option<Table>)|{
                 let path = match path.to_string() {
                     Ok(s)=> s,
                     Err(_)=> return Err(LuaError::ToLuaConversionError { from: "(lua)", to: "Path | string", message: None }),
                 };
-                img.save_async(path.as_str());
+                img.save_async(path.as_str(), &get_metadata(metadata)?);
                 Ok(())
             });
+            // save image to klei texture file (*.tex)
+            _methods.add_method("save_tex", |_, img: &Self, (path, options): (Value, Option<Table>)|{
+                let path = path.to_string()?;
+                let options = crate::ktex::lua_ktex::get_encode_options(options)?;
+                let bytes = img.save_tex_bytes(&options).map_err(LuaError::RuntimeError)?;
+                if let Err(err) = std::fs::write(path.as_str(), bytes) {
+                    eprintln!("Failed to save tex `{}` because of Error: {}", path, err);
+                    Ok(false)
+                }
+                else {
+                    Ok(true)
+                }
+            });
+            // get klei texture file bytes of image
+            _methods.add_method("save_tex_bytes", |lua: Context, img: &Self, options: Option<Table>|{
+                let options = crate::ktex::lua_ktex::get_encode_options(options)?;
+                let bytes = img.save_tex_bytes(&options).map_err(LuaError::RuntimeError)?;
+                lua.create_string(bytes.as_slice())
+            });
+            // save image to dds file, only `format` and `mipmap` in options are used
+            _methods.add_method("save_dds", |_, img: &Self, (path, options): (Value, Option<Table>)|{
+                let path = path.to_string()?;
+                let options = crate::ktex::lua_ktex::get_encode_options(options)?;
+                let bytes = img.save_dds_bytes(&options).map_err(LuaError::RuntimeError)?;
+                if let Err(err) = std::fs::write(path.as_str(), bytes) {
+                    eprintln!("Failed to save dds `{}` because of Error: {}", path, err);
+                    Ok(false)
+                }
+                else {
+                    Ok(true)
+                }
+            });
+            // get dds file bytes of image
+            _methods.add_method("save_dds_bytes", |lua: Context, img: &Self, options: Option<Table>|{
+                let options = crate::ktex::lua_ktex::get_encode_options(options)?;
+                let bytes = img.save_dds_bytes(&options).map_err(LuaError::RuntimeError)?;
+                lua.create_string(bytes.as_slice())
+            });
             // get png file bytes of image
             _methods.add_method("save_png_bytes", |lua: Context, img: &Self, ()|{
                 lua.create_string(img.save_png_bytes().as_slice())
@@ -728,18 +1878,25 @@ pub mod lua_image {
                 lua.create_string(img.inner.as_bytes())
             });
             // paste another image on this
-            _methods.add_method_mut("paste", |_, img: &mut Self, (other, px, py): (AnyUserData, i64, i64)|{
+            _methods.add_method_mut("paste", |_, img: &mut Self, 
+                (other, px, py, blend, colorspace): (AnyUserData, i64, i64, Option<BlendMode>, Option<ColorSpace>)|{
                 match other.borrow::<Image>() {
                     Ok(other)=> {
-                        // TODO: this clone can be removed
-                        img.paste(other.clone(), px, py);
+                        img.paste(&other, px, py, blend.unwrap_or(BlendMode::Normal), colorspace.unwrap_or(ColorSpace::Srgb));
                         Ok(())
                     },
                     Err(_)=> Err(LuaError::ToLuaConversionError { from: "(lua)", to: "Image", message: None })
                 }
             });
+            // bleed edge colors into fully transparent pixels to avoid dark halo in bilinear sampling and mipmaps
+            // default radius is unlimited
+            _methods.add_method_mut("alpha_bleed", |_, img: &mut Self, radius: Option<u32>|{
+                img.alpha_bleed(radius.unwrap_or(u32::MAX));
+                Ok(())
+            });
             // filter rgba channels by each map function
-            _methods.add_method_mut("apply_filter", |_, img: &mut Self, filter: Value|{
+            _methods.add_method_mut("apply_filter", |_, img: &mut Self, (filter, colorspace): (Value, Option<ColorSpace>)|{
+                let colorspace = colorspace.unwrap_or(ColorSpace::Srgb);
                 match filter {
                     Value::Table(t)=> {
                         let filter = Filter::from_lua(
@@ -747,20 +1904,39 @@ pub mod lua_image {
                             t.get::<_, Function>(2)?,
                             t.get::<_, Function>(3)?,
                             t.get::<_, Function>(4)?)?;
-                        img.apply_filter(&filter);
+                        img.apply_filter(&filter, colorspace);
                         Ok(true)
                     },
                     Value::UserData(u)=> {
                         let filter = u.borrow::<Filter>()?;
-                        img.apply_filter(&filter);
+                        img.apply_filter(&filter, colorspace);
                         Ok(true)
                     },
                     _=> Err(LuaError::FromLuaConversionError { from: "(lua)", to: "table|Filter", message: None })
                 }
             });
             // apply dontstarve colour_cube on image
-            _methods.add_method_mut("apply_cc", |_, img: &mut Self, (cc, percent): (LuaString, f64)|{
-                img.apply_cc(cc.as_bytes(), percent).map_err(|e|LuaError::RuntimeError(e.to_string()))
+            // cc: game colour cube bytes (1024x32 rgb) or ColorCube
+            _methods.add_method_mut("apply_cc", |_, img: &mut Self, (cc, percent, colorspace): (Value, f64, Option<ColorSpace>)|{
+                let colorspace = colorspace.unwrap_or(ColorSpace::Srgb);
+                match cc {
+                    Value::String(s)=> img.apply_cc(s.as_bytes(), percent, colorspace).map_err(LuaError::RuntimeError),
+                    Value::UserData(v)=> {
+                        img.apply_color_cube(&*v.borrow::<ColorCube>()?, percent, colorspace);
+                        Ok(())
+                    },
+                    _=> Err(LuaError::FromLuaConversionError { from: "(lua)", to: "string|ColorCube", message: None })
+                }
+            });
+            // perceptual hash, return 16 hex digits
+            _methods.add_method("ahash", |_, img: &Self, ()|{
+                Ok(to_hex(imagehash::average_hash(img)))
+            });
+            _methods.add_method("dhash", |_, img: &Self, ()|{
+                Ok(to_hex(imagehash::difference_hash(img)))
+            });
+            _methods.add_method("phash", |_, img: &Self, ()|{
+                Ok(to_hex(imagehash::perceptual_hash(img)))
             });
             _methods.add_meta_method(MetaMethod::ToString, |_, img: &Self, ()|{
                 Ok(format!("Image<{}x{} {}>", img.width, img.height, img.pixelformat()))
@@ -802,6 +1978,8 @@ pub mod lua_image {
         img: Image,
         matrix: AffineTransform,
         filter: Option<Filter>,
+        /// set by worker if transform failed (eg. singular matrix)
+        error: Option<String>,
 
         task_id: String,
         worker_id: usize,
@@ -810,8 +1988,8 @@ pub mod lua_image {
     struct CompositeTaskData {
         canvas: Image,
         index: usize,
-        /// element image, px, py (paste position may be nagative)
-        elements: Vec<(Image, i64, i64)>,
+        /// element image, px, py (paste position may be nagative), blend mode
+        elements: Vec<(Image, i64, i64, BlendMode)>,
 
         worker_id: usize,
     }
@@ -821,6 +1999,46 @@ pub mod lua_image {
         table.set("Open", lua_ctx.create_function(|_, path: String|{
             Image::open(&path).map_err(|e| LuaError::RuntimeError(e.to_string()))
         })?)?;  
+        // read png text chunks, return {[key] = value}
+        table.set("ReadMetadata", lua_ctx.create_function(|lua, path: Value|{
+            let metadata = read_metadata(path.to_string()?.as_str())
+                .map_err(LuaError::RuntimeError)?;
+            let t = lua.create_table()?;
+            for (key, value) in metadata {
+                t.set(key, value)?;
+            }
+            Ok(t)
+        })?)?;
+        table.set("OpenTex", lua_ctx.create_function(|_, path: Value|{
+            crate::ktex::lua_ktex::open_tex(path.to_string()?.as_str())
+        })?)?;
+        table.set("LoadTex", lua_ctx.create_function(|_, data: LuaString|{
+            crate::ktex::lua_ktex::load_tex(data.as_bytes())
+        })?)?;
+        table.set("ColorCubeFromTex", lua_ctx.create_function(|_, tex: Value|{
+            crate::colorcube::lua_colorcube::from_tex(tex)
+        })?)?;
+        table.set("ColorCubeFromBytes", lua_ctx.create_function(|_, (data, size): (LuaString, Option<usize>)|{
+            crate::colorcube::lua_colorcube::from_bytes(data.as_bytes(), size)
+        })?)?;
+        table.set("LoadCube", lua_ctx.create_function(|_, data: LuaString|{
+            crate::colorcube::lua_colorcube::from_cube(&String::from_utf8_lossy(data.as_bytes()))
+        })?)?;
+        table.set("OpenCube", lua_ctx.create_function(|_, path: Value|{
+            let data = std::fs::read_to_string(path.to_string()?)
+                .map_err(|e| LuaError::RuntimeError(e.to_string()))?;
+            crate::colorcube::lua_colorcube::from_cube(&data)
+        })?)?;
+        table.set("HashDistance", lua_ctx.create_function(|_, (a, b): (String, String)|{
+            Ok(imagehash::hamming_distance(from_hex(&a)?, from_hex(&b)?))
+        })?)?;
+        // kind: ahash|dhash|phash (default)
+        table.set("HashIndex", lua_ctx.create_function(|_, kind: Option<String>|{
+            Ok(LuaHashIndex::new(get_kind(kind)?))
+        })?)?;
+        table.set("PackAtlas", lua_ctx.create_function(|lua, (images, options): (Table, Option<Table>)|{
+            crate::atlas::lua_atlas::pack_atlas(lua, images, options)
+        })?)?;
         table.set("From_RGBA", lua_ctx.create_function(|_, (data, width, height): (LuaString, u32, u32)|{
             Ok(Image::from_rgba(Vec::from(data.as_bytes()), width, height))
         })?)?;
@@ -830,20 +2048,27 @@ pub mod lua_image {
         table.set("Affine", lua_ctx.create_function(|_, vec: Vec<f64>|{
             Ok(AffineTransform::from_vec(vec))
         })?)?;
-        table.set("GifWriter", lua_ctx.create_function(|_, _path: String| -> LuaResult<()>{
-            unreachable!("Rust GifWriter is removed");
-            // GifWriter::new(&path).map_err(|e|LuaError::RuntimeError(e.to_string()))
+        table.set("AnimWriter", lua_ctx.create_function(|_, args: Table|{
+            crate::animwriter::lua_animwriter::create_writer(args)
+        })?)?;
+        table.set("SpriteSheetWriter", lua_ctx.create_function(|_, args: Table|{
+            crate::spritesheet::lua_spritesheet::create_writer(args)
         })?)?;
+        // native anim renderer, see animrender.rs
+        crate::animrender::lua_animrender::init(lua_ctx, &table)?;
         table.set("MultiThreadedTransform", lua_ctx.create_function(|lua, tasks: Table|{
             // type tasks = {
             //    [K:task-id]: task, 
             //    "@thread": int, 
             //    "@resampler": int, 
+            //    "@supersample": int,
+            //    "@mipmap": boolean, sample from mip level picked by matrix (default true)
             //    "@progress": function(current, total, percent)}
             //
             // type task = {
             //   id: string,
-            //   img: Image, width, height, matrix
+            //   img: Image, width, height, matrix,
+            //   filter?: Filter (Image.Filter|ColorMatrix|HSVFilter|HSLFilter)
             // }
             let num_threads = tasks.get::<_, usize>("@thread")
                 .unwrap_or_else(|_|num_cpus::get())
@@ -852,6 +2077,17 @@ pub mod lua_image {
             let onprogress = tasks.get::<_, Option<Function>>("@progress")?;
             let resampler = tasks.get::<_, Resampler>("@resampler")
                 .unwrap_or(Resampler::Bilinear);
+            let supersample = tasks.get::<_, u32>("@supersample")
+                .unwrap_or(1)
+                .clamp(1, 8);
+            let alpha = tasks.get::<_, AlphaMode>("@alpha")
+                .unwrap_or(AlphaMode::Premultiplied);
+            let colorspace = tasks.get::<_, ColorSpace>("@colorspace")
+                .unwrap_or(ColorSpace::Srgb);
+            let mipmap = tasks.get::<_, Option<bool>>("@mipmap")?.unwrap_or(true);
+            // mip chain of each source (kept alive by entry), shared by tasks and released when returned
+            let mips = Arc::new(Mutex::new(HashMap::<usize, (Image, Arc<MipChain>)>::new()));
+            let mip_bytes = Arc::new(AtomicUsize::new(0));
             let mut threads = Vec::with_capacity(num_threads);
             let mut keys = tasks.clone().pairs::<String, _>()
                 .map(|pair|pair.unwrap_or(("".to_string(), lua.create_table().unwrap())).0.to_string())
@@ -859,9 +2095,12 @@ pub mod lua_image {
                 .collect::<Vec<String>>();
             let total = keys.len();
             let (main_tx, main_rx) = sync_channel::<ElementTaskData>(1);
+            let cancel = Arc::new(AtomicBool::new(false));
             #[allow(unused_variables)]
             for i in 0..num_threads {
                 let main_tx = main_tx.clone();
+                let cancel = Arc::clone(&cancel);
+                let (mips, mip_bytes) = (Arc::clone(&mips), Arc::clone(&mip_bytes));
                 let (tx, rx) = sync_channel::<ElementTaskData>(1);
                 threads.push((spawn(move ||{
                     loop {
@@ -869,11 +2108,29 @@ pub mod lua_image {
                             Ok(task)=> task,
                             Err(_)=> return, // function returned, channel closed
                         };
+                        if cancel.load(Ordering::Relaxed) {
+                            return;
+                        }
                         // println!("WORKER {} <-", i);
-                        task.img = task.img.affine_transform(task.width, task.height, task.matrix, resampler).unwrap();
-                        if let Some(filter) = task.filter {
-                            task.img.apply_filter(&filter);
-                            task.filter = None;
+                        let level = if mipmap { task.img.mip_level(&task.matrix, supersample as f64) } else { 0 };
+                        let mip = if level > 0 {
+                            let chain = Arc::clone(&mips.lock().unwrap()
+                                .entry(task.img.storage_id())
+                                .or_insert_with(|| (task.img.clone(), Arc::default())).1);
+                            chain.get(&task.img, level, &mip_bytes, usize::MAX)
+                        }
+                        else {
+                            task.img.clone()
+                        };
+                        match mip.affine_transform(task.width, task.height, task.img.mip_matrix(&task.matrix, &mip),
+                            resampler, supersample, alpha, colorspace) {
+                            Ok(img)=> {
+                                task.img = img;
+                                if let Some(filter) = task.filter.take() {
+                                    task.img.apply_filter(&filter, colorspace);
+                                }
+                            },
+                            Err(e)=> task.error = Some(e.to_string()),
                         }
                         // println!("WORKER {} ->", i);
                         if main_tx.send(task).is_err() { // function returned, silently exit worker thread
@@ -884,6 +2141,11 @@ pub mod lua_image {
             }
    
             loop {
+                if is_interrupted(lua) {
+                    // pending tasks are dropped, workers exit when channels closed
+                    cancel.store(true, Ordering::Relaxed);
+                    return Err(interrupted_error());
+                }
                 for (i, (_, tx, is_available)) in threads.iter_mut().enumerate() {
                     if *is_available && !keys.is_empty() {
                         let key = keys.pop().unwrap();
@@ -900,6 +2162,7 @@ pub mod lua_image {
                                 Some(filter)=> Some(filter.borrow::<Filter>()?.clone()),
                                 None=> None,
                             },
+                            error: None,
                             task_id: key.clone(),
                             worker_id: i,
                         }).is_err() {
@@ -913,9 +2176,18 @@ pub mod lua_image {
                         return Err(LuaError::RuntimeError(format!("received task data from idle worker: {}", task.worker_id)));
                     }
                     *idle = true;
+                    if let Some(e) = task.error {
+                        cancel.store(true, Ordering::Relaxed);
+                        return Err(LuaError::RuntimeError(format!("Failed to transform {}: {}", task.task_id, e)));
+                    }
                     tasks.get::<_, Table>(task.task_id)?
                         .set("img", task.img)?;
                 }
+                // a busy worker exited, its task will never return
+                if threads.iter().any(|(thread, _, is_available)| !*is_available && thread.is_finished()) {
+                    cancel.store(true, Ordering::Relaxed);
+                    return Err(LuaError::RuntimeError("Transform worker panicked".into()));
+                }
                 if let Some(ref onprogress) = onprogress {
                     let current = total - keys.len();
                     onprogress.call::<_, ()>((current, total, current as f64 / total as f64))?;
@@ -927,7 +2199,7 @@ pub mod lua_image {
             Ok(())
         })?)?;
 
-        table.set("MultiThreadedCompositeAndRender", lua_ctx.create_function(|_, tasks: Table|{
+        table.set("MultiThreadedCompositeAndRender", lua_ctx.create_function(|lua, tasks: Table|{
             // type tasks = {
             //    [I: usize]: task, 
             //    "@numframe" : usize,
@@ -936,6 +2208,9 @@ pub mod lua_image {
             //    "@sequential": boolean
             //    "@progress": function(current, total, percent)}
             //
+            // type task = [{
+            //   img: Image, px, py, blend?: int
+            // }]
             let num_threads = tasks.get::<_, usize>("@thread")
                 .unwrap_or_else(|_|num_cpus::get())
                 .clamp(1, 64);
@@ -944,17 +2219,23 @@ pub mod lua_image {
             let canvas = tasks.get::<_, AnyUserData>("@canvas")?;
             let canvas = canvas.borrow::<Image>()?;
             let sequential = tasks.get::<_, bool>("@sequential")?;
+            let colorspace = tasks.get::<_, ColorSpace>("@colorspace")
+                .unwrap_or(ColorSpace::Srgb);
             let current_index = Arc::new(Mutex::new(1_usize)); // Lua table index starts at 1
             let total = tasks.get::<_, usize>("@numframe")?;
             let mut keys = (1..=total).rev().collect::<Vec<usize>>(); 
             let cond = Arc::new(Condvar::new());
+            let cancel = Arc::new(AtomicBool::new(false));
             let mut threads = Vec::with_capacity(num_threads);
             let (main_tx, main_rx) = sync_channel::<CompositeTaskData>(1);
+            // canvases are reused after encoded, so at most `num_threads` canvases are alive
+            let mut pool = Vec::<Image>::with_capacity(num_threads);
             info!("spawn {} threads", num_threads);
             #[allow(unused_variables)]
             for i in 0..num_threads {
                 let main_tx = main_tx.clone();
                 let cond = Arc::clone(&cond);
+                let cancel = Arc::clone(&cancel);
                 let (tx, rx) = sync_channel::<CompositeTaskData>(1);
                 let current_index = Arc::clone(&current_index);
                 threads.push((spawn(move||{
@@ -963,9 +2244,12 @@ pub mod lua_image {
                             Ok(task)=> task,
                             Err(_)=> return, // function returned, channel closed
                         };
+                        if cancel.load(Ordering::Relaxed) {
+                            return;
+                        }
                         // println!("WORKER {} <-", i);
-                        for (ele, x, y) in task.elements {
-                            task.canvas.paste(ele, x, y);
+                        for (ele, x, y, blend) in task.elements {
+                            task.canvas.paste(&ele, x, y, blend, colorspace);
                         }
                         task.elements = vec![];
                         // wait for sync
@@ -973,7 +2257,10 @@ pub mod lua_image {
                         // * png encoder is not
                         let mut index = current_index.lock().unwrap();
                         while *index != task.index && sequential {
-                            index = cond.wait(index).unwrap();
+                            if cancel.load(Ordering::Relaxed) {
+                                return;
+                            }
+                            index = cond.wait_timeout(index, Duration::from_millis(100)).unwrap().0;
                         }
                         // println!("WORKER {} ->", i);
                         if main_tx.send(task).is_err() { // function returned, silently exit worker thread
@@ -985,7 +2272,16 @@ pub mod lua_image {
             }
 
             info!("start rendering");
+            // wake up workers waiting for sequential encoding
+            let stop = || {
+                cancel.store(true, Ordering::Relaxed);
+                cond.notify_all();
+            };
             loop {
+                if is_interrupted(lua) {
+                    stop();
+                    return Err(interrupted_error());
+                }
                 for (i, (_, tx, is_available)) in threads.iter_mut().enumerate() {
                     if *is_available && !keys.is_empty() {
                         let key = keys.pop().unwrap();
@@ -995,11 +2291,16 @@ pub mod lua_image {
                             let img = v.get::<_, AnyUserData>("img")?.borrow::<Image>()?.clone();
                             let px = v.get::<_, i64>("px")?;
                             let py = v.get::<_, i64>("py")?;
-                            elements.push((img, px, py));
+                            let blend = v.get::<_, Option<BlendMode>>("blend")?.unwrap_or(BlendMode::Normal);
+                            elements.push((img, px, py, blend));
                         }
                         *is_available = false;
+                        let task_canvas = match pool.pop() {
+                            Some(mut img)=> { img.reset_from(&canvas); img },
+                            None=> canvas.clone(),
+                        };
                         if tx.send(CompositeTaskData{
-                            canvas: canvas.clone(),
+                            canvas: task_canvas,
                             index: key,
                             elements,
                             worker_id: i,
@@ -1024,11 +2325,14 @@ pub mod lua_image {
                         *current_index += 1;
                         cond.notify_all();
                     }
-                    encoder.call::<_, ()>((task.canvas, task.index))?;
+                    let data = lua.create_userdata(task.canvas)?;
+                    encoder.call::<_, ()>((data.clone(), task.index)).inspect_err(|_| stop())?;
+                    // canvas may be kept by lua, pixels are copied on write in that case
+                    pool.push(data.borrow::<Image>()?.clone());
                 }
                 if let Some(ref onprogress) = onprogress {
                     let current = total - keys.len();
-                    onprogress.call::<_, ()>((current, total, current as f64 / total as f64))?;
+                    onprogress.call::<_, ()>((current, total, current as f64 / total as f64)).inspect_err(|_| stop())?;
                 }
                 if keys.is_empty() && !threads.iter().any(|v|!v.2) {
                     break;
@@ -1040,6 +2344,20 @@ pub mod lua_image {
     
         table.set("NEAREST", Resampler::Nearest as u8)?;
         table.set("BILINEAR", Resampler::Bilinear as u8)?;
+        table.set("BICUBIC", Resampler::Bicubic as u8)?;
+        table.set("LANCZOS3", Resampler::Lanczos3 as u8)?;
+        table.set("ALPHA_STRAIGHT", AlphaMode::Straight as u8)?;
+        table.set("ALPHA_PREMULTIPLIED", AlphaMode::Premultiplied as u8)?;
+        table.set("COLORSPACE_SRGB", ColorSpace::Srgb as u8)?;
+        table.set("COLORSPACE_LINEAR", ColorSpace::Linear as u8)?;
+        table.set("FIT_CONTAIN", FitMode::Contain as u8)?;
+        table.set("FIT_COVER", FitMode::Cover as u8)?;
+        table.set("FIT_EXACT", FitMode::Exact as u8)?;
+        table.set("FIT_MAX_SIDE", FitMode::MaxSide as u8)?;
+        table.set("BLEND_NORMAL", BlendMode::Normal as u8)?;
+        table.set("BLEND_ADDITIVE", BlendMode::Additive as u8)?;
+        table.set("BLEND_MULTIPLY", BlendMode::Multiply as u8)?;
+        table.set("BLEND_SCREEN", BlendMode::Screen as u8)?;
         table.set("Filter", lua_ctx.create_function(|_, fns: Table|{
             Filter::from_lua(
                 fns.get(1)?, 
@@ -1048,6 +2366,33 @@ pub mod lua_image {
                 fns.get(4)?
             )
         })?)?;
+        // 4x5 color matrix (20 numbers), or {mult = {r,g,b,a}, add = {r,g,b,a}}
+        table.set("ColorMatrix", lua_ctx.create_function(|_, data: Table|{
+            if data.contains_key("mult")? || data.contains_key("add")? {
+                let get = |key: &str, default: [f64; 4]| -> LuaResult<[f64; 4]> {
+                    match data.get::<_, Option<Vec<f64>>>(key)? {
+                        Some(v)=> {
+                            let mut result = default;
+                            result.iter_mut().zip(v).for_each(|(a, b)| *a = b);
+                            Ok(result)
+                        },
+                        None=> Ok(default),
+                    }
+                };
+                return Ok(Filter::mult_add(get("mult", [1.0; 4])?, get("add", [0.0, 0.0, 0.0, 1.0])?));
+            }
+            let m = data.sequence_values::<f64>().collect::<LuaResult<Vec<f64>>>()?;
+            match <[f64; 20]>::try_from(m) {
+                Ok(m)=> Ok(Filter::Matrix(m)),
+                Err(_)=> Err(LuaError::RuntimeError("color matrix must contain 20 numbers".into()))
+            }
+        })?)?;
+        table.set("HSVFilter", lua_ctx.create_function(|_, (h, s, v): (f64, Option<f64>, Option<f64>)|{
+            Ok(Filter::Hsv(h, s.unwrap_or(1.0), v.unwrap_or(1.0)))
+        })?)?;
+        table.set("HSLFilter", lua_ctx.create_function(|_, (h, s, l): (f64, Option<f64>, Option<f64>)|{
+            Ok(Filter::Hsl(h, s.unwrap_or(1.0), l.unwrap_or(1.0)))
+        })?)?;
 
         table.set("EncodeJson", lua_ctx.create_function(|_,
             (bytes, width, height): (LuaString, u32, u32)|{
@@ -1083,9 +2428,13 @@ pub mod lua_image {
                     bytes_len, num_pixels * 3, num_pixels * 4)))
             }
         })?)?;
-        table.set("Wait", lua_ctx.create_function(|_, _: ()|{
-            ASYNC_SAVER.lock().unwrap().wait();
-            Ok(())
+        table.set("Wait", lua_ctx.create_function(|lua, _: ()|{
+            if ASYNC_SAVER.lock().unwrap().wait(|| is_interrupted(lua)) {
+                Ok(())
+            }
+            else {
+                Err(interrupted_error())
+            }
         })?)?;
 
         let globals = lua_ctx.globals();
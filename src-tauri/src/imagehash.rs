@@ -0,0 +1,299 @@
+// perceptual image hash (aHash / dHash / pHash) and duplicate clustering
+use std::collections::HashMap;
+
+use crate::image::lua_image::Image;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashKind {
+    Average,
+    Difference,
+    Perceptual,
+}
+
+impl HashKind {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "ahash" => Some(HashKind::Average),
+            "dhash" => Some(HashKind::Difference),
+            "phash" => Some(HashKind::Perceptual),
+            _ => None,
+        }
+    }
+}
+
+/// mean of pixels is used as threshold
+pub fn average_hash(img: &Image) -> u64 {
+    let luma = img.luma_thumbnail(8, 8);
+    let mean = luma.iter().sum::<f64>() / 64.0;
+    luma.iter().enumerate()
+        .fold(0, |hash, (i, &v)| if v > mean { hash | 1 << i } else { hash })
+}
+
+/// compare each pixel with its right neighbor
+pub fn difference_hash(img: &Image) -> u64 {
+    let luma = img.luma_thumbnail(9, 8);
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            if luma[y* 9 + x] < luma[y* 9 + x + 1] {
+                hash |= 1 << (y* 8 + x);
+            }
+        }
+    }
+    hash
+}
+
+/// low frequency of 32x32 DCT, compared with median
+pub fn perceptual_hash(img: &Image) -> u64 {
+    const N: usize = 32;
+    let luma = img.luma_thumbnail(N as u32, N as u32);
+    let cos_table = (0..8).flat_map(|u| (0..N).map(move |x|
+        f64::cos((2* x + 1) as f64 * u as f64 * std::f64::consts::PI / (2* N) as f64)))
+        .collect::<Vec<f64>>();
+    // only 8x8 coeffs are needed
+    let mut rows = vec![0.0; N* 8];
+    for y in 0..N {
+        for u in 0..8 {
+            rows[y* 8 + u] = (0..N).map(|x| luma[y* N + x]* cos_table[u* N + x]).sum();
+        }
+    }
+    let mut coeffs = [0.0; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coeffs[v* 8 + u] = (0..N).map(|y| rows[y* 8 + u]* cos_table[v* N + y]).sum();
+        }
+    }
+    // DC is excluded from median
+    let mut sorted = coeffs[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    coeffs.iter().enumerate()
+        .fold(0, |hash, (i, &v)| if v > median { hash | 1 << i } else { hash })
+}
+
+pub fn image_hash(img: &Image, kind: HashKind) -> u64 {
+    match kind {
+        HashKind::Average => average_hash(img),
+        HashKind::Difference => difference_hash(img),
+        HashKind::Perceptual => perceptual_hash(img),
+    }
+}
+
+#[inline]
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// BK-tree on hamming distance
+struct BKNode {
+    hash: u64,
+    items: Vec<usize>,
+    children: HashMap<u32, usize>,
+}
+
+#[derive(Default)]
+pub struct HashIndex {
+    nodes: Vec<BKNode>,
+    /// (id, hash)
+    pub items: Vec<(String, u64)>,
+}
+
+impl HashIndex {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, id: String, hash: u64) {
+        let item = self.items.len();
+        self.items.push((id, hash));
+        if self.nodes.is_empty() {
+            self.nodes.push(BKNode { hash, items: vec![item], children: HashMap::new() });
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let d = hamming_distance(self.nodes[current].hash, hash);
+            if d == 0 {
+                self.nodes[current].items.push(item);
+                return;
+            }
+            match self.nodes[current].children.get(&d) {
+                Some(&next) => current = next,
+                None => {
+                    let next = self.nodes.len();
+                    self.nodes.push(BKNode { hash, items: vec![item], children: HashMap::new() });
+                    self.nodes[current].children.insert(d, next);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// find all items within distance, return (item index, distance)
+    pub fn query(&self, hash: u64, max_distance: u32) -> Vec<(usize, u32)> {
+        let mut result = vec![];
+        if self.nodes.is_empty() {
+            return result;
+        }
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let d = hamming_distance(node.hash, hash);
+            if d <= max_distance {
+                result.extend(node.items.iter().map(|&i| (i, d)));
+            }
+            for (&k, &child) in node.children.iter() {
+                if k + max_distance >= d && k <= d + max_distance {
+                    stack.push(child);
+                }
+            }
+        }
+        result
+    }
+
+    /// group items which are connected by distance <= max_distance,
+    /// return clusters (item indices, similarity score in 0-1), single item is ignored
+    pub fn clusters(&self, max_distance: u32) -> Vec<(Vec<usize>, f64)> {
+        let n = self.items.len();
+        let mut parent = (0..n).collect::<Vec<usize>>();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for i in 0..n {
+            for (j, _) in self.query(self.items[i].1, max_distance) {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                if a != b {
+                    parent[a.max(b)] = a.min(b);
+                }
+            }
+        }
+        let mut groups = HashMap::<usize, Vec<usize>>::new();
+        for i in 0..n {
+            let root = find(&mut parent, i);
+            groups.entry(root).or_default().push(i);
+        }
+        let mut result = groups.into_values()
+            .filter(|group| group.len() > 1)
+            .map(|group| {
+                // average pairwise distance
+                let mut total = 0;
+                let mut count = 0;
+                for (k, &i) in group.iter().enumerate() {
+                    for &j in group[k+1..].iter() {
+                        total += hamming_distance(self.items[i].1, self.items[j].1);
+                        count += 1;
+                    }
+                }
+                let score = 1.0 - total as f64 / count as f64 / 64.0;
+                (group, score)
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0[0].cmp(&b.0[0])));
+        result
+    }
+}
+
+#[test]
+fn check_cluster() {
+    let mut index = HashIndex::new();
+    index.insert("a".into(), 0b1111_0000);
+    index.insert("b".into(), 0b1111_0001);
+    index.insert("c".into(), u64::MAX);
+    index.insert("d".into(), 0b1111_0011);
+    index.insert("e".into(), u64::MAX ^ 1);
+    let clusters = index.clusters(1);
+    assert_eq!(clusters.len(), 2);
+    assert_eq!(clusters[0].0, vec![0, 1, 3]);
+    assert_eq!(clusters[1].0, vec![2, 4]);
+    assert!((clusters[1].1 - 63.0 / 64.0).abs() < 1e-9);
+}
+
+pub mod lua_imagehash {
+    use super::*;
+    use rlua::prelude::{LuaResult, LuaError};
+    use rlua::{AnyUserData, UserData, UserDataMethods};
+
+    pub fn to_hex(hash: u64) -> String {
+        format!("{:016x}", hash)
+    }
+
+    pub fn from_hex(s: &str) -> LuaResult<u64> {
+        u64::from_str_radix(s, 16)
+            .map_err(|_| LuaError::RuntimeError(format!("Invalid hash: {}", s)))
+    }
+
+    pub fn get_kind(kind: Option<String>) -> LuaResult<HashKind> {
+        match kind {
+            Some(s) => HashKind::from_name(s.as_str())
+                .ok_or_else(|| LuaError::RuntimeError(format!("Invalid hash type: {}", s))),
+            None => Ok(HashKind::Perceptual),
+        }
+    }
+
+    pub struct LuaHashIndex {
+        kind: HashKind,
+        inner: HashIndex,
+    }
+
+    impl LuaHashIndex {
+        pub fn new(kind: HashKind) -> Self {
+            LuaHashIndex { kind, inner: HashIndex::new() }
+        }
+    }
+
+    impl UserData for LuaHashIndex {
+        fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {
+            // add an image (optional crop region {x, y, w, h} for atlas element), return hash
+            _methods.add_method_mut("add", |_, index: &mut Self, (id, img, region): (String, AnyUserData, Option<Vec<u32>>)|{
+                let img = img.borrow::<Image>()?;
+                let hash = match region {
+                    Some(r) if r.len() == 4 => image_hash(&img.crop(r[0], r[1], r[2], r[3]), index.kind),
+                    Some(_) => return Err(LuaError::RuntimeError("region must be {x, y, w, h}".into())),
+                    None => image_hash(&img, index.kind),
+                };
+                index.inner.insert(id, hash);
+                Ok(to_hex(hash))
+            });
+            // add a precomputed hex hash
+            _methods.add_method_mut("add_hash", |_, index: &mut Self, (id, hash): (String, String)|{
+                index.inner.insert(id, from_hex(&hash)?);
+                Ok(())
+            });
+            _methods.add_method("len", |_, index: &Self, ()|{
+                Ok(index.inner.items.len())
+            });
+            // find items similar to a hash, return {{id, distance}}
+            _methods.add_method("query", |lua, index: &Self, (hash, max_distance): (String, Option<u32>)|{
+                let result = lua.create_table()?;
+                let mut items = index.inner.query(from_hex(&hash)?, max_distance.unwrap_or(8));
+                items.sort_by_key(|v| (v.1, v.0));
+                for (i, (item, d)) in items.into_iter().enumerate() {
+                    let t = lua.create_table()?;
+                    t.set("id", index.inner.items[item].0.as_str())?;
+                    t.set("distance", d)?;
+                    result.set(i + 1, t)?;
+                }
+                Ok(result)
+            });
+            // group similar items, return {{ids = {...}, score = 0-1}}
+            _methods.add_method("clusters", |lua, index: &Self, max_distance: Option<u32>|{
+                let result = lua.create_table()?;
+                for (i, (group, score)) in index.inner.clusters(max_distance.unwrap_or(8)).into_iter().enumerate() {
+                    let t = lua.create_table()?;
+                    let ids = lua.create_table()?;
+                    for (k, item) in group.into_iter().enumerate() {
+                        ids.set(k + 1, index.inner.items[item].0.as_str())?;
+                    }
+                    t.set("ids", ids)?;
+                    t.set("score", score)?;
+                    result.set(i + 1, t)?;
+                }
+                Ok(result)
+            });
+        }
+    }
+}
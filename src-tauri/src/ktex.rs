@@ -0,0 +1,476 @@
+// native reader and writer for klei texture file (*.tex)
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::error::Error;
+
+use crate::algorithm::lua_algorithm::{dxt1_decompress, dxt3_decompress, dxt5_decompress, flip_bytes_mut, div_alpha_mut};
+use crate::algorithm::lua_algorithm::{dxt1_compress, dxt5_compress, mult_alpha};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Dxt1 = 0,
+    Dxt3 = 1,
+    Dxt5 = 2,
+    Argb = 4,
+    Rgb = 5,
+    Unknown = 7,
+}
+
+impl PixelFormat {
+    pub fn from_name(s: &str) -> Self {
+        match s {
+            "DXT1" => PixelFormat::Dxt1,
+            "DXT3" => PixelFormat::Dxt3,
+            "DXT5" => PixelFormat::Dxt5,
+            "ARGB" | "RGBA" => PixelFormat::Argb,
+            "RGB" => PixelFormat::Rgb,
+            _ => PixelFormat::Unknown,
+        }
+    }
+
+    fn from_u32(v: u32) -> Self {
+        match v {
+            0 => PixelFormat::Dxt1,
+            1 => PixelFormat::Dxt3,
+            2 => PixelFormat::Dxt5,
+            4 => PixelFormat::Argb,
+            5 => PixelFormat::Rgb,
+            _ => PixelFormat::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PixelFormat::Dxt1 => "DXT1",
+            PixelFormat::Dxt3 => "DXT3",
+            PixelFormat::Dxt5 => "DXT5",
+            PixelFormat::Argb => "ARGB",
+            PixelFormat::Rgb => "RGB",
+            PixelFormat::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// bytes per pixel of decoded mipmap
+    pub fn pixel_size(&self) -> usize {
+        match self {
+            PixelFormat::Rgb => 3,
+            _ => 4,
+        }
+    }
+
+    /// bytes of one row in encoded mipmap (row of 4x4 blocks for DXT)
+    fn pitch(&self, width: usize) -> usize {
+        match self {
+            PixelFormat::Dxt1 => width.div_ceil(4)* 8,
+            PixelFormat::Dxt3 | PixelFormat::Dxt5 => width.div_ceil(4)* 16,
+            _ => width* self.pixel_size(),
+        }
+    }
+}
+
+/// KTEX header, packed in a little endian u32
+/// | platform: 4 | pixelformat: 5 | texturetype: 4 | nummips: 5 | flags: 2 | fill: 12 |
+#[derive(Debug, Clone, Copy)]
+pub struct KTexHeader {
+    pub platform: u8,
+    pub pixel_format: PixelFormat,
+    pub texture_type: u8,
+    pub num_mips: u8,
+    /// header flags (2 bits)
+    pub flags: u8,
+}
+
+impl KTexHeader {
+    fn to_u32(self) -> u32 {
+        (self.platform as u32 & 15) |
+        (self.pixel_format as u32 & 31) << 4 |
+        (self.texture_type as u32 & 15) << 9 |
+        (self.num_mips as u32 & 31) << 13 |
+        (self.flags as u32 & 3) << 18 |
+        0xFFF << 20
+    }
+
+    fn from_u32(header: u32) -> Self {
+        KTexHeader {
+            platform: (header & 15) as u8,
+            pixel_format: PixelFormat::from_u32((header >> 4) & 31),
+            texture_type: ((header >> 9) & 15) as u8,
+            num_mips: ((header >> 13) & 31) as u8,
+            flags: ((header >> 18) & 3) as u8,
+        }
+    }
+
+    pub fn texture_type_str(&self) -> &'static str {
+        match self.texture_type {
+            1 => "1D",
+            2 => "2D",
+            3 => "3D",
+            4 => "CUBEMAP",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn platform_str(&self) -> &'static str {
+        match self.platform {
+            0 => "DEFAULT",
+            10 => "XBOX360",
+            11 => "PS3",
+            12 => "PC",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+pub struct Mipmap {
+    pub width: u32,
+    pub height: u32,
+    pub pitch: u32,
+    data: Vec<u8>,
+}
+
+pub struct KTex {
+    pub header: KTexHeader,
+    pub mipmaps: Vec<Mipmap>,
+}
+
+impl KTex {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let f = File::open(path)?;
+        Self::from_reader(BufReader::new(f))
+    }
+
+    pub fn from_reader(mut f: impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut buf = [0; 4];
+        f.read_exact(&mut buf)?;
+        if &buf != b"KTEX" {
+            return Err("KTEX file sig not satisfied".into());
+        }
+        f.read_exact(&mut buf)?;
+        let header = KTexHeader::from_u32(u32::from_le_bytes(buf));
+        if header.pixel_format == PixelFormat::Unknown {
+            return Err("Unsupported pixelformat".into());
+        }
+        let mut mipmaps = Vec::with_capacity(header.num_mips as usize);
+        let mut sizes = Vec::with_capacity(header.num_mips as usize);
+        for _ in 0..header.num_mips {
+            let mut buf = [0; 10];
+            f.read_exact(&mut buf)?;
+            mipmaps.push(Mipmap {
+                width: u16::from_le_bytes([buf[0], buf[1]]) as u32,
+                height: u16::from_le_bytes([buf[2], buf[3]]) as u32,
+                pitch: u16::from_le_bytes([buf[4], buf[5]]) as u32,
+                data: vec![],
+            });
+            sizes.push(u32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]) as usize);
+        }
+        for (mip, size) in mipmaps.iter_mut().zip(sizes) {
+            let mut data = vec![0; size];
+            f.read_exact(&mut data)?;
+            mip.data = data;
+        }
+        Ok(KTex { header, mipmaps })
+    }
+
+    /// convert lua mip index (starts from 1) to vec index
+    pub fn normalize_mip_index(&self, index: usize) -> usize {
+        index.clamp(1, self.mipmaps.len().max(1)) - 1
+    }
+
+    /// raw (compressed) data of mipmap
+    pub fn get_data(&self, index: usize) -> Option<&[u8]> {
+        self.mipmaps.get(index).map(|m|m.data.as_slice())
+    }
+
+    /// decode mipmap to rgb/rgba bytes
+    pub fn decode(&self, index: usize, flip: bool, div_alpha: bool) -> Result<Vec<u8>, String> {
+        let mip = self.mipmaps.get(index).ok_or("Mipmap index out of range")?;
+        let (width, height) = (mip.width as usize, mip.height as usize);
+        let pixel_size = self.header.pixel_format.pixel_size();
+        // bc decoder writes whole blocks, so decode in padded size and crop
+        let decode_bc = |decompress: fn(&[u8], usize, usize) -> Vec<u8>| {
+            let (pw, ph) = (width.div_ceil(4)* 4, height.div_ceil(4)* 4);
+            let bytes = decompress(&mip.data, pw, ph);
+            if bytes.len() != pw* ph* 4 || (pw == width && ph == height) {
+                bytes
+            }
+            else {
+                bytes.chunks_exact(pw* 4)
+                    .take(height)
+                    .flat_map(|row|&row[..width* 4])
+                    .copied()
+                    .collect()
+            }
+        };
+        let mut bytes = match self.header.pixel_format {
+            PixelFormat::Dxt1 => decode_bc(dxt1_decompress),
+            PixelFormat::Dxt3 => decode_bc(dxt3_decompress),
+            PixelFormat::Dxt5 => decode_bc(dxt5_decompress),
+            PixelFormat::Argb | PixelFormat::Rgb => {
+                let len = width * height * pixel_size;
+                if mip.data.len() < len {
+                    vec![]
+                }
+                else {
+                    mip.data[..len].to_vec()
+                }
+            },
+            PixelFormat::Unknown => return Err("Unsupported pixelformat".into()),
+        };
+        if bytes.len() != width * height * pixel_size {
+            return Err(format!("Failed to decode mipmap: {}x{} {}",
+                width, height, self.header.pixel_format.as_str()));
+        }
+        if flip && height > 0 {
+            flip_bytes_mut(&mut bytes, width * pixel_size);
+        }
+        if div_alpha && pixel_size == 4 {
+            div_alpha_mut(&mut bytes);
+        }
+        Ok(bytes)
+    }
+}
+
+pub struct EncodeOptions {
+    pub pixel_format: PixelFormat,
+    /// generate full mip chain down to 1x1
+    pub mipmap: bool,
+    /// store rows bottom-up, as the game does
+    pub flip: bool,
+    /// premultiply rgb by alpha, as the game does
+    pub premultiply: bool,
+    pub platform: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            pixel_format: PixelFormat::Dxt5,
+            mipmap: true,
+            flip: true,
+            premultiply: true,
+            platform: 0,
+        }
+    }
+}
+
+/// downsample rgba bytes to half size with a 2x2 box filter
+pub fn half_size(bytes: &[u8], width: usize, height: usize) -> (Vec<u8>, usize, usize) {
+    let (w, h) = ((width / 2).max(1), (height / 2).max(1));
+    let mut result = Vec::with_capacity(w* h* 4);
+    for y in 0..h {
+        let (y0, y1) = ((y* 2).min(height - 1), (y* 2 + 1).min(height - 1));
+        for x in 0..w {
+            let (x0, x1) = ((x* 2).min(width - 1), (x* 2 + 1).min(width - 1));
+            for c in 0..4 {
+                let sum = bytes[(y0* width + x0)* 4 + c] as u32 +
+                    bytes[(y0* width + x1)* 4 + c] as u32 +
+                    bytes[(y1* width + x0)* 4 + c] as u32 +
+                    bytes[(y1* width + x1)* 4 + c] as u32;
+                result.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+    (result, w, h)
+}
+
+impl KTex {
+    /// encode rgba bytes to KTEX file
+    pub fn encode(bytes: &[u8], width: u32, height: u32, options: &EncodeOptions) -> Result<Vec<u8>, String> {
+        let (width, height) = (width as usize, height as usize);
+        if bytes.len() != width* height* 4 {
+            return Err(format!("Invalid rgba bytes length: {}, expected {}x{}x4", bytes.len(), width, height));
+        }
+        if width == 0 || height == 0 || width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(format!("Invalid texture size: {}x{}", width, height));
+        }
+        let pixel_format = options.pixel_format;
+        match pixel_format {
+            PixelFormat::Dxt1 | PixelFormat::Dxt5 | PixelFormat::Argb | PixelFormat::Rgb => (),
+            other => return Err(format!("Unsupported pixelformat for encoding: {}", other.as_str())),
+        };
+
+        // build mip chain, alpha is dropped by rgb format so colors are kept straight
+        let premultiply = options.premultiply && pixel_format != PixelFormat::Rgb;
+        let mut level = (if premultiply { mult_alpha(bytes) } else { bytes.to_vec() }, width, height);
+        let mut levels = vec![];
+        loop {
+            let next = if options.mipmap && (level.1 > 1 || level.2 > 1) {
+                Some(half_size(&level.0, level.1, level.2))
+            }
+            else {
+                None
+            };
+            levels.push(level);
+            match next {
+                Some(next)=> level = next,
+                None=> break,
+            }
+        }
+
+        let mut mipmaps = Vec::with_capacity(levels.len());
+        for (mut data, w, h) in levels {
+            if options.flip {
+                flip_bytes_mut(&mut data, w* 4);
+            }
+            let data = match pixel_format {
+                PixelFormat::Dxt1 => dxt1_compress(&data, w, h),
+                PixelFormat::Dxt5 => dxt5_compress(&data, w, h),
+                PixelFormat::Rgb => data.chunks_exact(4).flat_map(|c|[c[0], c[1], c[2]]).collect(),
+                _ => data,
+            };
+            mipmaps.push(Mipmap {
+                width: w as u32,
+                height: h as u32,
+                pitch: pixel_format.pitch(w) as u32,
+                data,
+            });
+        }
+
+        let header = KTexHeader {
+            platform: options.platform,
+            pixel_format,
+            texture_type: 2,
+            num_mips: mipmaps.len() as u8,
+            flags: 0,
+        };
+        KTex { header, mipmaps }.to_bytes()
+    }
+
+    /// serialize to KTEX file bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut result = Vec::with_capacity(8 + self.mipmaps.iter().map(|m|10 + m.data.len()).sum::<usize>());
+        result.extend_from_slice(b"KTEX");
+        result.extend_from_slice(&self.header.to_u32().to_le_bytes());
+        for mip in self.mipmaps.iter() {
+            if mip.pitch > u16::MAX as u32 {
+                return Err(format!("Mipmap pitch overflow: {}", mip.pitch));
+            }
+            result.extend_from_slice(&(mip.width as u16).to_le_bytes());
+            result.extend_from_slice(&(mip.height as u16).to_le_bytes());
+            result.extend_from_slice(&(mip.pitch as u16).to_le_bytes());
+            result.extend_from_slice(&(mip.data.len() as u32).to_le_bytes());
+        }
+        for mip in self.mipmaps.iter() {
+            result.extend_from_slice(&mip.data);
+        }
+        Ok(result)
+    }
+}
+
+#[test]
+fn check_encode() {
+    let (width, height) = (13, 6);
+    let bytes = (0..width* height).flat_map(|i| if i % 3 == 0 { [0, 0, 0, 0] } else { [200, 100, 50, 255] }).collect::<Vec<u8>>();
+    for format in ["DXT1", "DXT5", "RGB", "ARGB"] {
+        let options = EncodeOptions { pixel_format: PixelFormat::from_name(format), ..Default::default() };
+        let file = KTex::encode(&bytes, width as u32, height as u32, &options).unwrap();
+        let tex = KTex::from_reader(file.as_slice()).unwrap();
+        assert_eq!(tex.header.pixel_format.as_str(), format);
+        assert_eq!(tex.mipmaps.len(), 4); // 13x6 -> 6x3 -> 3x1 -> 1x1
+        let decoded = tex.decode(0, true, true).unwrap();
+        let pixel_size = tex.header.pixel_format.pixel_size();
+        for (i, c) in decoded.chunks_exact(pixel_size).enumerate() {
+            if i % 3 != 0 {
+                assert!(c[0].abs_diff(200) < 8 && c[1].abs_diff(100) < 8 && c[2].abs_diff(50) < 8);
+            }
+            else if pixel_size == 4 {
+                assert_eq!(c[3], 0);
+            }
+        }
+    }
+    // semi-transparent pixels are not darkened in rgb format
+    let options = EncodeOptions { pixel_format: PixelFormat::Rgb, mipmap: false, ..Default::default() };
+    let file = KTex::encode(&[200, 100, 50, 128].repeat(4), 2, 2, &options).unwrap();
+    let decoded = KTex::from_reader(file.as_slice()).unwrap().decode(0, true, false).unwrap();
+    assert_eq!(decoded, [200, 100, 50].repeat(4));
+}
+
+pub mod lua_ktex {
+    use super::*;
+    use rlua::prelude::{LuaResult, LuaError};
+    use rlua::{MetaMethod, Table, UserData, UserDataMethods, Variadic};
+    use crate::image::lua_image::Image;
+
+    impl UserData for KTex {
+        fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {
+            // return header fields as table
+            _methods.add_method("info", |lua, tex: &Self, ()|{
+                let info = lua.create_table()?;
+                info.set("platform", tex.header.platform_str())?;
+                info.set("pixelformat", tex.header.pixel_format.as_str())?;
+                info.set("texturetype", tex.header.texture_type_str())?;
+                info.set("nummips", tex.header.num_mips)?;
+                info.set("flags", tex.header.flags)?;
+                Ok(info)
+            });
+            _methods.add_method("nummips", |_, tex: &Self, ()|{
+                Ok(tex.mipmaps.len())
+            });
+            // return width, height of mipmap (index starts from 1)
+            _methods.add_method("size", |_, tex: &Self, index: Option<usize>|{
+                let i = tex.normalize_mip_index(index.unwrap_or(1));
+                match tex.mipmaps.get(i) {
+                    Some(m)=> Ok(Variadic::from_iter([m.width, m.height])),
+                    None=> Err(LuaError::RuntimeError("Texture has no mipmap".into()))
+                }
+            });
+            // return raw data of mipmap
+            _methods.add_method("data", |lua, tex: &Self, index: Option<usize>|{
+                let i = tex.normalize_mip_index(index.unwrap_or(1));
+                tex.get_data(i).map(|data|lua.create_string(data)).transpose()
+            });
+            // decode mipmap to Image
+            //   flip_y     default true
+            //   div_alpha  default true
+            _methods.add_method("image", |_, tex: &Self, (index, options): (Option<usize>, Option<Table>)|{
+                let (flip, div) = match options {
+                    Some(options)=> (
+                        options.get::<_, Option<bool>>("flip_y")?.unwrap_or(true),
+                        options.get::<_, Option<bool>>("div_alpha")?.unwrap_or(true),
+                    ),
+                    None=> (true, true),
+                };
+                let i = tex.normalize_mip_index(index.unwrap_or(1));
+                let bytes = tex.decode(i, flip, div).map_err(LuaError::RuntimeError)?;
+                let mip = &tex.mipmaps[i];
+                match tex.header.pixel_format {
+                    PixelFormat::Rgb => Ok(Image::from_rgb(bytes, mip.width, mip.height)),
+                    _ => Ok(Image::from_rgba(bytes, mip.width, mip.height)),
+                }
+            });
+            _methods.add_meta_method(MetaMethod::ToString, |_, tex: &Self, ()|{
+                Ok(format!("Tex<{} nummips={}>", tex.header.pixel_format.as_str(), tex.mipmaps.len()))
+            });
+        }
+    }
+
+    /// read encode options from lua table
+    ///   format     DXT1|DXT5|RGB|ARGB (default DXT5)
+    ///   mipmap     default true
+    ///   flip_y     default true
+    ///   mult_alpha default true
+    pub fn get_encode_options(options: Option<Table>) -> LuaResult<EncodeOptions> {
+        let mut result = EncodeOptions::default();
+        if let Some(options) = options {
+            if let Some(format) = options.get::<_, Option<String>>("format")? {
+                result.pixel_format = PixelFormat::from_name(format.to_uppercase().as_str());
+                if result.pixel_format == PixelFormat::Unknown {
+                    return Err(LuaError::RuntimeError(format!("Unknown format: {}", format)));
+                }
+            }
+            result.mipmap = options.get::<_, Option<bool>>("mipmap")?.unwrap_or(result.mipmap);
+            result.flip = options.get::<_, Option<bool>>("flip_y")?.unwrap_or(result.flip);
+            result.premultiply = options.get::<_, Option<bool>>("mult_alpha")?.unwrap_or(result.premultiply);
+            result.platform = options.get::<_, Option<u8>>("platform")?.unwrap_or(result.platform);
+        }
+        Ok(result)
+    }
+
+    pub fn open_tex(path: &str) -> LuaResult<KTex> {
+        KTex::open(path).map_err(|e|LuaError::RuntimeError(format!("Failed to open tex: {}", e)))
+    }
+
+    pub fn load_tex(bytes: &[u8]) -> LuaResult<KTex> {
+        KTex::from_reader(bytes).map_err(|e|LuaError::RuntimeError(format!("Failed to load tex: {}", e)))
+    }
+}
@@ -26,6 +26,16 @@ use tauri::{Manager, Emitter};
 extern crate json;
 
 mod image;
+mod ktex;
+mod dds;
+mod atlas;
+mod animwriter;
+mod animrender;
+mod spritesheet;
+mod animbin;
+mod scml;
+mod colorcube;
+mod imagehash;
 mod filesystem;
 mod algorithm;
 mod misc;
@@ -366,6 +376,8 @@ fn lua_call<R: tauri::Runtime>(app: tauri::AppHandle<R>, _window: tauri::Window<
                     e
                 }
             },
+            // interrupted in native worker pool (wrapped by callback error)
+            other if other.to_string().contains("IPC_INTERRUPTED") => "IPC_INTERRUPTED".to_string(),
             other=> other.to_string(),
         }
     )
@@ -472,6 +484,7 @@ fn init_lua_impl(handle: tauri::AppHandle, state: tauri::State<'_, LuaEnv>) -> L
         fastindex::lua_fastindex::init(lua_ctx).unwrap_or_else(init_error("fastindex"));
         fmodparse::lua_fmodparse::init(lua_ctx).unwrap_or_else(init_error("fmodparse"));
         quicklook::lua_quicklook::init(lua_ctx).unwrap_or_else(init_error("quicklook"));
+        scml::lua_scml::init(lua_ctx).unwrap_or_else(init_error("scml"));
 
         info!("[LUA] remove default loaders");
 
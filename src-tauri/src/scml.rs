@@ -0,0 +1,1144 @@
+// spriter project (.scml) decompiler for anim zip, and compiler from scml to anim zip
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{Read, Write as _};
+use std::path::{Path, PathBuf};
+
+use zip::{ZipArchive, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::algorithm::lua_algorithm::kleihash;
+use crate::animbin::{resolve_hash, Anim, AnimBin, BuildBin, BuildImage, Element, Frame, Symbol, Vertex};
+use crate::atlas::{self, escape_xml, PackOptions};
+use crate::fastindex::HashTable;
+use crate::image::{FitMode, Resampler};
+use crate::image::lua_image::Image;
+use crate::ktex::{EncodeOptions, KTex};
+
+/// facing postfix of animation name, see facing.lua `Facing.SCML_ALIAS`
+const FACING_ALIAS: [(&str, u8); 13] = [
+    ("up", 2),
+    ("down", 8),
+    ("side", 1 + 4),
+    ("left", 4),
+    ("right", 1),
+    ("upside", 16 + 32),
+    ("downside", 64 + 128),
+    ("upleft", 32),
+    ("upright", 16),
+    ("downleft", 128),
+    ("downright", 64),
+    ("45s", 16 + 32 + 64 + 128),
+    ("90s", 2 + 8 + 1 + 4),
+];
+
+const FACING_ALL: u8 = 255;
+
+/// padding of atlas elements, 4x4 block size of mip level 1
+const ATLAS_PADDING: u32 = 8;
+
+fn fmt_num(v: f64) -> String {
+    let s = format!("{:.6}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".into() } else { s.into() }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlFile {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub pivot_x: f64,
+    pub pivot_y: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlFolder {
+    pub name: String,
+    pub files: Vec<ScmlFile>,
+}
+
+/// sprite state of a timeline key, in spriter space (y up, angle in degrees)
+/// bone keys use the same struct, folder and file are ignored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScmlObject {
+    pub folder: usize,
+    pub file: usize,
+    pub x: f64,
+    pub y: f64,
+    pub angle: f64,
+    pub scale_x: f64,
+    pub scale_y: f64,
+    /// override pivot of file
+    pub pivot: Option<(f64, f64)>,
+}
+
+impl ScmlObject {
+    /// convert anim.bin matrix (y down) to spriter transform, skew is dropped
+    pub fn from_matrix(folder: usize, file: usize, matrix: &[f32; 6]) -> Self {
+        let [a, b, c, d, tx, ty] = matrix.map(|v| v as f64);
+        let det = a* d - b* c;
+        let scale_x = a.hypot(b);
+        let (angle, scale_y) = if scale_x < 1e-6 {
+            (0.0, d)
+        }
+        else {
+            ((-b).atan2(a).to_degrees().rem_euclid(360.0), det / scale_x)
+        };
+        ScmlObject { folder, file, x: tx, y: -ty, angle, scale_x, scale_y, pivot: None }
+    }
+
+    /// check if self is the linear interpolation of prev and next
+    fn is_lerp_of(&self, prev: &Self, next: &Self, t: f64) -> bool {
+        if self.folder != prev.folder || self.file != prev.file
+            || self.folder != next.folder || self.file != next.file {
+            return false;
+        }
+        let lerp = |a: f64, b: f64| a + (b - a)* t;
+        let near = |a: f64, b: f64, eps: f64| (a - b).abs() < eps;
+        let delta = |a: f64, b: f64| (b - a + 180.0).rem_euclid(360.0) - 180.0;
+        near(self.x, lerp(prev.x, next.x), 0.01)
+            && near(self.y, lerp(prev.y, next.y), 0.01)
+            && near(self.scale_x, lerp(prev.scale_x, next.scale_x), 1e-4)
+            && near(self.scale_y, lerp(prev.scale_y, next.scale_y), 1e-4)
+            && near(delta(prev.angle + delta(prev.angle, next.angle)* t, self.angle), 0.0, 0.01)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlTimelineKey {
+    pub time: u32,
+    /// 1: counter-clockwise, -1: clockwise, 0: no rotation
+    pub spin: i32,
+    /// curve_type="instant", no interpolation to next key
+    pub instant: bool,
+    pub object: ScmlObject,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlTimeline {
+    pub name: String,
+    pub keys: Vec<ScmlTimelineKey>,
+}
+
+/// object_ref or bone_ref of mainline key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScmlObjectRef {
+    pub timeline: usize,
+    pub key: usize,
+    pub z_index: usize,
+    /// index of parent bone_ref
+    pub parent: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlMainlineKey {
+    pub time: u32,
+    pub bone_refs: Vec<ScmlObjectRef>,
+    pub object_refs: Vec<ScmlObjectRef>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlAnimation {
+    pub name: String,
+    pub length: u32,
+    pub interval: u32,
+    pub looping: bool,
+    pub mainline: Vec<ScmlMainlineKey>,
+    pub timelines: Vec<ScmlTimeline>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScmlEntity {
+    pub name: String,
+    pub animations: Vec<ScmlAnimation>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScmlProject {
+    pub folders: Vec<ScmlFolder>,
+    pub entities: Vec<ScmlEntity>,
+}
+
+impl ScmlProject {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::with_capacity(4096);
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<spriter_data scml_version=\"1.0\" generator=\"BrashMonkey Spriter\" generator_version=\"r11\">\n");
+        for (i, folder) in self.folders.iter().enumerate() {
+            writeln!(xml, "    <folder id=\"{}\" name=\"{}\">", i, escape_xml(&folder.name)).unwrap();
+            for (j, file) in folder.files.iter().enumerate() {
+                writeln!(xml, "        <file id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\" pivot_x=\"{}\" pivot_y=\"{}\"/>",
+                    j, escape_xml(&file.name), file.width, file.height, fmt_num(file.pivot_x), fmt_num(file.pivot_y)).unwrap();
+            }
+            xml.push_str("    </folder>\n");
+        }
+        for (i, entity) in self.entities.iter().enumerate() {
+            writeln!(xml, "    <entity id=\"{}\" name=\"{}\">", i, escape_xml(&entity.name)).unwrap();
+            for (j, anim) in entity.animations.iter().enumerate() {
+                writeln!(xml, "        <animation id=\"{}\" name=\"{}\" length=\"{}\" interval=\"{}\"{}>",
+                    j, escape_xml(&anim.name), anim.length, anim.interval,
+                    if anim.looping { "" } else { " looping=\"false\"" }).unwrap();
+                xml.push_str("            <mainline>\n");
+                for (k, key) in anim.mainline.iter().enumerate() {
+                    writeln!(xml, "                <key id=\"{}\" time=\"{}\">", k, key.time).unwrap();
+                    for (n, r) in key.object_refs.iter().enumerate() {
+                        writeln!(xml, "                    <object_ref id=\"{}\" timeline=\"{}\" key=\"{}\" z_index=\"{}\"/>",
+                            n, r.timeline, r.key, r.z_index).unwrap();
+                    }
+                    xml.push_str("                </key>\n");
+                }
+                xml.push_str("            </mainline>\n");
+                for (k, timeline) in anim.timelines.iter().enumerate() {
+                    writeln!(xml, "            <timeline id=\"{}\" name=\"{}\">", k, escape_xml(&timeline.name)).unwrap();
+                    for (n, key) in timeline.keys.iter().enumerate() {
+                        let o = &key.object;
+                        writeln!(xml, "                <key id=\"{}\" time=\"{}\" spin=\"{}\">", n, key.time, key.spin).unwrap();
+                        writeln!(xml, "                    <object folder=\"{}\" file=\"{}\" x=\"{}\" y=\"{}\" angle=\"{}\" scale_x=\"{}\" scale_y=\"{}\"/>",
+                            o.folder, o.file, fmt_num(o.x), fmt_num(o.y), fmt_num(o.angle), fmt_num(o.scale_x), fmt_num(o.scale_y)).unwrap();
+                        xml.push_str("                </key>\n");
+                    }
+                    xml.push_str("            </timeline>\n");
+                }
+                xml.push_str("        </animation>\n");
+            }
+            xml.push_str("    </entity>\n");
+        }
+        xml.push_str("</spriter_data>\n");
+        xml
+    }
+}
+
+/// name of animation in spriter, facing is added as postfix
+fn anim_name(anim: &Anim) -> String {
+    if anim.facing == FACING_ALL {
+        return anim.name.clone();
+    }
+    match FACING_ALIAS.iter().find(|(_, v)| *v == anim.facing) {
+        Some((alias, _)) => format!("{}_{}", anim.name, alias),
+        None => format!("{}_{}", anim.name, anim.facing),
+    }
+}
+
+/// draw build image from atlas quads, return image in source resolution
+fn extract_image(img: &BuildImage, atlases: &[Option<Image>]) -> Result<Image, String> {
+    let width = (img.w.round() as u32).max(1);
+    let height = (img.h.round() as u32).max(1);
+    let mut canvas = Image::from_rgba(vec![0; (width* height* 4) as usize], width, height)
+        .ok_or("Failed to create image")?;
+    let (ox, oy) = (img.x - img.w / 2.0, img.y - img.h / 2.0);
+    for quad in img.vertices.chunks_exact(6) {
+        let sampler = quad[0][5].round() as usize;
+        let atlas = match atlases.get(sampler) {
+            Some(Some(atlas)) => atlas,
+            _ => return Err(format!("Atlas not found: {}", sampler)),
+        };
+        let (aw, ah) = (atlas.width as f32, atlas.height as f32);
+        let bound = |i: usize| quad.iter().map(|v| v[i]).fold((f32::MAX, f32::MIN), |(a, b), v| (a.min(v), b.max(v)));
+        let ((x0, x1), (y0, y1), (u0, u1), (v0, v1)) = (bound(0), bound(1), bound(3), bound(4));
+        // v = 1 is the first row of flipped texture
+        let sx = (u0* aw).round().clamp(0.0, aw) as u32;
+        let sy = ((1.0 - v1)* ah).round().clamp(0.0, ah) as u32;
+        let sw = ((u1* aw).round().clamp(0.0, aw) as u32).saturating_sub(sx);
+        let sh = (((1.0 - v0)* ah).round().clamp(0.0, ah) as u32).saturating_sub(sy);
+        let dw = ((x1 - x0).round() as u32).max(1);
+        let dh = ((y1 - y0).round() as u32).max(1);
+        if sw == 0 || sh == 0 {
+            continue;
+        }
+        let mut part = atlas.crop(sx, sy, sw, sh);
+        if sw != dw || sh != dh {
+            part = part.resize(dw, dh, Resampler::Lanczos3, FitMode::Exact);
+        }
+        let dx = ((x0 - ox).round().max(0.0) as u32).min(width - 1);
+        let dy = ((y0 - oy).round().max(0.0) as u32).min(height - 1);
+        if dx + part.width > width || dy + part.height > height {
+            part = part.crop(0, 0, part.width.min(width - dx), part.height.min(height - dy));
+        }
+        canvas.copy_from(&part, dx, dy);
+    }
+    Ok(canvas)
+}
+
+/// convert one anim to spriter animation
+/// `find_file` returns (folder, file) of element image
+fn convert_anim(anim: &Anim, name: String, hash_table: &HashMap<u32, Vec<u8>>,
+    find_file: &dyn Fn(&Element) -> Option<(usize, usize)>) -> ScmlAnimation {
+    let interval = 1000.0 / (anim.framerate as f64).max(1.0);
+    let time = |i: usize| (i as f64* interval).round() as u32;
+    // timeline is identified by layer, symbol and occurrence in frame
+    let mut timeline_index = HashMap::<(u32, u32, usize), usize>::new();
+    let mut timelines = Vec::<(ScmlTimeline, Vec<(usize, ScmlObject)>)>::new();
+    let mut frame_refs = Vec::with_capacity(anim.frames.len());
+    for (i, frame) in anim.frames.iter().enumerate() {
+        let mut count = HashMap::<(u32, u32), usize>::new();
+        let mut refs = vec![];
+        for element in frame.elements.iter() {
+            let n = count.entry((element.layerhash, element.imghash)).or_default();
+            let key = (element.layerhash, element.imghash, *n);
+            *n += 1;
+            let (folder, file) = match find_file(element) {
+                Some(v) => v,
+                None => continue,
+            };
+            let id = *timeline_index.entry(key).or_insert_with(|| {
+                timelines.push((ScmlTimeline {
+                    name: resolve_hash(hash_table, element.layerhash),
+                    keys: vec![],
+                }, vec![]));
+                timelines.len() - 1
+            });
+            timelines[id].1.push((i, ScmlObject::from_matrix(folder, file, &element.matrix)));
+            refs.push((id, element.z));
+        }
+        // smaller z is at front, spriter draws larger z_index at front
+        refs.sort_by(|a, b| b.1.total_cmp(&a.1));
+        frame_refs.push(refs);
+    }
+    // drop keys that can be interpolated from neighbours
+    let mut key_of_frame = vec![HashMap::<usize, usize>::new(); anim.frames.len()];
+    for (id, (timeline, states)) in timelines.iter_mut().enumerate() {
+        let mut last_kept: Option<usize> = None;
+        for (n, (frame, object)) in states.iter().enumerate() {
+            let redundant = match (last_kept, states.get(n + 1)) {
+                (Some(prev), Some((nf, no))) => {
+                    let (pf, po) = &states[prev];
+                    // only across continuous frames, and all dropped keys still match
+                    *pf + (n + 1 - prev) == *nf && (prev + 1..=n).all(|m| {
+                        let (mf, mo) = &states[m];
+                        mo.is_lerp_of(po, no, (mf - pf) as f64 / (nf - pf) as f64)
+                    })
+                },
+                _ => false,
+            };
+            if !redundant {
+                timeline.keys.push(ScmlTimelineKey { time: time(*frame), spin: 1, instant: false, object: *object });
+                last_kept = Some(n);
+            }
+            key_of_frame[*frame].insert(id, timeline.keys.len() - 1);
+        }
+        // rotate along the shorter arc to next key
+        for n in 1..timeline.keys.len() {
+            let delta = (timeline.keys[n].object.angle - timeline.keys[n - 1].object.angle).rem_euclid(360.0);
+            timeline.keys[n - 1].spin = if delta > 180.0 { -1 } else { 1 };
+        }
+    }
+    let mut mainline = Vec::<ScmlMainlineKey>::new();
+    for (i, refs) in frame_refs.into_iter().enumerate() {
+        let object_refs = refs.into_iter().enumerate().map(|(z, (id, _))| ScmlObjectRef {
+            timeline: id,
+            key: key_of_frame[i][&id],
+            z_index: z,
+            parent: None,
+        }).collect::<Vec<_>>();
+        // nothing changed since last key
+        if mainline.last().is_some_and(|k| k.object_refs == object_refs) {
+            continue;
+        }
+        mainline.push(ScmlMainlineKey { time: time(i), bone_refs: vec![], object_refs });
+    }
+    ScmlAnimation {
+        name,
+        length: time(anim.frames.len()).max(1),
+        interval: interval.round() as u32,
+        looping: true,
+        mainline,
+        timelines: timelines.into_iter().map(|t| t.0).collect(),
+    }
+}
+
+/// decompile anim.bin and build.bin to spriter project, images are saved to `output`
+/// `atlases` is the decoded textures of build
+pub fn decompile(anim: Option<&AnimBin>, build: Option<&BuildBin>, atlases: &[Option<Image>], output: &Path) -> Result<ScmlProject, String> {
+    let mut project = ScmlProject::default();
+    // (imghash, image index) -> (folder, file)
+    let mut files = HashMap::<(u32, u32), (usize, usize)>::new();
+    if let Some(build) = build {
+        for symbol in build.symbols.iter() {
+            let name = resolve_hash(&build.hash_table, symbol.imghash);
+            let dirname = filenamify::filenamify(&name);
+            std::fs::create_dir_all(output.join(&dirname))
+                .map_err(|e| format!("Failed to create dir: {}", e))?;
+            let mut folder = ScmlFolder { name: dirname.clone(), files: vec![] };
+            for img in symbol.images.iter() {
+                let filename = format!("{}/{}-{}.png", dirname, dirname, img.index);
+                let image = extract_image(img, atlases)?;
+                image.save(output.join(&filename).to_string_lossy().as_ref(), &[])
+                    .map_err(|e| format!("Failed to save image: {}: {}", filename, e))?;
+                let (w, h) = (img.w.max(1.0) as f64, img.h.max(1.0) as f64);
+                folder.files.push(ScmlFile {
+                    name: filename,
+                    width: image.width,
+                    height: image.height,
+                    pivot_x: 0.5 - img.x as f64 / w,
+                    pivot_y: 0.5 + img.y as f64 / h,
+                });
+                files.insert((symbol.imghash, img.index), (project.folders.len(), folder.files.len() - 1));
+            }
+            project.folders.push(folder);
+        }
+    }
+    let Some(anim) = anim else {
+        return Ok(project);
+    };
+    let find_file = |element: &Element| {
+        let symbol = build?.symbols.iter().find(|s| s.imghash == element.imghash)?;
+        let img = symbol.find_image(element.imgindex)?;
+        files.get(&(element.imghash, img.index)).copied()
+    };
+    let hash_table = anim.hash_table.iter()
+        .chain(build.map(|b| b.hash_table.iter()).into_iter().flatten())
+        .map(|(k, v)| (*k, v.clone()))
+        .collect::<HashMap<_, _>>();
+    // group by bank
+    let mut entity_index = HashMap::<u32, usize>::new();
+    let mut names = HashMap::<(u32, String), usize>::new();
+    for a in anim.anims.iter() {
+        let id = *entity_index.entry(a.bankhash).or_insert_with(|| {
+            project.entities.push(ScmlEntity {
+                name: resolve_hash(&anim.hash_table, a.bankhash),
+                animations: vec![],
+            });
+            project.entities.len() - 1
+        });
+        let mut name = anim_name(a);
+        let n = names.entry((a.bankhash, name.clone())).or_default();
+        *n += 1;
+        if *n > 1 {
+            name = format!("{}_{}", name, n);
+        }
+        project.entities[id].animations.push(convert_anim(a, name, &hash_table, &find_file));
+    }
+    Ok(project)
+}
+
+/// decompile anim zip files (anim.bin / build.bin / atlas) to `output` directory, return path of scml
+pub fn decompile_zip(paths: &[PathBuf], output: &Path) -> Result<PathBuf, String> {
+    let mut anim = None;
+    let mut build = None;
+    let mut entries = HashMap::<String, Vec<u8>>::new();
+    for path in paths {
+        let f = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut archive = ZipArchive::new(f).map_err(|e| format!("Failed to read zip file: {}", e))?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+            match file.name() {
+                "anim.bin" => anim = Some(AnimBin::from_bytes(&bytes)
+                    .map_err(|e| format!("Failed to parse anim.bin: {}", e))?),
+                "build.bin" => build = Some(BuildBin::from_bytes(&bytes)
+                    .map_err(|e| format!("Failed to parse build.bin: {}", e))?),
+                name => { entries.insert(name.to_string(), bytes); },
+            }
+        }
+    }
+    if anim.is_none() && build.is_none() {
+        return Err("anim.bin and build.bin not found".into());
+    }
+    let mut atlases = vec![];
+    for name in build.iter().flat_map(|b| b.atlases.iter()) {
+        atlases.push(match entries.get(name) {
+            Some(bytes) => {
+                let tex = KTex::from_reader(bytes.as_slice())
+                    .map_err(|e| format!("Failed to load atlas {}: {}", name, e))?;
+                let (w, h) = tex.mipmaps.first().map(|m| (m.width, m.height))
+                    .ok_or_else(|| format!("Atlas has no mipmap: {}", name))?;
+                // same as Bc_Decompress in assetloader.lua, flipped and unpremultiplied
+                Image::from_rgba(tex.decode(0, true, true)?, w, h)
+            },
+            None => None,
+        });
+    }
+    std::fs::create_dir_all(output).map_err(|e| format!("Failed to create dir: {}", e))?;
+    let project = decompile(anim.as_ref(), build.as_ref(), &atlases, output)?;
+    let stem = match (&build, paths.first()) {
+        (Some(build), _) => build.name.clone(),
+        (None, Some(path)) => path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+        _ => "anim".into(),
+    };
+    let path = output.join(format!("{}.scml", filenamify::filenamify(stem)));
+    std::fs::write(&path, project.to_xml()).map_err(|e| format!("Failed to write scml: {}", e))?;
+    Ok(path)
+}
+
+/// minimal xml tree, only elements and attributes are kept
+#[derive(Debug, Default)]
+struct XmlNode {
+    tag: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<XmlNode>,
+}
+
+impl XmlNode {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn parse_attr<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.attr(key).and_then(|v| v.trim().parse().ok())
+    }
+
+    fn children<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a XmlNode> {
+        self.children.iter().filter(move |n| n.tag == tag)
+    }
+
+    fn child<'a>(&'a self, tag: &'a str) -> Option<&'a XmlNode> {
+        self.children(tag).next()
+    }
+
+    /// ids are used as index in spriter, sort children by id
+    fn children_by_id<'a>(&'a self, tag: &'a str) -> Vec<&'a XmlNode> {
+        let mut nodes = self.children(tag).collect::<Vec<_>>();
+        nodes.sort_by_key(|n| n.parse_attr::<usize>("id").unwrap_or(usize::MAX));
+        nodes
+    }
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_xml(s: &str) -> Result<XmlNode, String> {
+    let mut stack = vec![XmlNode::default()];
+    let mut rest = s;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        // skip prolog, comment and doctype
+        let skip = [("<?", "?>"), ("<!--", "-->"), ("<!", ">")].iter()
+            .find(|(open, _)| rest.starts_with(open))
+            .map(|(_, close)| rest.find(close).map(|i| i + close.len()).unwrap_or(rest.len()));
+        if let Some(end) = skip {
+            rest = &rest[end..];
+            continue;
+        }
+        let end = rest.find('>').ok_or("Unclosed xml tag")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if let Some(name) = tag.strip_prefix('/') {
+            let node = stack.pop().filter(|_| !stack.is_empty()).ok_or("Unexpected closing tag")?;
+            if node.tag != name.trim() {
+                return Err(format!("Mismatched closing tag: <{}> </{}>", node.tag, name.trim()));
+            }
+            stack.last_mut().unwrap().children.push(node);
+            continue;
+        }
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        let mut node = XmlNode { tag: tag[..name_end].to_string(), ..Default::default() };
+        let mut attrs = &tag[name_end..];
+        while let Some(eq) = attrs.find('=') {
+            let key = attrs[..eq].trim().to_string();
+            let value = attrs[eq + 1..].trim_start();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')
+                .ok_or_else(|| format!("Invalid attribute in <{}>", node.tag))?;
+            let close = value[1..].find(quote).ok_or_else(|| format!("Unclosed attribute in <{}>", node.tag))?;
+            node.attrs.push((key, unescape_xml(&value[1..close + 1])));
+            attrs = &value[close + 2..];
+        }
+        if self_closing {
+            stack.last_mut().unwrap().children.push(node);
+        }
+        else {
+            stack.push(node);
+        }
+    }
+    if stack.len() != 1 {
+        return Err(format!("Unclosed xml tag: <{}>", stack.last().unwrap().tag));
+    }
+    stack.pop().unwrap().children.into_iter().next().ok_or_else(|| "Empty xml document".into())
+}
+
+impl ScmlProject {
+    pub fn from_xml(s: &str) -> Result<Self, String> {
+        let root = parse_xml(s)?;
+        if root.tag != "spriter_data" {
+            return Err(format!("Invalid scml root: <{}>", root.tag));
+        }
+        let mut project = ScmlProject::default();
+        for folder in root.children_by_id("folder") {
+            project.folders.push(ScmlFolder {
+                name: folder.attr("name").unwrap_or_default().to_string(),
+                files: folder.children_by_id("file").into_iter().map(|f| ScmlFile {
+                    name: f.attr("name").unwrap_or_default().replace('\\', "/"),
+                    width: f.parse_attr("width").unwrap_or(0),
+                    height: f.parse_attr("height").unwrap_or(0),
+                    pivot_x: f.parse_attr("pivot_x").unwrap_or(0.0),
+                    pivot_y: f.parse_attr("pivot_y").unwrap_or(1.0),
+                }).collect(),
+            });
+        }
+        let parse_ref = |r: &XmlNode| ScmlObjectRef {
+            timeline: r.parse_attr("timeline").unwrap_or(0),
+            key: r.parse_attr("key").unwrap_or(0),
+            z_index: r.parse_attr("z_index").unwrap_or(0),
+            parent: r.parse_attr("parent"),
+        };
+        for entity in root.children_by_id("entity") {
+            let mut animations = vec![];
+            for anim in entity.children_by_id("animation") {
+                let mainline = anim.child("mainline").map(|m| m.children_by_id("key"))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|key| ScmlMainlineKey {
+                        time: key.parse_attr("time").unwrap_or(0),
+                        bone_refs: key.children_by_id("bone_ref").into_iter().map(parse_ref).collect(),
+                        object_refs: key.children_by_id("object_ref").into_iter().map(parse_ref).collect(),
+                    })
+                    .collect();
+                let timelines = anim.children_by_id("timeline").into_iter().map(|timeline| ScmlTimeline {
+                    name: timeline.attr("name").unwrap_or_default().to_string(),
+                    keys: timeline.children_by_id("key").into_iter().map(|key| {
+                        let o = key.child("object").or_else(|| key.child("bone"));
+                        let get = |k: &str, default: f64| o.and_then(|o| o.parse_attr(k)).unwrap_or(default);
+                        let pivot = o.and_then(|o| match (o.parse_attr("pivot_x"), o.parse_attr("pivot_y")) {
+                            (None, None) => None,
+                            (x, y) => Some((x.unwrap_or(0.0), y.unwrap_or(1.0))),
+                        });
+                        ScmlTimelineKey {
+                            time: key.parse_attr("time").unwrap_or(0),
+                            spin: key.parse_attr("spin").unwrap_or(1),
+                            instant: key.attr("curve_type") == Some("instant"),
+                            object: ScmlObject {
+                                folder: o.and_then(|o| o.parse_attr("folder")).unwrap_or(0),
+                                file: o.and_then(|o| o.parse_attr("file")).unwrap_or(0),
+                                x: get("x", 0.0),
+                                y: get("y", 0.0),
+                                angle: get("angle", 0.0),
+                                scale_x: get("scale_x", 1.0),
+                                scale_y: get("scale_y", 1.0),
+                                pivot,
+                            },
+                        }
+                    }).collect(),
+                }).collect();
+                animations.push(ScmlAnimation {
+                    name: anim.attr("name").unwrap_or_default().to_string(),
+                    length: anim.parse_attr("length").unwrap_or(0),
+                    interval: anim.parse_attr("interval").unwrap_or(100),
+                    looping: anim.attr("looping") != Some("false"),
+                    mainline,
+                    timelines,
+                });
+            }
+            project.entities.push(ScmlEntity {
+                name: entity.attr("name").unwrap_or_default().to_string(),
+                animations,
+            });
+        }
+        Ok(project)
+    }
+}
+
+/// affine transform in spriter space (y up): [a, b, c, d, tx, ty], x' = a*x + c*y + tx
+type Affine = [f64; 6];
+
+fn affine_mult(p: &Affine, c: &Affine) -> Affine {
+    [
+        p[0]* c[0] + p[2]* c[1],
+        p[1]* c[0] + p[3]* c[1],
+        p[0]* c[2] + p[2]* c[3],
+        p[1]* c[2] + p[3]* c[3],
+        p[0]* c[4] + p[2]* c[5] + p[4],
+        p[1]* c[4] + p[3]* c[5] + p[5],
+    ]
+}
+
+impl ScmlObject {
+    /// translate * rotate * scale
+    fn to_affine(self) -> Affine {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        [cos* self.scale_x, sin* self.scale_x, -sin* self.scale_y, cos* self.scale_y, self.x, self.y]
+    }
+}
+
+impl ScmlAnimation {
+    /// state of timeline key at time, interpolated to next key
+    fn sample_key(&self, timeline: usize, key: usize, time: f64) -> Option<ScmlObject> {
+        let keys = &self.timelines.get(timeline)?.keys;
+        // mainline may skip keys of timeline, use the latest key before time
+        let key = keys.partition_point(|k| (k.time as f64) <= time).saturating_sub(1).max(key);
+        let current = keys.get(key)?;
+        let next = match keys.get(key + 1) {
+            Some(next) => Some((next, next.time as f64)),
+            None if self.looping && keys.len() > 1 => Some((&keys[0], keys[0].time as f64 + self.length as f64)),
+            _ => None,
+        };
+        let mut o = current.object;
+        if let Some((next, next_time)) = next {
+            let start = current.time as f64;
+            if !current.instant && next_time > start && time > start {
+                let t = ((time - start) / (next_time - start)).min(1.0);
+                let n = &next.object;
+                let lerp = |a: f64, b: f64| a + (b - a)* t;
+                o.x = lerp(o.x, n.x);
+                o.y = lerp(o.y, n.y);
+                o.scale_x = lerp(o.scale_x, n.scale_x);
+                o.scale_y = lerp(o.scale_y, n.scale_y);
+                o.angle = match current.spin {
+                    0 => o.angle,
+                    1 if n.angle < o.angle => lerp(o.angle, n.angle + 360.0),
+                    -1 if n.angle > o.angle => lerp(o.angle, n.angle - 360.0),
+                    _ => lerp(o.angle, n.angle),
+                };
+            }
+        }
+        Some(o)
+    }
+
+    /// objects at time: (timeline, object, world transform), sorted by z index (back to front)
+    fn sample(&self, time: f64) -> Vec<(usize, ScmlObject, Affine)> {
+        let i = self.mainline.partition_point(|k| (k.time as f64) <= time);
+        let Some(key) = self.mainline.get(i.saturating_sub(1)) else {
+            return vec![];
+        };
+        // bone parent always has smaller index
+        let mut bones = Vec::<Affine>::with_capacity(key.bone_refs.len());
+        for r in key.bone_refs.iter() {
+            let local = self.sample_key(r.timeline, r.key, time)
+                .map(|b| b.to_affine())
+                .unwrap_or([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+            bones.push(match r.parent.and_then(|p| bones.get(p)) {
+                Some(parent) => affine_mult(parent, &local),
+                None => local,
+            });
+        }
+        let mut result = key.object_refs.iter().filter_map(|r| {
+            let o = self.sample_key(r.timeline, r.key, time)?;
+            let local = o.to_affine();
+            let world = match r.parent.and_then(|p| bones.get(p)) {
+                Some(parent) => affine_mult(parent, &local),
+                None => local,
+            };
+            Some((r.z_index, r.timeline, o, world))
+        }).collect::<Vec<_>>();
+        result.sort_by_key(|v| v.0);
+        result.into_iter().map(|v| (v.1, v.2, v.3)).collect()
+    }
+}
+
+/// symbol hash of name, `hash-{}` is the unresolved hash written by decompiler
+fn name_hash(name: &str) -> u32 {
+    name.strip_prefix("hash-")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| kleihash(name.as_bytes()))
+}
+
+/// split animation name and facing postfix
+fn split_facing(name: &str) -> (&str, u8) {
+    for (alias, facing) in FACING_ALIAS.iter() {
+        if let Some(prefix) = name.strip_suffix(alias).and_then(|s| s.strip_suffix('_')) {
+            if !prefix.is_empty() {
+                return (prefix, *facing);
+            }
+        }
+    }
+    (name, FACING_ALL)
+}
+
+pub struct CompileOptions {
+    /// build name, default is file name of scml
+    pub build: Option<String>,
+    /// frames per second of sampled animation, default is from snapping interval of animation
+    pub rate: Option<f64>,
+    /// max size of atlas texture
+    pub max_size: u32,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            build: None,
+            rate: None,
+            max_size: 2048,
+        }
+    }
+}
+
+/// build image of compiled symbol
+struct SymbolImage {
+    index: u32,
+    image: Image,
+    /// offset of image center to origin, y down
+    x: f64,
+    y: f64,
+}
+
+/// atlas file name and texture bytes
+pub type AtlasFile = (String, Vec<u8>);
+
+/// compile spriter project, `root` is the directory of scml
+pub fn compile(project: &ScmlProject, root: &Path, options: &CompileOptions)
+    -> Result<(AnimBin, BuildBin, Vec<AtlasFile>), String> {
+    let build_name = options.build.clone().unwrap_or_else(|| "build".into());
+    // (folder, file) -> (imghash, index)
+    let mut file_map = HashMap::<(usize, usize), (u32, u32)>::new();
+    let mut symbols = Vec::<(u32, Vec<SymbolImage>)>::new();
+    let mut hash_table = HashTable::new();
+    for (i, folder) in project.folders.iter().enumerate() {
+        let mut images = vec![];
+        for (j, file) in folder.files.iter().enumerate() {
+            let path = Path::new(&file.name);
+            // name of symbol image is `symbol-index.png`
+            let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            let (symbol, index) = match stem.rsplit_once('-').map(|(s, n)| (s, n.parse::<u32>())) {
+                Some((s, Ok(n))) => (s.to_string(), n),
+                _ => (stem.clone(), j as u32),
+            };
+            let symbol = if folder.name.is_empty() { symbol } else { folder.name.clone() };
+            let image = Image::open(root.join(path).to_string_lossy().as_ref())
+                .map_err(|e| format!("{}: {}", e, file.name))?;
+            let (w, h) = (image.width as f64, image.height as f64);
+            let imghash = name_hash(&symbol);
+            hash_table.entry(imghash).or_insert_with(|| symbol.as_bytes().to_vec());
+            file_map.insert((i, j), (imghash, index));
+            images.push((imghash, SymbolImage {
+                index,
+                image,
+                x: (0.5 - file.pivot_x)* w,
+                y: (file.pivot_y - 0.5)* h,
+            }));
+        }
+        for (imghash, img) in images {
+            match symbols.iter_mut().find(|s| s.0 == imghash) {
+                Some(s) => s.1.push(img),
+                None => symbols.push((imghash, vec![img])),
+            }
+        }
+    }
+    for s in symbols.iter_mut() {
+        s.1.sort_by_key(|img| img.index);
+        if let Some(w) = s.1.windows(2).find(|w| w[0].index == w[1].index) {
+            return Err(format!("Duplicate image index {} of symbol {}", w[0].index, resolve_hash(&hash_table, s.0)));
+        }
+    }
+
+    // pack trimmed images to atlases
+    let mut trimmed = vec![];
+    for (i, (_, images)) in symbols.iter().enumerate() {
+        for (j, img) in images.iter().enumerate() {
+            if let Some(trim) = img.image.trim(0) {
+                trimmed.push((i, j, trim));
+            }
+        }
+    }
+    let sizes = trimmed.iter().map(|v| (v.2.0.width, v.2.0.height)).collect::<Vec<_>>();
+    // atlas has full mip chain in 4x4 blocks, padding is filled by extruded edges
+    // and keeps elements in different blocks on the first mip levels
+    let packed = atlas::pack(&sizes, &PackOptions { padding: ATLAS_PADDING, pot: true, max_size: options.max_size })?;
+    let mut vertices = HashMap::<(usize, usize), Vec<Vertex>>::new();
+    let mut textures = vec![];
+    for (sampler, atlas) in packed.iter().enumerate() {
+        let (aw, ah) = (atlas.width, atlas.height);
+        let mut canvas = Image::from_rgba(vec![0; (aw* ah* 4) as usize], aw, ah)
+            .ok_or("Failed to create atlas")?;
+        for (n, rect) in atlas.elements.iter() {
+            let (i, j, (src, sx, sy)) = &trimmed[*n];
+            let img = &symbols[*i].1[*j];
+            canvas.copy_from(src, rect.x, rect.y);
+            let (w, h) = (img.image.width as f64, img.image.height as f64);
+            let left = (img.x - w / 2.0 + *sx as f64) as f32;
+            let top = (img.y - h / 2.0 + *sy as f64) as f32;
+            let (right, bottom) = (left + src.width as f32, top + src.height as f32);
+            let u0 = rect.x as f32 / aw as f32;
+            let u1 = (rect.x + rect.w) as f32 / aw as f32;
+            let v0 = 1.0 - rect.y as f32 / ah as f32;
+            let v1 = 1.0 - (rect.y + rect.h) as f32 / ah as f32;
+            let s = sampler as f32;
+            vertices.insert((*i, *j), vec![
+                [left, top, 0.0, u0, v0, s],
+                [right, top, 0.0, u1, v0, s],
+                [left, bottom, 0.0, u0, v1, s],
+                [right, top, 0.0, u1, v0, s],
+                [right, bottom, 0.0, u1, v1, s],
+                [left, bottom, 0.0, u0, v1, s],
+            ]);
+        }
+        let mut bytes = canvas.to_rgba8();
+        for (_, rect) in atlas.elements.iter() {
+            atlas::extrude(&mut bytes, aw, ah, rect, ATLAS_PADDING);
+        }
+        let canvas = Image::from_rgba(bytes, aw, ah).ok_or("Failed to create atlas")?;
+        textures.push((format!("atlas-{}.tex", sampler), canvas.save_tex_bytes(&EncodeOptions::default())?));
+    }
+
+    let build = BuildBin {
+        name: build_name,
+        atlases: textures.iter().map(|t| t.0.clone()).collect(),
+        symbols: symbols.iter().enumerate().map(|(i, (imghash, images))| Symbol {
+            imghash: *imghash,
+            images: images.iter().enumerate().map(|(j, img)| BuildImage {
+                index: img.index,
+                // displayed until next image
+                duration: images.get(j + 1).map(|next| next.index - img.index).unwrap_or(1),
+                x: img.x as f32,
+                y: img.y as f32,
+                w: img.image.width as f32,
+                h: img.image.height as f32,
+                vertices: vertices.remove(&(i, j)).unwrap_or_default(),
+            }).collect(),
+        }).collect(),
+        hash_table: hash_table.clone(),
+    };
+
+    let mut anims = vec![];
+    for entity in project.entities.iter() {
+        let bankhash = name_hash(&entity.name);
+        hash_table.entry(bankhash).or_insert_with(|| entity.name.as_bytes().to_vec());
+        for anim in entity.animations.iter() {
+            let (name, facing) = split_facing(&anim.name);
+            let rate = options.rate.unwrap_or((1000.0 / anim.interval.max(1) as f64).round());
+            let interval = 1000.0 / rate;
+            let num_frames = ((anim.length as f64 / interval).round() as usize).max(1);
+            let mut frames = Vec::with_capacity(num_frames);
+            for i in 0..num_frames {
+                // spriter key time is in whole milliseconds
+                let objects = anim.sample((i as f64* interval).round());
+                let mut elements = vec![];
+                let mut bbox = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+                // anim.bin is front to back
+                for (timeline, object, world) in objects.iter().rev() {
+                    let Some(&(imghash, imgindex)) = file_map.get(&(object.folder, object.file)) else {
+                        continue;
+                    };
+                    let file = &project.folders[object.folder].files[object.file];
+                    let layer = anim.timelines[*timeline].name.as_str();
+                    let layerhash = name_hash(layer);
+                    hash_table.entry(layerhash).or_insert_with(|| layer.as_bytes().to_vec());
+                    let mut world = *world;
+                    // move origin from object pivot to file pivot
+                    if let Some((px, py)) = object.pivot {
+                        let dx = (file.pivot_x - px)* file.width as f64;
+                        let dy = (file.pivot_y - py)* file.height as f64;
+                        world[4] += world[0]* dx + world[2]* dy;
+                        world[5] += world[1]* dx + world[3]* dy;
+                    }
+                    // flip y axis
+                    let [a, b, c, d, tx, ty] = world;
+                    let matrix = [a, -b, -c, d, tx, -ty];
+                    let img = build.symbols.iter().find(|s| s.imghash == imghash).and_then(|s| s.find_image(imgindex));
+                    if let Some(img) = img {
+                        let (w, h) = (img.w as f64, img.h as f64);
+                        let (x0, y0) = (img.x as f64 - w / 2.0, img.y as f64 - h / 2.0);
+                        for (x, y) in [(x0, y0), (x0 + w, y0), (x0, y0 + h), (x0 + w, y0 + h)] {
+                            let px = matrix[0]* x + matrix[2]* y + matrix[4];
+                            let py = matrix[1]* x + matrix[3]* y + matrix[5];
+                            bbox = (bbox.0.min(px), bbox.1.min(py), bbox.2.max(px), bbox.3.max(py));
+                        }
+                    }
+                    elements.push(Element {
+                        imghash,
+                        imgindex,
+                        layerhash,
+                        matrix: matrix.map(|v| v as f32),
+                        z: 0.0,
+                    });
+                }
+                let num = elements.len();
+                for (k, e) in elements.iter_mut().enumerate() {
+                    e.z = (k as f64* 10.0 / num as f64 - 5.0) as f32;
+                }
+                let rect = if bbox.0 <= bbox.2 {
+                    [(bbox.0 + bbox.2) / 2.0, (bbox.1 + bbox.3) / 2.0, bbox.2 - bbox.0, bbox.3 - bbox.1].map(|v| v as f32)
+                }
+                else {
+                    [0.0; 4]
+                };
+                frames.push(Frame { rect, events: vec![], elements });
+            }
+            anims.push(Anim {
+                name: name.to_string(),
+                facing,
+                bankhash,
+                framerate: rate as f32,
+                frames,
+            });
+        }
+    }
+    Ok((AnimBin { anims, hash_table }, build, textures))
+}
+
+/// compile scml file to anim zip, return path of zip
+pub fn compile_scml(path: &Path, output: Option<&Path>, options: &CompileOptions) -> Result<PathBuf, String> {
+    let s = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let project = ScmlProject::from_xml(&s)?;
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let options = CompileOptions {
+        build: options.build.clone().or(Some(stem)),
+        rate: options.rate,
+        max_size: options.max_size,
+    };
+    let root = path.parent().unwrap_or(Path::new("."));
+    let (anim, build, textures) = compile(&project, root, &options)?;
+    let output = output.map(PathBuf::from).unwrap_or_else(|| path.with_extension("zip"));
+    let f = File::create(&output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let mut zip = ZipWriter::new(f);
+    let file_options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut files = vec![("anim.bin".to_string(), anim.to_bytes()), ("build.bin".to_string(), build.to_bytes())];
+    files.extend(textures);
+    for (name, bytes) in files {
+        zip.start_file(name, file_options).map_err(|e| e.to_string())?;
+        zip.write_all(&bytes).map_err(|e| e.to_string())?;
+    }
+    zip.finish().map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+#[test]
+fn check_scml_matrix() {
+    // rotate and scale x by 2, flip y
+    let object = ScmlObject::from_matrix(0, 0, &[0.0, 2.0, 1.0, 0.0, 10.0, 20.0]);
+    assert!((object.angle - 270.0).abs() < 1e-6);
+    assert!((object.scale_x - 2.0).abs() < 1e-6);
+    assert!((object.scale_y + 1.0).abs() < 1e-6);
+    assert_eq!((object.x, object.y), (10.0, -20.0));
+    let a = ScmlObject::from_matrix(0, 0, &[1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    let b = ScmlObject::from_matrix(0, 0, &[1.0, 0.0, 0.0, 1.0, 4.0, 0.0]);
+    let mid = ScmlObject::from_matrix(0, 0, &[1.0, 0.0, 0.0, 1.0, 2.0, 0.0]);
+    assert!(mid.is_lerp_of(&a, &b, 0.5));
+    assert!(!a.is_lerp_of(&mid, &b, 0.5));
+}
+
+#[test]
+fn check_scml_parse() {
+    let xml = r#"<?xml version="1.0"?>
+<spriter_data scml_version="1.0">
+    <folder id="0" name="arm"><file id="0" name="arm/arm-0.png" width="10" height="20" pivot_x="0.5" pivot_y="0.5"/></folder>
+    <entity id="0" name="wilson">
+        <animation id="0" name="idle_down" length="200" interval="100">
+            <mainline><key id="0"><object_ref id="0" timeline="0" key="0" z_index="0"/></key></mainline>
+            <timeline id="0" name="arm &amp; hand">
+                <key id="0" spin="-1"><object folder="0" file="0" x="0" angle="0"/></key>
+                <key id="1" time="100"><object folder="0" file="0" x="10" angle="270"/></key>
+            </timeline>
+        </animation>
+    </entity>
+</spriter_data>"#;
+    let project = ScmlProject::from_xml(xml).unwrap();
+    let anim = &project.entities[0].animations[0];
+    assert_eq!(anim.timelines[0].name, "arm & hand");
+    assert_eq!(split_facing(&anim.name), ("idle", 8));
+    let (_, object, _) = anim.sample(50.0)[0];
+    assert!((object.x - 5.0).abs() < 1e-6);
+    assert!((object.angle + 45.0).abs() < 1e-6);
+    // loop back to first key
+    let (_, object, _) = anim.sample(150.0)[0];
+    assert!((object.x - 5.0).abs() < 1e-6);
+}
+
+#[test]
+fn check_scml_compile() {
+    let dir = std::env::temp_dir().join(format!("scml-compile-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("arm")).unwrap();
+    let red = Image::from_rgba([255, 0, 0, 255].repeat(10* 20), 10, 20).unwrap();
+    let blue = Image::from_rgba([0, 0, 255, 255].repeat(6* 6), 6, 6).unwrap();
+    red.save(dir.join("arm/arm-0.png").to_str().unwrap(), &[]).unwrap();
+    blue.save(dir.join("arm/arm-1.png").to_str().unwrap(), &[]).unwrap();
+    let scml = |files: &str| format!(r#"<spriter_data scml_version="1.0">
+    <folder id="0" name="arm">{}</folder>
+    <entity id="0" name="wilson">
+        <animation id="0" name="idle_down" length="200" interval="100">
+            <mainline><key id="0"><object_ref id="0" timeline="0" key="0" z_index="0"/></key></mainline>
+            <timeline id="0" name="arm"><key id="0"><object folder="0" file="1" x="0"/></key></timeline>
+        </animation>
+    </entity>
+</spriter_data>"#, files);
+    let files = r#"<file id="0" name="arm/arm-0.png" width="10" height="20" pivot_x="0.5" pivot_y="0.5"/>
+        <file id="1" name="arm/arm-1.png" width="6" height="6" pivot_x="0" pivot_y="1"/>"#;
+    std::fs::write(dir.join("wilson.scml"), scml(files)).unwrap();
+    let path = compile_scml(&dir.join("wilson.scml"), None, &CompileOptions::default()).unwrap();
+    // readable by asset indexer (Indexer.LoadAnimZip)
+    let (anim, build, hash_table) = crate::fastindex::load_anim_zip(File::open(&path).unwrap()).unwrap();
+    assert_eq!(anim.unwrap(), vec![("idle".to_string(), name_hash("wilson"), 8)]);
+    let build = build.unwrap();
+    assert_eq!((build.0.as_str(), build.1), ("wilson", 1));
+    assert_eq!(hash_table.get(&name_hash("arm")).map(|v| v.as_slice()), Some(b"arm".as_slice()));
+    // atlas elements are extruded into padding
+    let mut zip = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+    let mut bytes = vec![];
+    zip.by_name("build.bin").unwrap().read_to_end(&mut bytes).unwrap();
+    let build = BuildBin::from_bytes(&bytes).unwrap();
+    assert_eq!(build.symbols[0].images.len(), 2);
+    let mut bytes = vec![];
+    zip.by_name("atlas-0.tex").unwrap().read_to_end(&mut bytes).unwrap();
+    let tex = KTex::from_reader(bytes.as_slice()).unwrap();
+    assert!(tex.mipmaps.len() > 1);
+    let (aw, ah) = (tex.mipmaps[0].width, tex.mipmaps[0].height);
+    let rgba = tex.decode(0, true, true).unwrap();
+    for img in build.symbols[0].images.iter() {
+        let [_, _, _, u0, v0, _] = img.vertices[0];
+        let (x, y) = ((u0* aw as f32).round() as usize, ((1.0 - v0)* ah as f32).round() as usize);
+        let inside = &rgba[(y* aw as usize + x)* 4..][..4];
+        let outside = &rgba[((y - ATLAS_PADDING as usize)* aw as usize + x - ATLAS_PADDING as usize)* 4..][..4];
+        assert_eq!(inside[3], 255);
+        assert_eq!(inside, outside);
+    }
+    // duplicate frame index is an error
+    let files = r#"<file id="0" name="arm/arm-0.png" width="10" height="20" pivot_x="0.5" pivot_y="0.5"/>
+        <file id="1" name="arm/arm-0.png" width="10" height="20" pivot_x="0.5" pivot_y="0.5"/>"#;
+    std::fs::write(dir.join("wilson.scml"), scml(files)).unwrap();
+    let err = compile_scml(&dir.join("wilson.scml"), None, &CompileOptions::default()).unwrap_err();
+    assert!(err.contains("Duplicate image index 0 of symbol arm"), "{}", err);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+pub mod lua_scml {
+    use super::*;
+    use rlua::prelude::{LuaContext, LuaError, LuaResult};
+    use rlua::{Table, Value};
+    use crate::filesystem::lua_filesystem::ConvertArgToString;
+
+    pub fn init(lua: LuaContext) -> LuaResult<()> {
+        let table = lua.create_table()?;
+
+        // decompile anim zip to spriter project
+        //   input   path or list of paths (anim zip and its build zip)
+        //   output  directory of scml and images
+        table.set("Decompile", lua.create_function(|_, (input, output): (Value, Value)|{
+            let paths = match input {
+                Value::Table(t) => t.sequence_values::<Value>()
+                    .map(|v| v.and_then(|v| v.to_string()).map(PathBuf::from))
+                    .collect::<LuaResult<Vec<_>>>()?,
+                v => vec![PathBuf::from(v.to_string()?)],
+            };
+            let output = PathBuf::from(output.to_string()?);
+            decompile_zip(&paths, &output)
+                .map(|path| path.to_string_lossy().to_string())
+                .map_err(LuaError::RuntimeError)
+        })?)?;
+
+        // compile spriter project to anim zip
+        //   path     scml file
+        //   output   path of zip, default is scml path with .zip extension
+        //   options  {build = string, rate = number, max_size = number}
+        table.set("Compile", lua.create_function(|_, (path, output, options): (Value, Option<Value>, Option<Table>)|{
+            let path = PathBuf::from(path.to_string()?);
+            let output = match output {
+                Some(Value::Nil) | None => None,
+                Some(v) => Some(PathBuf::from(v.to_string()?)),
+            };
+            let mut compile_options = CompileOptions::default();
+            if let Some(options) = options {
+                compile_options.build = options.get("build")?;
+                compile_options.rate = options.get::<_, Option<f64>>("rate")?.filter(|v| *v > 0.0);
+                if let Some(max_size) = options.get("max_size")? {
+                    compile_options.max_size = max_size;
+                }
+            }
+            compile_scml(&path, output.as_deref(), &compile_options)
+                .map(|path| path.to_string_lossy().to_string())
+                .map_err(LuaError::RuntimeError)
+        })?)?;
+
+        lua.globals().set("Scml", table)?;
+        Ok(())
+    }
+}
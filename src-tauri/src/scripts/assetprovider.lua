@@ -1365,9 +1365,10 @@ function Provider:BatchDownload(args)
 			local symbol_name = HashLib:Hash2String(hash) or "HASH-"..hash
 			for _, img in ipairs(v.imglist)do
 				local index = img.index
+				local metadata = { build = build.buildname, symbol = symbol_name, index = index }
 				if img.blank then
 					local f = Image.From_RGBA("\0\0\0\0", 1, 1)
-					f:save((output_dir_path/(symbol_name.."-"..index..".png")):as_string())
+					f:save((output_dir_path/(symbol_name.."-"..index..".png")):as_string(), metadata)
 				else
 					local sampler = assert(img.sampler, "Failed to get img sampler for `"..file.."`")
 					local atlas = atlaslist[sampler]
@@ -1386,7 +1387,7 @@ function Provider:BatchDownload(args)
 							unsigned(img.w * x_scale),
 							unsigned(img.h * y_scale)
 						local f = Image.From_RGBA(CropBytes(atlas:GetImageBytes(0), w, h, bbx, bby, subw, subh), subw, subh)
-						f:save((output_dir_path/(symbol_name.."-"..index..".png")):as_string())
+						f:save((output_dir_path/(symbol_name.."-"..index..".png")):as_string(), metadata)
 						-- TODO: xls info
 					end
 				end
@@ -205,29 +205,52 @@ local function render_animation()
 	r.facing = animation.facing
 	r.format = Args.format
 	r.rate = Args.fps
+	r.linear_light = Args.linear_light
+
+	local function ParseIntegerOrExit(name, v)
+		local n = tonumber(v)
+		if n == nil or n < 0 or n ~= math.floor(n) then
+			print_error("[ERROR] invalid value of --"..name..": "..v)
+			exit(1)
+		end
+		return n
+	end
+
+	r.sheet = {
+		format = Args.sheet_format and Args.sheet_format:lower(),
+		trim = not Args.no_trim,
+		padding = Args.padding and ParseIntegerOrExit("padding", Args.padding),
+		max_size = Args.max_size and ParseIntegerOrExit("max-size", Args.max_size),
+		pot = Args.pot,
+	}
 
 	local color = { ParseColorOrExit(Args.background_color or "transparent") }
 	r.bgc_string = string.char(unpack(color))
 
 	local output = Args.output
-	local extension = output and output:sub(-3)
+	local extension = output and (output:match("%.(%w+)$") or "")
 	if r.format == nil and output ~= nil then
-		if extension == "gif" or extension == "mp4" or extension == "mov" then
+		if extension == "gif" or extension == "mp4" or extension == "mov" 
+			or extension == "apng" or extension == "webp" then
 			r.format = extension
+		elseif extension == "json" then
+			r.format = "sheet"
 		else
 			print_error("[ERROR] failed to determine file format from output path, consider set it explicitly")
 			exit(1)
 		end
 	end
 	if output == nil then
-		if r.format ~= "png" then
+		if r.format == "sheet" then
+			output = APP_WORK_DIR/"export.json"
+		elseif r.format ~= "png" then
 			output = APP_WORK_DIR/("export."..r.format)
 		else
 			output = APP_WORK_DIR/"export"
 		end
 	else
 		-- check if format and file extension match
-		if extension ~= r.format then
+		if extension ~= (r.format == "sheet" and "json" or r.format) then
 			input_or_exit("output file extension `"..extension.."` do not match the format `"..r.format.."`, continue? (y/n)")
 		end
 	end
@@ -275,6 +298,35 @@ local function compile()
 	require("compiler.preview_gen").main(env)
 end
 
+local function decompile()
+	local input = Args:list("input")
+	local output = Args.output
+	if output == nil then
+		-- xxx/name.zip -> xxx/name/
+		output = input[1]:gsub("%.[^%.\\/]*$", "")
+	end
+	local success, result = pcall(Scml.Decompile, input, output)
+	if success then
+		print_info("[INFO] 反编译完成: "..result)
+	else
+		print_error("[ERROR] "..tostring(result))
+		exit(1)
+	end
+end
+
+local function compile_scml()
+	local success, result = pcall(Scml.Compile, Args.input, Args.output, {
+		build = Args.build,
+		rate = tonumber(Args.fps),
+	})
+	if success then
+		print_info("[INFO] 编译完成: "..result)
+	else
+		print_error("[ERROR] "..tostring(result))
+		exit(1)
+	end
+end
+
 local function install_ffmpeg()
 	require "cli_ffmpeg"
 end
@@ -287,6 +339,10 @@ local function cli_main()
 	elseif name == "compile" then
 		load_root()
 		compile()
+	elseif name == "decompile" then
+		decompile()
+	elseif name == "compile-scml" then
+		compile_scml()
 	elseif name == "install-ffmpeg" then
 		install_ffmpeg()
 	elseif name == "dummy" then
@@ -165,8 +165,10 @@ IpcHandlers.Register("quicklook_load", function(param)
 			or ext == "lua" or ext == "py" or ext == "json" or ext == "scml"
 			then
 			load_as = "raw_txt"
-		elseif ext == "png" or ext == "jpg" or ext == "jpeg" or ext == "gif" then
+		elseif ext == "png" or ext == "jpg" or ext == "jpeg" or ext == "gif" or ext == "webp" then
 			load_as = "raw_image"
+		elseif ext == "tga" or ext == "dds" then
+			load_as = "image"
 		else
 			local head = FileSystem.SigOf(filepath, 1000)
 		 	if #head - string.get_utf8_last_valid_index(head) < 5 then
@@ -230,6 +232,14 @@ IpcHandlers.Register("quicklook_load", function(param)
 		LoadAsFsb(f, data, filepath)
 	elseif load_as == "dyn" then
 		LoadAsDyn(f, data)
+	elseif load_as == "image" then
+		-- not supported by webview, convert to png
+		local success, result = pcall(Image.Open, filepath)
+		if success then
+			table.insert(data, { type = "raw_image", content = result:save_png_base64() })
+		else
+			table.insert(data, { type = "loader", error = tostring(result) })
+		end
 	elseif load_as == "raw_txt" or load_as == "raw_image" or load_as == "raw_bin" then
 		local threshold = load_as == "raw_image" and 100 or 10
 		local content = f:read(threshold * MB)
@@ -239,7 +249,16 @@ IpcHandlers.Register("quicklook_load", function(param)
 			content = Algorithm.B64Encode(content)
 		end
 		local large_file = f:read(1) ~= nil
-		table.insert(data, { type = load_as, content = content, large_file_threshold = large_file and threshold or nil })
+		local metadata = nil
+		if ext == "png" then
+			-- provenance written by exporter (bank, build, animation, frame, symbol...)
+			local success, result = pcall(Image.ReadMetadata, filepath)
+			if success and next(result) ~= nil then
+				metadata = result
+			end
+		end
+		table.insert(data, { type = load_as, content = content, large_file_threshold = large_file and threshold or nil,
+			metadata = metadata })
 	else
 		print("Warning: load_as is not handled: "..load_as)
 	end
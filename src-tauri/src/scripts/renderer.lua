@@ -15,6 +15,7 @@ local Render = Class(function(self, api_list)
 	self.format = "auto"
 	self.scale = nil
 	self.rate = nil
+	self.linear_light = false
 end)
 
 local function tohash(v)
@@ -63,6 +64,8 @@ function Render:SetRenderParam(param)
 	self.rate = param.rate
 	self.format = param.format
 	self.scale = param.scale
+	self.linear_light = param.linear_light
+	self.sheet = param.sheet
 	self.skip_index = param.skip_index
 	self.current_frame = param.current_frame
 end
@@ -190,6 +193,9 @@ function Render:Refine()
 	end
 
 	return {
+		bank = bank,
+		build = build,
+		animation = animation,
 		builddata = builddata,
 		anim = anim,
 		color = { mult = mult, add = add, symbol_mult = symbol_mult, symbol_add = symbol_add },
@@ -286,17 +292,6 @@ function Render:GetSymbolElement(build, symbol, index)
 	return img
 end
 
-local function Mult(a, b)
-    return {
-        a[1]*b[1]+a[2]*b[3],
-        a[1]*b[2]+a[2]*b[4],
-        a[3]*b[1]+a[4]*b[3],
-        a[3]*b[2]+a[4]*b[4],
-        a[1]*b[5]+a[2]*b[6]+a[5],
-        a[3]*b[5]+a[4]*b[6]+a[6],
-    }
-end
-
 function Render:TryInterrupt()
 	if IpcInterrupted() then
 		return error(ERROR.IPC_INTERRUPTED)
@@ -308,6 +303,9 @@ function Render:IsFileOutput(format)
 	return format == "gif"
 		or format == "mov"
 		or format == "mp4"
+		or format == "apng"
+		or format == "webp"
+		or format == "sheet"
 end
 
 function Render:Run()
@@ -322,15 +320,18 @@ function Render:Run()
 
 	local format = self.format:lower()
 	if format == "auto" then
-		for _, suffix in ipairs{ "gif", "mp4", "mov" }do
+		for _, suffix in ipairs{ "gif", "mp4", "mov", "apng", "webp" }do
 			if path:check_extention(suffix) then
 				format = suffix
 				break
 			end
 		end
+		if path:check_extention("json") then
+			format = "sheet"
+		end
 	end
 	assert(format ~= "auto", "Failed to infer export format from file path: "..path:as_string())
-	assert(table.contains({"gif", "mp4", "mov", "png", "snapshot"}, format), "Invalid export format: "..format)
+	assert(table.contains({"gif", "mp4", "mov", "apng", "webp", "png", "snapshot", "sheet"}, format), "Invalid export format: "..format)
 	
 	if format == "png" then
 		assert(path:is_dir(), "Error: png sequence must export to a directory")
@@ -342,7 +343,12 @@ function Render:Run()
 			if not path:check_extention("png") then
 				path = path:with_extension("png")
 			end
-		elseif not FFmpegManager:IsAvailable() then
+		elseif format == "sheet" then
+			-- sheet image is saved side by side as xxx.png
+			if not path:check_extention("json") then
+				path = path:with_extension("json")
+			end
+		elseif (format == "mp4" or format == "mov") and not FFmpegManager:IsAvailable() then
 			IpcEmitEvent("render_event", json.encode_compliant{
 				session_id = self.session_id,
 				state = "error",
@@ -375,152 +381,29 @@ function Render:Run()
 
 	assert(#frame_list > 0, "Frame list is empty")
 
-	-- loop 1: calculate symbol source and render rect
-	local allelements = {}
-	for _,frame in ipairs(frame_list)do
-		self:TryInterrupt()
-		for _,element in ipairs(frame)do
-			table.insert(allelements, element)
-			local imghash = element.imghash
-			local layerhash = element.layerhash
-			if skip_render.symbol[imghash] or skip_render.layer[layerhash] then
-				-- skip render, do nothing
-			else
-				local matrix = element.matrix
-				local imgindex = element.imgindex
-				-- get symbol source with possible redirection (by `AddOverrideBuild` | `OverrideSymbol`)
-				local symboldata = source_map[imghash] and source_map[imghash].symboldata
-					or builddata and builddata:GetSymbol(imghash) or nil
-				local buildname = source_map[imghash] and source_map[imghash].buildname
-					or builddata and builddata.buildname or nil
-
-				if symboldata ~= nil then
-					local img = nil
-					for _,v in pairs(symboldata.imglist)do
-						-- TODO: 可以优化为二分查找
-						if v.index <= imgindex then
-							img = v
-						else
-							break
-						end
-					end
-					if img ~= nil
-						and img.index + img.duration > imgindex --[[ check duration ]] then
-						element._img = img
-						local w, h = img.w, img.h
-						local x, y = img.x, img.y
-						local m = Affine(unpack(matrix)):ToLinear()
-						local x_values = {}
-						local y_values = {}
-						for _,v in ipairs{
-							{x-w/2, y-h/2}, {x-w/2, y+h/2}, 
-							{x+w/2, y-h/2}, {x+w/2, y+h/2},
-						}do
-							local px, py = m:OnPoint(unpack(v))
-							table.insert(x_values, px)
-							table.insert(y_values, py)
-						end
-						element._rect = {
-							-- left top right bottom
-							math.min(unpack(x_values))-1,
-							math.min(unpack(y_values))-1,
-							math.max(unpack(x_values))+1,
-							math.max(unpack(y_values))+1,
-						}
-						element._source = self:GetSymbolElement(buildname, symboldata.imghash, img.index)
-						-- TODO resize to canvas size
-					end
-				end
-			end
-		end
-	end
-
-	-- loop 2: render elements
-	IpcEmitEvent("render_event", json.encode_compliant({
-		session_id = self.session_id,
-		state = "render_element",
-		progress = 0,
-	}))
-	local framebuffer = {}
-	local element_tasks = {}
-	for index, frame in ipairs(frame_list)do
-		self:TryInterrupt()
-		local buffer = {} -- each buffer contains several element render rects
-		table.insert(framebuffer, buffer) 
-		for _,element in ipairs(frame)do
-			local img = element._img
-			local rect = element._rect
-			local source = element._source
-			if img ~= nil then
-				local matrix = element.matrix
-				local px, py = matrix[5], matrix[6]
-				local render_width = math.ceil(rect[3]) - math.floor(rect[1])
-				local render_height = math.ceil(rect[4]) - math.floor(rect[2])
-				local pos_global = {
-					px + rect[1],
-					py + rect[2],
-				}
-				local pos_int = { 
-					math.floor(pos_global[1]),
-					math.floor(pos_global[2]),
-				}
-				local pos_decimal = {
-					pos_global[1] - math.floor(pos_global[1]),
-					pos_global[2] - math.floor(pos_global[2]),
-				}
-				local m1 = {1, 0, 0, 1, img.x-img.w/2, img.y-img.h/2} -- anchor
-				local m2 = {matrix[1], matrix[3], matrix[2], matrix[4], 0, 0} -- linear transformation part
-				local m3 = {1, 0, 0, 1, -rect[1]+pos_decimal[1], -rect[2]+pos_decimal[2]} -- place to small render rect
-				local m = Mult(m3, (Mult(m2, m1)))
-				m[2], m[3] = m[3], m[2] -- TODO: fix mult function
-
-				-- local small_img = source:affine_transform(render_width, render_height,
-				-- 	m, Image.BILINEAR)
-
-				local key = "task-"..getaddr(source).."("..render_width.."x"..render_height..")"..
-					table.concat(m, "+")
-				local filter, filter_key = self:GetFilter({					
-					mult = {color.mult, color.symbol_mult[element.imghash]},
-					add  = {color.add, color.symbol_add[element.imghash]},
-				})
-				if filter_key ~= nil then
-					key = key .. "filter:" .. filter_key
-				end
-				element_tasks[key] = {
-					render_width = render_width,
-					render_height = render_height,
-					img = source,
-					matrix = m,
-					filter = filter,
-				}
-				table.insert(buffer, {
-					-- small_img = small_img,
-					small_img_id = key,
-					pos_int = pos_int,
-					render_width = render_width,
-					render_height = render_height,
-					z_index = element.z_index,
-					-- mult = {color.mult, color.symbol_mult[element.imghash]},
-					-- add  = {color.add, color.symbol_add[element.imghash]},
-				})
-			end
-		end
-	end
-
-	-- loop 2.1: run the multithreaded renderer
-	-- element_tasks["@thread"] = 1
-	-- element_tasks["@resampler"] = Image.NEAREST
-	element_tasks["@progress"] = function(current, _, percent)
-		if current % 100 == 0 then
+	-- loop 1: resolve symbol source, load element images and build render tasks (native)
+	local renderer = Image.AnimRenderer{
+		frames = frame_list,
+		build = builddata,
+		overrides = source_map,
+		hide = skip_render,
+		color = color,
+		loader = function(buildname, imghash, index)
 			self:TryInterrupt()
-			IpcEmitEvent("render_event", json.encode_compliant({
-				session_id = self.session_id,
-				state = "render_element",
-				progress = percent,
-			}))
-		end
-	end
-	Image.MultiThreadedTransform(element_tasks)
+			return self:GetSymbolElement(buildname, imghash, index)
+		end,
+	}
+
+	-- loop 2: calculate global render region
+	-- elements are transformed on demand during rendering (see loop 4)
+	local left, top, width, height = renderer:prepare{
+		-- thread = 1,
+		-- resampler = Image.NEAREST,
+		colorspace = self.linear_light and Image.COLORSPACE_LINEAR or Image.COLORSPACE_SRGB,
+		-- mov and png are always exported at full scale, see loop 3
+		scale = (format == "mov" or format == "png") and 1.0 or self.scale or 1.0,
+		-- memory = 1024, -- MB, limits in-flight frames and cached elements
+	}
 
 	IpcEmitEvent("render_event", json.encode_compliant{
 		session_id = self.session_id,
@@ -528,26 +411,7 @@ function Render:Run()
 		progress = 1,
 	})
 
-	-- loop 3: calculate global render region
-	local x_values = {}
-	local y_values = {}
-	for _, buffer in ipairs(framebuffer)do
-		for _, data in ipairs(buffer)do
-			local rect = data.rect
-			local pos_int = data.pos_int
-			table.insert(x_values, pos_int[1])
-			table.insert(x_values, pos_int[1]+data.render_width)
-			table.insert(y_values, pos_int[2])
-			table.insert(y_values, pos_int[2]+data.render_height)
-		end
-	end
-	local left = math.floor(Algorithm.Min(x_values))
-	local right = math.ceil(Algorithm.Max(x_values))
-	local top = math.floor(Algorithm.Min(y_values))
-	local bottom = math.ceil(Algorithm.Max(y_values))
-	local width = right - left
-	local height = bottom - top
-	if width == 0 or height == 0 then
+	if left == nil or width == 0 or height == 0 then
 		print("[Render] size is 0x0, finish")
 		return {
 			success = false,
@@ -557,10 +421,11 @@ function Render:Run()
 	-- h264 encoder need even size
 	if width % 2 == 1 then width = width + 1 end
 	if height % 2 == 1 then height = height + 1 end
-	print_info("[Render] region:", left, top, right, bottom)
+	print_info("[Render] region:", left, top, left + width, top + height)
 
-	-- loop 4: render the full canvas
-	if #framebuffer == 0 then
+	-- loop 3: render the full canvas (transform -> composite -> encode)
+	local numframe = renderer:numframe()
+	if numframe == 0 then
 		print("[Render] animation frame is 0, finish")
 		return {
 			success = false,
@@ -574,10 +439,46 @@ function Render:Run()
 	}))
 	self:TryInterrupt()
 
+	-- provenance of exported png, see Image.ReadMetadata()
+	local render = self
+	local metadata = {
+		software = "Asset Archive",
+		bank = basic.bank,
+		build = basic.build,
+		animation = basic.animation,
+		facing = anim.facing,
+	}
 	local enc = nil
 	local png_dir = path
-	local png_path = nil
-	if format == "mov" or format == "mp4" or format == "gif" then
+	local result_path = nil
+	if format == "apng" or format == "webp" or (format == "gif" and not FFmpegManager:IsAvailable()) then
+		-- native writer, no FFmpeg required
+		enc = Image.AnimWriter {
+			path = path:as_string(),
+			format = format,
+			scale = self.scale or 1.0,
+			width = width,
+			height = height,
+			rate = self.rate or anim.framerate or error("Failed to get export framerate"),
+		}
+	elseif format == "sheet" then
+		local sheet = self.sheet or {}
+		enc = Image.SpriteSheetWriter {
+			path = path:as_string(),
+			scale = self.scale or 1.0,
+			width = width,
+			height = height,
+			rate = self.rate or anim.framerate or error("Failed to get export framerate"),
+			format = sheet.format or "hash",
+			trim = sheet.trim ~= false,
+			padding = sheet.padding,
+			max_size = sheet.max_size,
+			pot = sheet.pot,
+			-- anim origin in canvas
+			pivot = { x = -left / width, y = -top / height },
+		}
+		result_path = path:as_string() -- sheet image is written side by side, report the json
+	elseif format == "mov" or format == "mp4" or format == "gif" then
 		if format == "mov" then
 			self.scale = 1.0 -- mov format always use full scale
 		end
@@ -604,10 +505,11 @@ function Render:Run()
 			encode_frame = function(self, img, index)
 				local name = string.format("%05d.png", index)
 				local out = (png_dir/name):as_string()
-				img:save(out)
+				metadata.frame = index -- same as file name (1-based)
+				img:save(out, metadata)
 
-				if png_path == nil and index == 1 then
-					png_path = out -- use the first frame to display
+				if result_path == nil and index == 1 then
+					result_path = out -- use the first frame to display
 				end
 			end,
 			wait = function() end, -- dummy
@@ -615,42 +517,29 @@ function Render:Run()
 	elseif format == "snapshot" then
 		enc = {
 			encode_frame = function(self, img)
-				img:save(path:as_string())
+				metadata.frame = render.current_frame + 1
+				img:save(path:as_string(), metadata)
 			end,
 			wait = function() end, -- dummy
 		}
 	end
 
-	local composite_tasks = {
-		["@canvas"] = Image.From_RGBA(
+	local bar = ProgressBar(numframe)
+	renderer:render{
+		canvas = Image.From_RGBA(
 			string.rep(self.bgc_string or self.bgc == "transparent" and "\0\0\0\0" or string.char(HexToRGB(self.bgc)).."\255", width* height),
 			width, height),
-		["@numframe"] = #framebuffer,
-		["@encoder"] = function(img, index) enc:encode_frame(img, index) end,
-		["@sequential"] = type(enc) == "userdata"
+		encoder = function(img, index) enc:encode_frame(img, index) end,
+		progress = function(current, _, percent)
+			self:TryInterrupt()
+			bar:set_position(current)
+			IpcEmitEvent("render_event", json.encode_compliant({
+				session_id = self.session_id,
+				state = "render_canvas",
+				progress = math.min(.99, percent),
+			}))
+		end,
 	}
-	local bar = ProgressBar(#framebuffer)
-	for index, buffer in ipairs(framebuffer)do
-		self:TryInterrupt()
-		table.sort(buffer, function(a,b) return a.z_index > b.z_index end)
-		for _, data in ipairs(buffer)do
-			data.img = 	assert(element_tasks[data.small_img_id], "internal error: element render task failed").img
-			data.px = data.pos_int[1] - left
-			data.py = data.pos_int[2] - top
-		end
-		composite_tasks[index] = buffer
-	end
-
-	-- composite_tasks["@thread"] = 8
-	composite_tasks["@progress"] = function(current, _, percent)
-		bar:set_position(current)
-		IpcEmitEvent("render_event", json.encode_compliant({
-			session_id = self.session_id,
-			state = "render_canvas",
-			progress = math.min(.99, current/#framebuffer),
-		}))
-	end
-	Image.MultiThreadedCompositeAndRender(composite_tasks)
 
 	enc:wait()  -- wait ffmpeg subprocess to finish and shutdown
 	bar:done()
@@ -658,7 +547,7 @@ function Render:Run()
 	IpcEmitEvent("render_event", json.encode_compliant({
 		session_id = self.session_id,
 		state = "finish",
-		path = png_path or self.path,
+		path = result_path or self.path,
 	}))
 end
 
@@ -678,10 +567,10 @@ function Render:GetFilter(data)
 		table.insert(key, "m-"..getaddr(v))
 	end
 	local add = data.add
-	local addRGB = {0, 0, 0, 0}
+	local addRGB = {0, 0, 0, 1}
 	for _,v in pairs(add)do
-		for i, c in ipairs(v)do
-			addRGB[i] = addRGB[i] + c* 255* v[4]
+		for i = 1, 3 do
+			addRGB[i] = addRGB[i] + v[i]* v[4]
 		end
 		table.insert(key, "a-"..getaddr(v))
 	end
@@ -692,11 +581,9 @@ function Render:GetFilter(data)
 	elseif self.filter[key] ~= nil then
 		return self.filter[key], key
 	else
-		local filter = Image.Filter({
-			function(r) return math.clamp(r* multRGBA[1] + addRGB[1], 0, 255) end,
-			function(g) return math.clamp(g* multRGBA[2] + addRGB[2], 0, 255) end,
-			function(b) return math.clamp(b* multRGBA[3] + addRGB[3], 0, 255) end,
-			function(a) return math.clamp(a* multRGBA[4], 0, 255) end
+		local filter = Image.ColorMatrix({
+			mult = multRGBA,
+			add = addRGB,
 		})
 		self.filter[key] = filter
 		return filter, key
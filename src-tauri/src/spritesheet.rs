@@ -0,0 +1,326 @@
+// sprite sheet writer, output is a png and a TexturePacker compatible json (hash / array)
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use crate::animwriter::{crop_rgba, FrameSink};
+use crate::atlas::{self, PackOptions, Rect};
+use crate::image::alpha_bbox;
+use crate::image::lua_image::Image;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetFormat {
+    /// "frames": { [name]: frame }
+    Hash,
+    /// "frames": [ { filename, ...frame } ]
+    Array,
+}
+
+impl SheetFormat {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "hash" => Some(SheetFormat::Hash),
+            "array" => Some(SheetFormat::Array),
+            _ => None,
+        }
+    }
+}
+
+pub struct SheetOptions {
+    pub format: SheetFormat,
+    /// remove transparent borders of each frame
+    pub trim: bool,
+    pub padding: u32,
+    pub pot: bool,
+    pub max_size: u32,
+    /// normalized anchor of source frame
+    pub pivot: (f64, f64),
+    /// frames per second
+    pub rate: f64,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        SheetOptions {
+            format: SheetFormat::Hash,
+            trim: true,
+            padding: 1,
+            pot: false,
+            max_size: 8192,
+            pivot: (0.5, 0.5),
+            rate: 30.0,
+        }
+    }
+}
+
+struct SheetFrame {
+    /// index of unique image
+    image: usize,
+    /// position in source frame
+    source: Rect,
+}
+
+pub struct SpriteSheetWriter {
+    /// output png path, json is written side by side
+    path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    options: SheetOptions,
+    frames: Vec<SheetFrame>,
+    /// unique images (w, h, rgba), identical frames are packed once
+    images: Vec<(u32, u32, Vec<u8>)>,
+    image_index: HashMap<u64, Vec<usize>>,
+    /// png and json are written, frames are released
+    closed: bool,
+}
+
+impl SpriteSheetWriter {
+    pub fn new(path: &str, width: u32, height: u32, options: SheetOptions) -> Self {
+        SpriteSheetWriter {
+            path: PathBuf::from(path).with_extension("png"),
+            width,
+            height,
+            options,
+            frames: vec![],
+            images: vec![],
+            image_index: HashMap::new(),
+            closed: false,
+        }
+    }
+
+    pub fn json_path(&self) -> PathBuf {
+        self.path.with_extension("json")
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn encode_frame(&mut self, rgba: &[u8]) -> Result<(), String> {
+        if self.is_closed() {
+            return Err("file is closed".into());
+        }
+        let (width, height) = (self.width, self.height);
+        if rgba.len() != (width* height* 4) as usize {
+            return Err(format!("Frame size not match, expect {}x{}", width, height));
+        }
+        let full = Rect { x: 0, y: 0, w: width, h: height };
+        let source = if self.options.trim {
+            // keep a single pixel for empty frame
+            alpha_bbox(rgba, width, height, 0)
+                .map(|(x, y, w, h)| Rect { x, y, w, h })
+                .unwrap_or(Rect { x: 0, y: 0, w: 1, h: 1 })
+        }
+        else {
+            full
+        };
+        let bytes = if source == full { rgba.to_vec() } else { crop_rgba(rgba, width, (source.x, source.y, source.w, source.h)) };
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (source.w, source.h, &bytes).hash(&mut hasher);
+        let candidates = self.image_index.entry(hasher.finish()).or_default();
+        let image = match candidates.iter().find(|&&i| self.images[i].2 == bytes) {
+            Some(&i) => i,
+            None => {
+                self.images.push((source.w, source.h, bytes));
+                candidates.push(self.images.len() - 1);
+                self.images.len() - 1
+            }
+        };
+        self.frames.push(SheetFrame { image, source });
+        Ok(())
+    }
+
+    fn frame_name(&self, index: usize) -> String {
+        let stem = self.path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        format!("{}_{:05}.png", stem, index)
+    }
+
+    /// pack frames, return sheet (width, height, rgba) and frame rects in sheet
+    fn pack(&self) -> Result<(u32, u32, Vec<u8>, Vec<Rect>), String> {
+        let sizes = self.images.iter().map(|img| (img.0, img.1)).collect::<Vec<_>>();
+        let pack_options = PackOptions {
+            padding: self.options.padding,
+            pot: self.options.pot,
+            max_size: self.options.max_size,
+        };
+        let mut atlases = atlas::pack(&sizes, &pack_options)?;
+        if atlases.len() != 1 {
+            return Err(format!("Frames can not fit in one sheet of {}x{}, try larger `max_size` or smaller scale",
+                self.options.max_size, self.options.max_size));
+        }
+        let atlas = atlases.remove(0);
+        let mut canvas = vec![0; (atlas.width* atlas.height* 4) as usize];
+        let mut rects = vec![Rect { x: 0, y: 0, w: 0, h: 0 }; self.images.len()];
+        for (i, rect) in atlas.elements {
+            let bytes = &self.images[i].2;
+            for y in 0..rect.h {
+                let src = (y* rect.w* 4) as usize;
+                let dst = (((rect.y + y)* atlas.width + rect.x)* 4) as usize;
+                canvas[dst..dst + (rect.w* 4) as usize].copy_from_slice(&bytes[src..src + (rect.w* 4) as usize]);
+            }
+            rects[i] = rect;
+        }
+        Ok((atlas.width, atlas.height, canvas, rects))
+    }
+
+    /// generate json descriptor
+    fn to_json(&self, sheet_width: u32, sheet_height: u32, rects: &[Rect]) -> json::JsonValue {
+        let duration = (1000.0 / self.options.rate.max(1e-3)).round() as u32;
+        let full = Rect { x: 0, y: 0, w: self.width, h: self.height };
+        let mut frames_hash = json::JsonValue::new_object();
+        let mut frames_array = json::JsonValue::new_array();
+        for (index, frame) in self.frames.iter().enumerate() {
+            let rect = &rects[frame.image];
+            let source = &frame.source;
+            let mut data = json::object!{
+                frame: { x: rect.x, y: rect.y, w: rect.w, h: rect.h },
+                rotated: false,
+                trimmed: *source != full,
+                spriteSourceSize: { x: source.x, y: source.y, w: source.w, h: source.h },
+                sourceSize: { w: self.width, h: self.height },
+                pivot: { x: self.options.pivot.0, y: self.options.pivot.1 },
+                duration: duration,
+            };
+            let name = self.frame_name(index);
+            match self.options.format {
+                SheetFormat::Hash => frames_hash[name] = data,
+                SheetFormat::Array => {
+                    data["filename"] = name.into();
+                    frames_array.push(data).unwrap();
+                }
+            }
+        }
+        let image = self.path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        json::object!{
+            frames: match self.options.format {
+                SheetFormat::Hash => frames_hash,
+                SheetFormat::Array => frames_array,
+            },
+            meta: {
+                app: "Asset Archive",
+                version: "1.0",
+                image: image,
+                format: "RGBA8888",
+                size: { w: sheet_width, h: sheet_height },
+                scale: "1",
+                frameRate: self.options.rate,
+            }
+        }
+    }
+
+    /// pack all frames, write png and json
+    pub fn finish(&mut self) -> Result<(), String> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        if self.frames.is_empty() {
+            return Err("Sprite sheet has no frame".into());
+        }
+        let (width, height, canvas, rects) = self.pack()?;
+        let img = Image::from_rgba(canvas, width, height)
+            .ok_or("Failed to create sprite sheet canvas")?;
+        img.save(self.path.to_string_lossy().as_ref(), &[])
+            .map_err(|e| e.to_string())?;
+        let json = self.to_json(width, height, &rects);
+        std::fs::write(self.json_path(), json.pretty(2))
+            .map_err(|e| format!("Failed to write json: {}", e))?;
+        self.frames.clear();
+        self.images.clear();
+        self.image_index.clear();
+        self.closed = true;
+        Ok(())
+    }
+}
+
+impl FrameSink for SpriteSheetWriter {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn encode_frame(&mut self, rgba: &[u8]) -> Result<(), String> {
+        SpriteSheetWriter::encode_frame(self, rgba)
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        SpriteSheetWriter::finish(self)
+    }
+}
+
+#[test]
+fn check_sheet() {
+    let (w, h) = (8, 6);
+    let mut writer = SpriteSheetWriter::new("/tmp/sheet.json", w, h, SheetOptions::default());
+    let mut frame = vec![0; (w* h* 4) as usize];
+    // opaque 2x3 block at (3, 1)
+    for y in 1..4 {
+        for x in 3..5 {
+            frame[((y* w + x)* 4 + 3) as usize] = 255;
+        }
+    }
+    writer.encode_frame(&frame).unwrap();
+    writer.encode_frame(&frame).unwrap();
+    writer.encode_frame(&vec![0; (w* h* 4) as usize]).unwrap();
+    assert_eq!(writer.images.len(), 2);
+    assert_eq!(writer.frames[0].source, Rect { x: 3, y: 1, w: 2, h: 3 });
+    let (_, _, _, rects) = writer.pack().unwrap();
+    let json = writer.to_json(16, 16, &rects);
+    let f = &json["frames"]["sheet_00001.png"];
+    assert_eq!(f["trimmed"], true);
+    assert_eq!(f["spriteSourceSize"]["x"], 3);
+    assert_eq!(f["frame"]["w"], 2);
+    assert_eq!(f["duration"], 33);
+    // finish is idempotent, and no frame is accepted after it
+    let path = std::env::temp_dir().join(format!("sheet-{}.json", std::process::id()));
+    let mut writer = SpriteSheetWriter::new(path.to_string_lossy().as_ref(), w, h, SheetOptions::default());
+    writer.encode_frame(&frame).unwrap();
+    writer.finish().unwrap();
+    writer.finish().unwrap();
+    assert!(writer.is_closed() && writer.encode_frame(&frame).is_err());
+    assert!(path.with_extension("png").is_file());
+    let _ = std::fs::remove_file(path.with_extension("png"));
+    let _ = std::fs::remove_file(&path);
+}
+
+pub mod lua_spritesheet {
+    use super::*;
+    use rlua::prelude::{LuaResult, LuaError};
+    use rlua::Table;
+    use crate::animwriter::lua_animwriter::{get_path_and_size, LuaFrameSink};
+
+    // create a new sprite sheet writer
+    //   path      xxxx/xxxx.json (sheet is saved as xxxx/xxxx.png)
+    //   width     canvas width
+    //   height    canvas height
+    //   scale     0.5|1
+    //   rate      30
+    //   format    hash|array (default hash)
+    //   trim      default true
+    //   padding   default 1
+    //   max_size  default 8192
+    //   pot       default false
+    //   pivot     {x, y} normalized anchor in canvas, default {0.5, 0.5}
+    pub fn create_writer(args: Table) -> LuaResult<LuaFrameSink<SpriteSheetWriter>> {
+        let (path, width, height) = get_path_and_size(&args)?;
+        let default = SheetOptions::default();
+        let format = match args.get::<_, Option<String>>("format")? {
+            Some(s) => SheetFormat::from_name(s.as_str())
+                .ok_or_else(|| LuaError::RuntimeError(format!("Invalid sheet format: {}", s)))?,
+            None => default.format,
+        };
+        let pivot = match args.get::<_, Option<Table>>("pivot")? {
+            Some(t) => (t.get("x")?, t.get("y")?),
+            None => default.pivot,
+        };
+        let options = SheetOptions {
+            format,
+            trim: args.get::<_, Option<bool>>("trim")?.unwrap_or(default.trim),
+            padding: args.get::<_, Option<u32>>("padding")?.unwrap_or(default.padding),
+            pot: args.get::<_, Option<bool>>("pot")?.unwrap_or(default.pot),
+            max_size: args.get::<_, Option<u32>>("max_size")?.unwrap_or(default.max_size),
+            pivot,
+            rate: args.get::<_, f64>("rate").unwrap_or(default.rate),
+        };
+        Ok(LuaFrameSink::new(SpriteSheetWriter::new(path.as_str(), width, height, options)))
+    }
+}
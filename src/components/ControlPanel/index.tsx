@@ -1,5 +1,5 @@
 import React, { SyntheticEvent, useCallback, useContext, useState } from 'react'
-import { Button, ButtonGroup, ButtonProps, Collapse, Dialog, DialogBody, Icon, IconName, InputGroup, Radio, RadioGroup } from '@blueprintjs/core'
+import { Button, ButtonGroup, ButtonProps, Checkbox, Collapse, Dialog, DialogBody, Icon, IconName, InputGroup, Radio, RadioGroup } from '@blueprintjs/core'
 import style from './index.module.css'
 import ApiPicker from '../ApiPicker'
 import ApiOperator from '../ApiOperator'
@@ -125,6 +125,7 @@ function Export() {
 
   const [resolution, setResolution] = useSharedLocalStorage("anim_export_resolution")
   const [rate, setRate] = useSharedLocalStorage("anim_export_framerate")
+  const [sheet, setSheet] = useSharedLocalStorage("anim_export_sheet")
 
   const call = useLuaCall("render_animation_sync", ()=> {}, {}, [])
   const requestExportTo = useCallback((path: string)=> {
@@ -141,13 +142,14 @@ function Export() {
         scale: resolution,
         rate,
         format: fileExtension,
+        sheet: fileExtension === "sheet" ? sheet : undefined,
         facing: animstate.getActualFacing(),
         bgc: bgcType === "use_current" ?
           (render.bgcType === "transparent" ? "transparent" : render.bgc) :
           bgcType === "transparent" ? "transparent" : colorValue,
       }  
     })
-  }, [fileExtension, resolution, rate, bgcType, colorValue, call,
+  }, [fileExtension, resolution, rate, sheet, bgcType, colorValue, call,
     animstate, render])
 
   const onClickExport = useCallback(()=> {
@@ -164,7 +166,7 @@ function Export() {
       // export as a single file
       save({
         title: "",
-        defaultPath:  "export." + fileExtension, // TODO: formater
+        defaultPath:  "export." + (fileExtension === "sheet" ? "json" : fileExtension), // TODO: formater
       }).then(
         path=> typeof path === "string" && requestExportTo(path)
       )
@@ -211,6 +213,14 @@ function Export() {
               onChange={e=> handleExtensionCheck(e, "png")}/>
           </Tooltip2>
         </div>
+        <div>
+          <Tooltip2 content={"所有帧打包为一张图片，附带TexturePacker格式的json，适合导入游戏引擎"} placement="right">
+            <Radio 
+              label="精灵表（json + png）" 
+              checked={fileExtension === "sheet"}
+              onChange={e=> handleExtensionCheck(e, "sheet")}/>
+          </Tooltip2>
+        </div>
       </RadioGroup>
       <br/>
       <p>
@@ -247,7 +257,7 @@ function Export() {
         <input type="color" style={{display: "inline-block", marginLeft: 10}} value={colorValue} onChange={onChangeColor}/>
       </RadioGroup>
       <br/>
-      <Tooltip2 content={"该选项仅在mp4或gif格式下生效。"}>
+      <Tooltip2 content={"该选项仅在mp4、gif或精灵表格式下生效。"}>
         <p><strong>分辨率</strong>&nbsp;
             <Icon icon="small-info-sign"/>
         </p>
@@ -255,7 +265,7 @@ function Export() {
       <RadioGroup 
         selectedValue={resolution}
         onChange={e=> setResolution(Number(e.currentTarget.value))}
-        disabled={fileExtension !== "gif" && fileExtension !== "mp4"}>
+        disabled={fileExtension !== "gif" && fileExtension !== "mp4" && fileExtension !== "sheet"}>
         <Radio label="原图" value={1} style={{display: "inline-block"}}/>
         <Radio label="1/2" value={0.5} style={{display: "inline-block", marginLeft: 20}}/>
       </RadioGroup>
@@ -280,6 +290,38 @@ function Export() {
             }
         </div>
       </p>
+      {
+        fileExtension === "sheet" && <>
+          <br/>
+          <p><strong>精灵表</strong></p>
+          <RadioGroup inline
+            selectedValue={sheet.format}
+            onChange={e=> setSheet({...sheet, format: e.currentTarget.value as typeof sheet.format})}>
+            <Radio label="hash" value={"hash"}/>
+            <Radio label="array" value={"array"}/>
+          </RadioGroup>
+          <Checkbox label="裁剪透明边缘" checked={sheet.trim}
+            onChange={e=> setSheet({...sheet, trim: e.currentTarget.checked})}/>
+          <Checkbox label="宽高取2的幂" checked={sheet.pot}
+            onChange={e=> setSheet({...sheet, pot: e.currentTarget.checked})}/>
+          <div style={{display: "flex", alignItems: "center", gap: 10}}>
+            <span>间距</span>
+            <div style={{width: 60}}>
+              <NumericInputGroup min={0} max={64} small
+                numericValue={sheet.padding}
+                intent={!isNaN(sheet.padding) ? "none" : "danger"}
+                onChangeNumericValue={v=> setSheet({...sheet, padding: v})}/>
+            </div>
+            <span>最大尺寸</span>
+            <div style={{width: 70}}>
+              <NumericInputGroup min={64} max={16384} small
+                numericValue={sheet.max_size}
+                intent={!isNaN(sheet.max_size) ? "none" : "danger"}
+                onChangeNumericValue={v=> setSheet({...sheet, max_size: v})}/>
+            </div>
+          </div>
+        </>
+      }
 
       {/* <p><strong>文件路径</strong></p>
       <InputGroup
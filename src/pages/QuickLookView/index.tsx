@@ -41,7 +41,7 @@ export default function QuickLookView() {
             case "ksh": return <LookKsh vs={v.vs} vs_name={v.vs_name} ps={v.ps} ps_name={v.ps_name}/>
             case "raw_txt":
             case "raw_bin": 
-            case "raw_image": return <LookRaw type={type.replace("raw_", "")} content={v.content} large_file_threshold={v.large_file_threshold}/>
+            case "raw_image": return <LookRaw type={type.replace("raw_", "")} content={v.content} large_file_threshold={v.large_file_threshold} metadata={v.metadata}/>
           }
 
           return JSON.stringify(v)
@@ -429,10 +429,11 @@ type LookRawProps = {
   type: (string & {}) | "txt" | "bin" | "image",
   content: string,
   large_file_threshold?: number,
+  metadata?: {[key: string]: string},
 }
 
 function LookRaw(props: LookRawProps) {
-  const {type, content, large_file_threshold} = props
+  const {type, content, large_file_threshold, metadata} = props
   const [url, setUrl] = useState("")
   const [resolution, setResolution] = useState([0, 0])
 
@@ -483,6 +484,23 @@ function LookRaw(props: LookRawProps) {
       {
         type === "image" && <p>分辨率: {resolution[0]}×{resolution[1]}</p>
       }
+      {
+        metadata && <div>
+          <p className="font-bold">来源:</p>
+          <table className="mx-2 my-0 p-1 rounded-[2px] border border-solid border-slate-400">
+            <tbody>
+              {
+                Object.entries(metadata).map(([k, v])=> (
+                  <tr key={k} className="[&>td]:px-[8px] [&>td]:py-[2px]">
+                    <td>{k}</td>
+                    <td className="select-text">{v}</td>
+                  </tr>
+                ))
+              }
+            </tbody>
+          </table>
+        </div>
+      }
     </div>
   )
 }
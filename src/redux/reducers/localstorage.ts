@@ -39,11 +39,18 @@ export type LocalStorage = {
   anim_panel_bgc_type: "transparent" | "solid",
   anim_panel_color_value: string,
   anim_panel_axis: "none" | "front" | "back",
-  anim_export_format: "gif" | "mov" | "png" | "mp4",
+  anim_export_format: "gif" | "mov" | "png" | "mp4" | "sheet",
   anim_export_bgc_type: "use_current" | "transparent" | "solid",
   anim_export_color_value: string,
   anim_export_resolution: number,
   anim_export_framerate: number,
+  anim_export_sheet: {
+    format: "hash" | "array",
+    trim: boolean,
+    padding: number,
+    max_size: number,
+    pot: boolean,
+  },
 }
 
 type Key = keyof LocalStorage
@@ -108,6 +115,7 @@ const localstorage = createSlice<LocalStorage, SliceCaseReducers<LocalStorage>>(
     anim_export_color_value: "#cccccc",
     anim_export_framerate: 30,
     anim_export_resolution: 1,
+    anim_export_sheet: {format: "hash", trim: true, padding: 1, max_size: 8192, pot: false},
 
     ...loadPersistant()
   }),